@@ -159,7 +159,6 @@ fn general_and_specific_error() {
         match error {
             DuzyError::SpecificError => {
                 println!("Ale guwno omg, {}", error);
-                return;
             }
 
             // All other errors
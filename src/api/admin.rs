@@ -5,10 +5,20 @@ use std::sync::Arc;
 
 use rocket::State;
 
-use rocket::response::content;
+use rocket::response::{content, status};
 use rocket::form::{Form, FromForm};
 
-use crate::topic::topic_service::{TopicService, TopicEntry, SubscriptionEntry};
+use crate::delivery::circuit_breaker::{CircuitState, SubscriptionHealth};
+use crate::delivery::credential_service::{Auth, CredentialService, DeliveryCredentials};
+use crate::delivery::dead_letter_service::DeadLetterService;
+use crate::delivery::history_service::{DeliveryAttempt, HistoryService};
+use crate::partition::partition::Offset;
+use crate::delivery::signing_service::{SigningService, RevealedSecret, DEFAULT_OVERLAP_SECONDS};
+use crate::delivery::verification::VerificationStatus;
+use crate::topic::offset_service::{OffsetService, OffsetReset, ResetTarget};
+use crate::topic::topic_service::{TopicService, TopicServiceError, TopicEntry, SubscriptionEntry, SubscriptionMode};
+use crate::topic::schema_service::{SchemaService, SchemaList};
+use crate::api::ApiError;
 use crate::api::templater::Templater;
 
 #[derive(FromForm)]
@@ -21,29 +31,33 @@ pub struct TopicDTO {
 pub fn create_topic(
     new_topic: Form<TopicDTO>,
     topic_service: &State<Arc<TopicService>>
-) -> content::RawHtml<String> {
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    let to_error = |error: TopicServiceError| status::Custom(
+        error.status(), 
+        content::RawHtml(format!("Can't create the topic due to {error}")));
 
-    // TODO: Add validation
+    // Name is validated by the topic service
     let topic_entry = TopicEntry {
         name: new_topic.name.clone(),
         owner: new_topic.owner.clone(),
-        subscribers: vec![]
+        subscribers: vec![],
+        config: topic_service.get_default_config().map_err(to_error)?
     };
 
-    match topic_service.create_topic(topic_entry.clone()) {
-        Err(error) => {
-
-            // TODO: better display the error in htmx - return 404 or something
-            return content::RawHtml(format!("Can't create the topic due to {error}"));
-        }
-        Ok(_) => () // Ignore the Ok response
-    }
+    topic_service.create_topic(topic_entry.clone()).map_err(to_error)?;
 
-    content::RawHtml(topic_entry.to_html())
+    Ok(content::RawHtml(topic_entry.to_html()))
 }
 
 #[get("/module/topic/<topic_name>")]
-pub fn module_topic(topic_name: &str, topic_service: &State<Arc<TopicService>>, templater: &State<Arc<Templater>>) -> content::RawHtml<String> {
+pub fn module_topic(
+    topic_name: &str, 
+    topic_service: &State<Arc<TopicService>>, 
+    schema_service: &State<Arc<SchemaService>>, 
+    credential_service: &State<Arc<CredentialService>>,
+    templater: &State<Arc<Templater>>
+) -> content::RawHtml<String> {
 
     let topic = match topic_service.get_topic(topic_name) {
         Ok(topic) => topic,
@@ -52,10 +66,21 @@ pub fn module_topic(topic_name: &str, topic_service: &State<Arc<TopicService>>,
         },
     };
 
+    let schemas = match schema_service.get_schemas(topic_name) {
+        Ok(schemas) => schemas,
+        Err(err) => {
+            return content::RawHtml(format!("Can't load schemas of the topic {topic_name} due to {err}"));
+        },
+    };
+
     let vars = HashMap::from([
         ("name", topic.name.clone()),
         ("owner", topic.owner.clone()),
-        ("subscribers", topic.subscribers.to_html())
+        ("subscribers", topic.subscribers.iter()
+            .map(|subscription| SubscriptionRow::new(topic_name, subscription, credential_service))
+            .collect::<Vec<_>>()
+            .to_html()),
+        ("schema", schemas.to_html())
     ]);
 
     content::RawHtml(templater.get("topic", vars))
@@ -85,8 +110,8 @@ pub fn web_main(templater: &State<Arc<Templater>>) -> content::RawHtml<String>{
     
     // Construct index component
     let index_vars = HashMap::from([
-        ("url", format!("/admin/module/main")),
-        ("browser_url", format!("/admin")),
+        ("url", "/admin/module/main".to_string()),
+        ("browser_url", "/admin".to_string()),
     ]);
 
     content::RawHtml(templater.get("index", index_vars))
@@ -110,28 +135,271 @@ pub struct SubscriberDTO {
     endpoint: String
 }
 
-#[post("/admin/topics/<topic_name>/subscribe", data = "<subscriber>")]
+#[post("/topics/<topic_name>/subscribe", data = "<subscriber>")]
 pub fn create_subscriber(
     topic_name: &str,
     subscriber: Form<SubscriberDTO>,
-    topic_service: &State<Arc<TopicService>>, 
-    // templater: &State<Arc<Templater>>
-) {
+    topic_service: &State<Arc<TopicService>>,
+    signing_service: &State<Arc<SigningService>>,
+    credential_service: &State<Arc<CredentialService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
 
-    let subscription_entry = SubscriptionEntry {
-        name: subscriber.name.clone(),
-        endpoint: subscriber.endpoint.clone()
-    };
+    let to_error = |status, error: &dyn std::fmt::Display| status::Custom(
+        status,
+        content::RawHtml(format!("Can't subscribe to the topic due to {error}")));
+
+    let subscription_entry = SubscriptionEntry::new(&subscriber.name, &subscriber.endpoint);
+
+    // Same as in the json api, the secret has to be there before the first delivery
+    let (subscription_entry, secret) = topic_service
+        .subscribe_topic_with(topic_name, subscription_entry, |subscription_entry| {
+            credential_service.create_credentials(topic_name, &subscription_entry.name, &DeliveryCredentials::default())?;
+            Ok::<_, ApiError>(signing_service.create_secret(topic_name, &subscription_entry.name)?)
+        })
+        .map_err(|error| to_error(error.status, &error.message))?;
+
+    // The only time the secret is shown, in a row right below the subscription
+    Ok(content::RawHtml(format!(
+        "{}
+        <tr>
+            <td colspan=\"5\">{}</td>
+        </tr>",
+        SubscriptionRow::new(topic_name, &subscription_entry, credential_service).to_html(),
+        secret.to_html())))
+}
+
+#[derive(FromForm)]
+pub struct EndpointDTO {
+    endpoint: String
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/endpoint", data = "<update>")]
+pub fn update_subscriber(
+    topic_name: &str,
+    subscription_name: &str,
+    update: Form<EndpointDTO>,
+    topic_service: &State<Arc<TopicService>>,
+    credential_service: &State<Arc<CredentialService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    match topic_service.change_endpoint(topic_name, subscription_name, &update.endpoint) {
+        Ok(subscription) => Ok(content::RawHtml(SubscriptionRow::new(topic_name, &subscription, credential_service).to_html())),
+        Err(error) => Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't change the endpoint due to {error}"))))
+    }
+}
+
+#[delete("/topics/<topic_name>/subscriptions/<subscription_name>")]
+pub fn delete_subscriber(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    // Empty response replaces the row
+    match topic_service.unsubscribe_topic(topic_name, subscription_name) {
+        Ok(_) => Ok(content::RawHtml(String::new())),
+        Err(error) => Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't remove the subscription due to {error}"))))
+    }
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/pause")]
+pub fn pause_subscriber(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>,
+    credential_service: &State<Arc<CredentialService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    set_paused(topic_name, subscription_name, true, topic_service, credential_service)
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/resume")]
+pub fn resume_subscriber(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>,
+    credential_service: &State<Arc<CredentialService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    set_paused(topic_name, subscription_name, false, topic_service, credential_service)
+}
+
+fn set_paused(
+    topic_name: &str,
+    subscription_name: &str,
+    paused: bool,
+    topic_service: &TopicService,
+    credential_service: &CredentialService
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    match topic_service.set_paused(topic_name, subscription_name, paused) {
+        Ok(subscription) => Ok(content::RawHtml(SubscriptionRow::new(topic_name, &subscription, credential_service).to_html())),
+        Err(error) => Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't change the subscription due to {error}"))))
+    }
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/verify")]
+pub fn verify_subscriber(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>,
+    credential_service: &State<Arc<CredentialService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    match topic_service.request_verification(topic_name, subscription_name) {
+        Ok(subscription) => Ok(content::RawHtml(SubscriptionRow::new(topic_name, &subscription, credential_service).to_html())),
+        Err(error) => Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't verify the subscription due to {error}"))))
+    }
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/secret/rotate")]
+pub fn rotate_secret(
+    topic_name: &str,
+    subscription_name: &str,
+    signing_service: &State<Arc<SigningService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    match signing_service.rotate_secret(topic_name, subscription_name, DEFAULT_OVERLAP_SECONDS) {
+        Ok(secret) => Ok(content::RawHtml(secret.to_html())),
+        Err(error) => Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't rotate the secret due to {error}"))))
+    }
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/redrive")]
+pub fn redrive_subscriber(
+    topic_name: &str,
+    subscription_name: &str,
+    dead_letter_service: &State<Arc<DeadLetterService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    match dead_letter_service.redrive(topic_name, subscription_name) {
+        Ok(redrive) => Ok(content::RawHtml(format!("Re-driven {} messages", redrive.redriven))),
+        Err(error) => Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't re-drive due to {error}"))))
+    }
+}
+
+#[derive(FromForm)]
+pub struct ResetDTO {
+    subscription: String,
+
+    /// earliest, latest, offset or timestamp
+    to: String,
+
+    /// Offset or timestamp, for targets that need one
+    value: Option<u64>,
+    dry_run: bool
+}
+
+#[post("/topics/<topic_name>/reset", data = "<reset>")]
+pub fn reset_subscriber(
+    topic_name: &str,
+    reset: Form<ResetDTO>,
+    offset_service: &State<Arc<OffsetService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    let target = reset_target(&reset.to, reset.value)?;
+
+    match offset_service.reset_subscription(topic_name, &reset.subscription, target, reset.dry_run) {
+        Ok(reset) => Ok(content::RawHtml(reset.to_html())),
+        Err(error) => Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't reset the subscription due to {error}"))))
+    }
+}
+
+#[derive(FromForm)]
+pub struct GroupResetDTO {
+    group: String,
+
+    /// earliest, latest, offset or timestamp
+    to: String,
+
+    /// Offset or timestamp, for targets that need one
+    value: Option<u64>,
+    dry_run: bool
+}
 
-    topic_service.subscribe_topic(topic_name, subscription_entry).unwrap();
-    // templater.generateSth(subscriber)
-    todo!()
+#[post("/topics/<topic_name>/groups/reset", data = "<reset>")]
+pub fn reset_group(
+    topic_name: &str,
+    reset: Form<GroupResetDTO>,
+    offset_service: &State<Arc<OffsetService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    let target = reset_target(&reset.to, reset.value)?;
+
+    match offset_service.reset_group(&reset.group, topic_name, target, reset.dry_run) {
+        Ok(reset) => Ok(content::RawHtml(reset.to_html())),
+        Err(error) => Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't reset the group due to {error}"))))
+    }
+}
+
+fn reset_target(to: &str, value: Option<u64>) -> Result<ResetTarget, status::Custom<content::RawHtml<String>>> {
+    let bad_request = |message: &str| status::Custom(rocket::http::Status::BadRequest, content::RawHtml(message.to_owned()));
+
+    match (to, value) {
+        ("earliest", _) => Ok(ResetTarget::Earliest),
+        ("latest", _) => Ok(ResetTarget::Latest),
+        ("offset", Some(offset)) => Ok(ResetTarget::Offset { offset }),
+        ("timestamp", Some(timestamp)) => Ok(ResetTarget::Timestamp { timestamp }),
+        ("offset" | "timestamp", None) => Err(bad_request("Value is required for this target")),
+        _ => Err(bad_request("Unknown reset target"))
+    }
+}
+
+// How many delivery attempts the topic page shows
+const SHOWN_ATTEMPTS: usize = 50;
+
+#[get("/topics/<topic_name>/attempts?<subscription>&<partition>&<offset>")]
+pub fn subscriber_attempts(
+    topic_name: &str,
+    subscription: &str,
+    partition: Option<u32>,
+    offset: Option<Offset>,
+    topic_service: &State<Arc<TopicService>>,
+    history_service: &State<Arc<HistoryService>>
+) -> Result<content::RawHtml<String>, status::Custom<content::RawHtml<String>>> {
+
+    if let Err(error) = topic_service.get_subscription(topic_name, subscription) {
+        return Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't show delivery attempts due to {error}"))));
+    }
+
+    match history_service.attempts(topic_name, subscription, partition, offset, SHOWN_ATTEMPTS) {
+        Ok(attempts) => Ok(content::RawHtml(attempts.to_html())),
+        Err(error) => Err(status::Custom(
+            error.status(),
+            content::RawHtml(format!("Can't show delivery attempts due to {error}"))))
+    }
 }
 
 trait ToHtml {
     fn to_html(&self) -> String;
 }
 
+/// Makes user-provided text safe to put inside html
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 impl<T: ToHtml> ToHtml for Vec<T> {
     fn to_html(&self) -> String {
         let mut html = String::new();
@@ -162,16 +430,260 @@ impl ToHtml for TopicEntry {
     }
 }
 
-impl ToHtml for SubscriptionEntry {
+/// Subscription as a row of its topic's table
+struct SubscriptionRow<'a> {
+    topic_name: &'a str,
+    subscription: &'a SubscriptionEntry,
+
+    // Always redacted
+    credentials: DeliveryCredentials
+}
+
+impl<'a> SubscriptionRow<'a> {
+    fn new(topic_name: &'a str, subscription: &'a SubscriptionEntry, credential_service: &CredentialService) -> Self {
+        let credentials = credential_service.get_credentials(topic_name, &subscription.name)
+            .map(|credentials| credentials.redacted())
+            .unwrap_or_else(|err| {
+                println!("Can't load credentials of {topic_name}/{}: {err}", subscription.name);
+                DeliveryCredentials::default()
+            });
+
+        SubscriptionRow { topic_name, subscription, credentials }
+    }
+}
+
+impl ToHtml for SubscriptionRow<'_> {
     fn to_html(&self) -> String {
-        let name = &self.name;
-        let endpoint = &self.endpoint.to_string();
+        let topic_name = self.topic_name;
+        let name = &self.subscription.name;
+        let endpoint = match self.subscription.mode {
+            SubscriptionMode::Push => format!(
+                "<form hx-post=\"/admin/topics/{topic_name}/subscriptions/{name}/endpoint\"
+                      hx-target=\"closest tr\"
+                      hx-swap=\"outerHTML\"
+                      class=\"d-flex\">
+                    <input class=\"form-control form-control-sm\" type=\"text\" name=\"endpoint\" value=\"{}\">
+                    <button class=\"btn btn-sm btn-outline-primary ms-2\">Save</button>
+                </form>
+                <button hx-post=\"/admin/topics/{topic_name}/subscriptions/{name}/secret/rotate\"
+                        hx-target=\"next div\"
+                        hx-confirm=\"Rotate the signing secret? The current one keeps working for a day\"
+                        class=\"btn btn-sm btn-outline-secondary mt-2\">Rotate secret
+                </button>
+                {}
+                <div></div>",
+                escape_html(&self.subscription.endpoint),
+                self.credentials.to_html()),
+            SubscriptionMode::Queue { .. } => String::from("<i>queue</i>")
+        };
+
+        let dead_letter = match &self.subscription.dead_letter_topic {
+            Some(dead_letter_topic) => format!(
+                "{dead_letter_topic}
+                <button hx-post=\"/admin/topics/{topic_name}/subscriptions/{name}/redrive\"
+                        hx-target=\"next span\"
+                        class=\"btn btn-sm btn-outline-secondary ms-2\">Re-drive
+                </button>
+                <span class=\"ms-2\"></span>"),
+            None => String::from("-")
+        };
+
+        let health = match self.subscription.mode {
+            SubscriptionMode::Push => self.subscription.health.to_html(),
+            SubscriptionMode::Queue { .. } => String::from("-")
+        };
+
+        let (paused, toggle) = match self.subscription.paused {
+            true => (" <span class=\"badge bg-secondary\">paused</span>", "resume"),
+            false => ("", "pause")
+        };
+
+        let websub = match &self.subscription.websub {
+            Some(lease) => format!("<small class=\"d-block text-muted\">WebSub lease until {} (seconds since epoch)</small>", lease.expires_at),
+            None => String::new()
+        };
 
-        String::from(format!(
+        let (verification, verify) = match self.subscription.verification {
+            VerificationStatus::Verified => (String::new(), String::new()),
+            VerificationStatus::Pending => (" <span class=\"badge bg-info text-dark\">pending verification</span>".to_owned(), String::new()),
+            VerificationStatus::Failed => (
+                " <span class=\"badge bg-danger\">verification failed</span>".to_owned(),
+                format!(
+                    "<button hx-post=\"/admin/topics/{topic_name}/subscriptions/{name}/verify\"
+                            hx-target=\"closest tr\"
+                            hx-swap=\"outerHTML\"
+                            class=\"btn btn-sm btn-outline-primary me-2\">Verify
+                    </button>"))
+        };
+
+        format!(
             "<tr>
-                <td>{name}</td>
+                <td>{name}{paused}{verification}{websub}</td>
                 <td>{endpoint}</td>
+                <td>{dead_letter}</td>
+                <td>{health}</td>
+                <td>
+                    {verify}
+                    <button hx-post=\"/admin/topics/{topic_name}/subscriptions/{name}/{toggle}\"
+                            hx-target=\"closest tr\"
+                            hx-swap=\"outerHTML\"
+                            class=\"btn btn-sm btn-outline-warning text-capitalize\">{toggle}
+                    </button>
+                    <button hx-delete=\"/admin/topics/{topic_name}/subscriptions/{name}\"
+                            hx-target=\"closest tr\"
+                            hx-swap=\"outerHTML\"
+                            hx-confirm=\"Remove subscription {name}? Its committed offsets are removed too\"
+                            class=\"btn btn-sm btn-outline-danger ms-2\">Remove
+                    </button>
+                </td>
             </tr>"
-        ))
+        )
     }
-}
\ No newline at end of file
+}
+
+impl ToHtml for DeliveryCredentials {
+    fn to_html(&self) -> String {
+        let auth = self.auth.iter().map(|auth| match auth {
+            Auth::Bearer { token } => format!("Authorization: Bearer {token}"),
+            Auth::Basic { username, password } => format!("Authorization: Basic {}:{password}", escape_html(username))
+        });
+        let headers = self.headers.iter().map(|(name, value)| format!("{}: {value}", escape_html(name)));
+
+        let lines: Vec<String> = auth.chain(headers).collect();
+        match lines.is_empty() {
+            true => String::new(),
+            false => format!("<small class=\"d-block text-muted mt-1\">{}</small>", lines.join("<br>"))
+        }
+    }
+}
+
+impl ToHtml for SubscriptionHealth {
+    fn to_html(&self) -> String {
+        let (class, circuit) = match self.circuit {
+            CircuitState::Closed => ("bg-success", "closed"),
+            CircuitState::HalfOpen => ("bg-warning text-dark", "half-open"),
+            CircuitState::Open => ("bg-danger", "open")
+        };
+
+        let last_success = match self.last_success_at {
+            Some(timestamp) => timestamp.to_string(),
+            None => String::from("never")
+        };
+
+        // Errors can quote the endpoint's response
+        let last_error = match (&self.last_error, self.last_error_at) {
+            (Some(error), Some(timestamp)) => format!("<br>Last error at {timestamp}: {}", escape_html(error)),
+            _ => String::new()
+        };
+
+        // Behind because of its own rate limit, not because the endpoint fails
+        let throttled = match self.throttled {
+            true => "<span class=\"badge bg-info text-dark\">throttled</span>",
+            false => ""
+        };
+
+        format!(
+            "<span class=\"badge {class}\">{circuit}</span>{throttled}
+            <small class=\"d-block text-muted\">
+                Last success at {last_success}{last_error}
+                <br>(seconds since epoch)
+            </small>")
+    }
+}
+
+impl ToHtml for RevealedSecret {
+    fn to_html(&self) -> String {
+        let previous = match self.previous_expires_at {
+            Some(expires_at) => format!("<br>Previous secret works until {expires_at} (seconds since epoch)."),
+            None => String::new()
+        };
+
+        format!(
+            "<div class=\"alert alert-warning mt-2 mb-0\">
+                Signing secret: <code>{}</code><br>
+                Copy it now, it won't be shown again.{previous}
+            </div>", self.secret)
+    }
+}
+
+impl ToHtml for OffsetReset {
+    fn to_html(&self) -> String {
+        let rows: String = self.partitions.iter().map(|partition| format!(
+            "<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>", partition.partition, partition.previous_offset, partition.new_offset)).collect();
+
+        let caption = match self.dry_run {
+            true => "Dry run, nothing was changed",
+            false => "Offsets were reset"
+        };
+
+        format!(
+            "<p>{caption}</p>
+            <table class=\"table table-sm table-bordered\">
+                <thead class=\"table-light\">
+                    <tr><th>Partition</th><th>Previous offset</th><th>New offset</th></tr>
+                </thead>
+                <tbody>{rows}</tbody>
+            </table>"
+        )
+    }
+}
+
+impl ToHtml for Vec<DeliveryAttempt> {
+    fn to_html(&self) -> String {
+        if self.is_empty() {
+            return String::from("<p>No delivery attempts recorded</p>");
+        }
+
+        let rows: String = self.iter().map(|attempt| format!(
+            "<tr>
+                <td>{}/{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{} ms</td>
+                <td>{}</td>
+            </tr>",
+            attempt.partition,
+            attempt.offset,
+            attempt.attempt,
+            attempt.timestamp,
+            attempt.status.map_or(String::from("-"), |status| status.to_string()),
+            attempt.latency_ms,
+            attempt.error.as_deref().map_or(String::new(), escape_html))).collect();
+
+        format!(
+            "<p>Newest first, times in seconds since epoch</p>
+            <table class=\"table table-sm table-bordered\">
+                <thead class=\"table-light\">
+                    <tr><th>Message</th><th>Attempt</th><th>Time</th><th>Status</th><th>Latency</th><th>Error</th></tr>
+                </thead>
+                <tbody>{rows}</tbody>
+            </table>"
+        )
+    }
+}
+
+impl ToHtml for SchemaList {
+    fn to_html(&self) -> String {
+        if self.0.is_empty() {
+            return String::from("<p>No schema, any json is accepted</p>");
+        }
+
+        let versions: String = self.0.iter().rev().map(|version| format!(
+            "<h6>Version {}</h6>
+            <p class=\"text-muted\">Registered at {}</p>
+            <pre class=\"bg-light p-3 border\">{}</pre>",
+            version.version,
+            version.created_at,
+            escape_html(&rocket::serde::json::serde_json::to_string_pretty(&version.schema).unwrap_or_default()))).collect();
+
+        format!(
+            "<p>Newest first, times in seconds since epoch. Messages are checked against the newest version</p>
+            {versions}"
+        )
+    }
+}
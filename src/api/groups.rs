@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+use rocket::{serde::json::Json, State};
+
+use crate::api::ApiError;
+use crate::partition::partition::Offset;
+use crate::topic::offset_service::{OffsetService, OffsetReset, ResetTarget};
+use crate::topic::group_service::{GroupService, Assignment, GroupDescription, OffsetCommit, DEFAULT_SESSION_TIMEOUT_MS};
+
+fn default_session_timeout() -> u64 {
+    DEFAULT_SESSION_TIMEOUT_MS
+}
+
+#[derive(Deserialize)]
+pub struct JoinDTO {
+    topic: String,
+
+    /// Generated by the broker if missing
+    #[serde(default)]
+    member: Option<String>,
+
+    #[serde(default = "default_session_timeout")]
+    session_timeout_ms: u64
+}
+
+#[derive(Deserialize)]
+pub struct HeartbeatDTO {
+    #[serde(default)]
+    generation: Option<u32>,
+
+    /// Offsets to commit, by partition
+    #[serde(default)]
+    offsets: BTreeMap<u32, Offset>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OffsetsDTO {
+    offsets: Vec<OffsetCommit>
+}
+
+#[derive(Deserialize)]
+pub struct CommitOffsetsDTO {
+    offsets: Vec<OffsetCommit>,
+
+    /// Required while the group has live members
+    #[serde(default)]
+    member: Option<String>,
+
+    #[serde(default)]
+    generation: Option<u32>
+}
+
+#[derive(Deserialize)]
+pub struct GroupResetDTO {
+    topic: String,
+
+    #[serde(flatten)]
+    target: ResetTarget,
+
+    #[serde(default)]
+    dry_run: bool
+}
+
+#[post("/groups/<group_name>/members", data = "<join>")]
+pub fn join_group(
+    group_name: &str,
+    join: Json<JoinDTO>,
+    group_service: &State<Arc<GroupService>>
+) -> Result<Json<Assignment>, ApiError> {
+
+    Ok(Json(group_service.join(group_name, &join.topic, join.member.as_deref(), join.session_timeout_ms)?))
+}
+
+#[post("/groups/<group_name>/members/<member_name>/heartbeat", data = "<heartbeat>")]
+pub fn heartbeat(
+    group_name: &str,
+    member_name: &str,
+    heartbeat: Json<HeartbeatDTO>,
+    group_service: &State<Arc<GroupService>>
+) -> Result<Json<Assignment>, ApiError> {
+
+    Ok(Json(group_service.heartbeat(group_name, member_name, heartbeat.generation, &heartbeat.offsets)?))
+}
+
+#[delete("/groups/<group_name>/members/<member_name>")]
+pub fn leave_group(
+    group_name: &str,
+    member_name: &str,
+    group_service: &State<Arc<GroupService>>
+) -> Result<(), ApiError> {
+
+    Ok(group_service.leave(group_name, member_name)?)
+}
+
+#[get("/groups/<group_name>")]
+pub fn get_group(
+    group_name: &str,
+    group_service: &State<Arc<GroupService>>
+) -> Result<Json<GroupDescription>, ApiError> {
+
+    Ok(Json(group_service.describe(group_name)?))
+}
+
+#[post("/groups/<group_name>/offsets", data = "<commit>")]
+pub fn commit_offsets(
+    group_name: &str,
+    commit: Json<CommitOffsetsDTO>,
+    group_service: &State<Arc<GroupService>>
+) -> Result<Json<OffsetsDTO>, ApiError> {
+
+    let commit = commit.into_inner();
+    group_service.commit_member_offsets(group_name, commit.member.as_deref(), commit.generation, &commit.offsets)?;
+    Ok(Json(OffsetsDTO { offsets: commit.offsets }))
+}
+
+#[get("/groups/<group_name>/offsets?<topic>")]
+pub fn fetch_offsets(
+    group_name: &str,
+    topic: Option<&str>,
+    group_service: &State<Arc<GroupService>>
+) -> Result<Json<OffsetsDTO>, ApiError> {
+
+    Ok(Json(OffsetsDTO { offsets: group_service.fetch_offsets(group_name, topic)? }))
+}
+
+#[post("/groups/<group_name>/offsets/reset", data = "<reset>")]
+pub fn reset_group(
+    group_name: &str,
+    reset: Json<GroupResetDTO>,
+    offset_service: &State<Arc<OffsetService>>
+) -> Result<Json<OffsetReset>, ApiError> {
+
+    Ok(Json(offset_service.reset_group(group_name, &reset.topic, reset.target, reset.dry_run)?))
+}
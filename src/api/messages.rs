@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use rocket::{serde::json::Json, State};
+
+use crate::api::ApiError;
+use crate::partition::partition::Offset;
+use crate::topic::consumer_service::{ConsumerService, FetchedMessages};
+
+// Messages returned when the consumer doesn't say how many it wants
+const DEFAULT_FETCH: usize = 100;
+
+#[get("/topics/<topic_name>/partitions/<partition>/messages?<offset>&<max>")]
+pub fn fetch_messages(
+    topic_name: &str,
+    partition: u32,
+    offset: Option<Offset>,
+    max: Option<usize>,
+    consumer_service: &State<Arc<ConsumerService>>
+) -> Result<Json<FetchedMessages>, ApiError> {
+
+    Ok(Json(consumer_service.fetch(topic_name, partition, offset.unwrap_or(0), max.unwrap_or(DEFAULT_FETCH))?))
+}
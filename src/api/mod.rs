@@ -1,3 +1,131 @@
 pub mod receiver;
 pub mod admin;
-pub mod templater;
\ No newline at end of file
+pub mod templater;
+pub mod topics;
+pub mod subscriptions;
+pub mod messages;
+pub mod groups;
+pub mod queues;
+pub mod websub;
+
+use rocket::http::Status;
+use rocket::response::{self, Responder, status};
+use rocket::serde::json::{Json, serde_json::json};
+use rocket::Request;
+
+use crate::delivery::credential_service::CredentialServiceError;
+use crate::delivery::history_service::HistoryServiceError;
+use crate::delivery::signing_service::SigningServiceError;
+use crate::delivery::websub_service::WebSubServiceError;
+use crate::topic::consumer_service::ConsumerServiceError;
+use crate::topic::group_service::GroupServiceError;
+use crate::topic::offset_service::OffsetServiceError;
+use crate::topic::queue_service::QueueServiceError;
+use crate::topic::topic_service::TopicServiceError;
+use crate::topic::publisher_service::PublisherServiceError;
+use crate::topic::schema_service::SchemaServiceError;
+
+///
+/// Error returned by the json endpoints. Rendered as `{ "error": "...", "details": [...] }` 
+/// with a matching status. Details are only present if there are any
+///
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub message: String,
+    pub details: Vec<String>
+}
+
+impl ApiError {
+    pub fn new(status: Status, message: String) -> Self {
+        ApiError { status, message, details: vec![] }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = match self.details.is_empty() {
+            true => json!({ "error": self.message }),
+            false => json!({ "error": self.message, "details": self.details })
+        };
+
+        status::Custom(self.status, Json(body)).respond_to(request)
+    }
+}
+
+impl From<TopicServiceError> for ApiError {
+    fn from(error: TopicServiceError) -> Self {
+        ApiError::new(error.status(), error.to_string())
+    }
+}
+
+impl From<SchemaServiceError> for ApiError {
+    fn from(error: SchemaServiceError) -> Self {
+        let mut api_error = ApiError::new(error.status(), error.to_string());
+
+        match error {
+            SchemaServiceError::ValidationFailed { errors, .. } => api_error.details = errors,
+            SchemaServiceError::Incompatible { errors, .. } => api_error.details = errors,
+            _ => ()
+        }
+
+        api_error
+    }
+}
+
+impl From<PublisherServiceError> for ApiError {
+    fn from(error: PublisherServiceError) -> Self {
+        match error {
+            PublisherServiceError::Schema(error) => error.into(),
+            error => ApiError::new(error.status(), error.to_string())
+        }
+    }
+}
+
+impl From<OffsetServiceError> for ApiError {
+    fn from(error: OffsetServiceError) -> Self {
+        ApiError::new(error.status(), error.to_string())
+    }
+}
+
+impl From<ConsumerServiceError> for ApiError {
+    fn from(error: ConsumerServiceError) -> Self {
+        ApiError::new(error.status(), error.to_string())
+    }
+}
+
+impl From<GroupServiceError> for ApiError {
+    fn from(error: GroupServiceError) -> Self {
+        ApiError::new(error.status(), error.to_string())
+    }
+}
+
+impl From<QueueServiceError> for ApiError {
+    fn from(error: QueueServiceError) -> Self {
+        ApiError::new(error.status(), error.to_string())
+    }
+}
+
+impl From<SigningServiceError> for ApiError {
+    fn from(error: SigningServiceError) -> Self {
+        ApiError::new(error.status(), error.to_string())
+    }
+}
+
+impl From<WebSubServiceError> for ApiError {
+    fn from(error: WebSubServiceError) -> Self {
+        ApiError::new(error.status(), error.to_string())
+    }
+}
+
+impl From<CredentialServiceError> for ApiError {
+    fn from(error: CredentialServiceError) -> Self {
+        ApiError::new(error.status(), error.to_string())
+    }
+}
+
+impl From<HistoryServiceError> for ApiError {
+    fn from(error: HistoryServiceError) -> Self {
+        ApiError::new(error.status(), error.to_string())
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+use rocket::{serde::json::Json, State};
+
+use crate::api::ApiError;
+use crate::topic::queue_service::{QueueService, ReceivedMessage, Settled};
+
+// Messages received when the consumer doesn't say how many it wants
+const DEFAULT_RECEIVE: usize = 10;
+
+#[derive(Serialize)]
+pub struct ReceivedDTO {
+    messages: Vec<ReceivedMessage>
+}
+
+#[derive(Deserialize)]
+pub struct AckDTO {
+    receipts: Vec<String>
+}
+
+#[derive(Deserialize)]
+pub struct NackDTO {
+    receipts: Vec<String>,
+
+    /// How long until the messages can be received again
+    #[serde(default)]
+    delay_ms: u64,
+
+    /// Recorded in the dead letter topic, if messages end up there
+    #[serde(default)]
+    reason: Option<String>
+}
+
+#[post("/topics/<topic_name>/queues/<queue_name>/receive?<max>&<visibility_timeout_ms>")]
+pub fn receive(
+    topic_name: &str,
+    queue_name: &str,
+    max: Option<usize>,
+    visibility_timeout_ms: Option<u64>,
+    queue_service: &State<Arc<QueueService>>
+) -> Result<Json<ReceivedDTO>, ApiError> {
+
+    let messages = queue_service.receive(topic_name, queue_name, max.unwrap_or(DEFAULT_RECEIVE), visibility_timeout_ms)?;
+    Ok(Json(ReceivedDTO { messages }))
+}
+
+#[post("/topics/<topic_name>/queues/<queue_name>/ack", data = "<ack>")]
+pub fn ack(
+    topic_name: &str,
+    queue_name: &str,
+    ack: Json<AckDTO>,
+    queue_service: &State<Arc<QueueService>>
+) -> Result<Json<Settled>, ApiError> {
+
+    Ok(Json(queue_service.ack(topic_name, queue_name, &ack.receipts)?))
+}
+
+#[post("/topics/<topic_name>/queues/<queue_name>/nack", data = "<nack>")]
+pub fn nack(
+    topic_name: &str,
+    queue_name: &str,
+    nack: Json<NackDTO>,
+    queue_service: &State<Arc<QueueService>>
+) -> Result<Json<Settled>, ApiError> {
+
+    Ok(Json(queue_service.nack(topic_name, queue_name, &nack.receipts, nack.delay_ms, nack.reason.as_deref())?))
+}
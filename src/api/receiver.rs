@@ -2,19 +2,26 @@ use std::sync::Arc;
 
 use rocket::{serde::json::Json, State};
 
+use crate::api::ApiError;
+use crate::topic::offset_service::{OffsetService, SubscriptionOffsets};
 use crate::topic::publisher_service::*;
 
 #[post("/publish/<topic_name>", data = "<payload>")]
 pub fn publish_message(
     topic_name: &str, 
     payload: Json<MessagePayload>, 
-    publisher_service: &State<Arc<PublisherService>>) {
-    // to do: controller responsible for message publishing 
+    publisher_service: &State<Arc<PublisherService>>
+) -> Result<Json<PublishedMessage>, ApiError> {
 
-    let _ = publisher_service.publish_message(topic_name, payload.0);
+    Ok(Json(publisher_service.publish_message(topic_name, payload.0)?))
 }
 
-#[get("/offset")]
-pub fn get_offset() -> &'static str {
-    "offset"
-}
\ No newline at end of file
+#[get("/offset/<topic_name>/<subscription_name>")]
+pub fn get_offset(
+    topic_name: &str,
+    subscription_name: &str,
+    offset_service: &State<Arc<OffsetService>>
+) -> Result<Json<SubscriptionOffsets>, ApiError> {
+
+    Ok(Json(offset_service.subscription_offsets(topic_name, subscription_name)?))
+}
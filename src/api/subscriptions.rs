@@ -0,0 +1,294 @@
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+use rocket::{serde::json::Json, State};
+
+use crate::api::ApiError;
+use crate::delivery::dead_letter_service::{DeadLetterService, Redrive};
+use crate::delivery::history_service::{DeliveryAttempt, HistoryService};
+use crate::delivery::batch_policy::BatchPolicy;
+use crate::delivery::circuit_breaker::CircuitBreakerPolicy;
+use crate::delivery::rate_limiter::RateLimit;
+use crate::delivery::credential_service::{CredentialService, CredentialServiceError, DeliveryCredentials};
+use crate::delivery::retry_policy::RetryPolicy;
+use crate::delivery::signing_service::{SigningService, RevealedSecret, DEFAULT_OVERLAP_SECONDS};
+use crate::partition::partition::Offset;
+use crate::topic::offset_service::{OffsetService, OffsetReset, ResetTarget};
+use crate::topic::topic_service::{TopicService, SubscriptionEntry, SubscriptionMode, DEFAULT_CONCURRENCY};
+
+fn default_concurrency() -> usize {
+    DEFAULT_CONCURRENCY
+}
+
+// How many delivery attempts are returned, unless asked otherwise
+const DEFAULT_ATTEMPTS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct NewSubscriptionDTO {
+    name: String,
+
+    #[serde(default)]
+    endpoint: String,
+
+    #[serde(default)]
+    mode: SubscriptionMode,
+
+    #[serde(default)]
+    filter: Option<String>,
+
+    #[serde(default)]
+    retry_policy: RetryPolicy,
+
+    #[serde(default)]
+    batch: Option<BatchPolicy>,
+
+    #[serde(default = "default_concurrency")]
+    concurrency: usize,
+
+    #[serde(default)]
+    circuit_breaker: CircuitBreakerPolicy,
+
+    #[serde(default)]
+    rate_limit: RateLimit,
+
+    /// `headers` and `auth` sent with every delivery
+    #[serde(flatten)]
+    credentials: DeliveryCredentials,
+
+    #[serde(default)]
+    dead_letter_topic: Option<String>
+}
+
+/// New subscription, with its signing secret. The secret isn't shown again
+#[derive(Serialize)]
+pub struct CreatedSubscriptionDTO {
+    #[serde(flatten)]
+    subscription: SubscriptionEntry,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signing_secret: Option<String>
+}
+
+fn default_overlap() -> u64 {
+    DEFAULT_OVERLAP_SECONDS
+}
+
+#[derive(Deserialize)]
+pub struct RotateSecretDTO {
+    /// How long the previous secret keeps working, in seconds
+    #[serde(default = "default_overlap")]
+    overlap_seconds: u64
+}
+
+/// Fields of the subscription to change, missing ones stay as they are
+#[derive(Deserialize)]
+pub struct UpdateSubscriptionDTO {
+    #[serde(default)]
+    endpoint: Option<String>,
+
+    /// Replaces the whole rate limit
+    #[serde(default)]
+    rate_limit: Option<RateLimit>
+}
+
+#[derive(Deserialize)]
+pub struct ResetDTO {
+    #[serde(flatten)]
+    target: ResetTarget,
+
+    #[serde(default)]
+    dry_run: bool
+}
+
+#[post("/topics/<topic_name>/subscriptions", data = "<subscription>")]
+pub fn create_subscription(
+    topic_name: &str,
+    subscription: Json<NewSubscriptionDTO>,
+    topic_service: &State<Arc<TopicService>>,
+    signing_service: &State<Arc<SigningService>>,
+    credential_service: &State<Arc<CredentialService>>
+) -> Result<Json<CreatedSubscriptionDTO>, ApiError> {
+
+    let subscription_entry = SubscriptionEntry {
+        mode: subscription.mode,
+        filter: subscription.filter.clone(),
+        retry_policy: subscription.retry_policy.clone(),
+        batch: subscription.batch.clone(),
+        concurrency: subscription.concurrency,
+        circuit_breaker: subscription.circuit_breaker.clone(),
+        rate_limit: subscription.rate_limit.clone(),
+        dead_letter_topic: subscription.dead_letter_topic.clone(),
+        ..SubscriptionEntry::new(&subscription.name, &subscription.endpoint)
+    };
+
+    if subscription_entry.mode != SubscriptionMode::Push && !subscription.credentials.is_empty() {
+        return Err(CredentialServiceError::NotPush(subscription_entry.name).into());
+    }
+
+    // Secret is saved before the subscription so even the first delivery is signed
+    let (subscription, signing_secret) = topic_service.subscribe_topic_with(topic_name, subscription_entry, |subscription_entry| {
+        if subscription_entry.mode != SubscriptionMode::Push {
+            return Ok::<_, ApiError>(None);
+        }
+
+        credential_service.create_credentials(topic_name, &subscription_entry.name, &subscription.credentials)?;
+        Ok(Some(signing_service.create_secret(topic_name, &subscription_entry.name)?.secret))
+    })?;
+
+    Ok(Json(CreatedSubscriptionDTO { subscription, signing_secret }))
+}
+
+#[get("/topics/<topic_name>/subscriptions")]
+pub fn get_subscriptions(
+    topic_name: &str,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<Vec<SubscriptionEntry>>, ApiError> {
+
+    Ok(Json(topic_service.get_topic(topic_name)?.subscribers))
+}
+
+#[get("/topics/<topic_name>/subscriptions/<subscription_name>")]
+pub fn get_subscription(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<SubscriptionEntry>, ApiError> {
+
+    Ok(Json(topic_service.get_subscription(topic_name, subscription_name)?))
+}
+
+#[patch("/topics/<topic_name>/subscriptions/<subscription_name>", data = "<update>")]
+pub fn update_subscription(
+    topic_name: &str,
+    subscription_name: &str,
+    update: Json<UpdateSubscriptionDTO>,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<SubscriptionEntry>, ApiError> {
+
+    let mut subscription = topic_service.get_subscription(topic_name, subscription_name)?;
+
+    if let Some(endpoint) = &update.endpoint {
+        subscription = topic_service.change_endpoint(topic_name, subscription_name, endpoint)?;
+    }
+
+    if let Some(rate_limit) = &update.rate_limit {
+        subscription = topic_service.set_rate_limit(topic_name, subscription_name, rate_limit)?;
+    }
+
+    Ok(Json(subscription))
+}
+
+#[delete("/topics/<topic_name>/subscriptions/<subscription_name>")]
+pub fn delete_subscription(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<SubscriptionEntry>, ApiError> {
+
+    Ok(Json(topic_service.unsubscribe_topic(topic_name, subscription_name)?))
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/pause")]
+pub fn pause_subscription(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<SubscriptionEntry>, ApiError> {
+
+    Ok(Json(topic_service.set_paused(topic_name, subscription_name, true)?))
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/resume")]
+pub fn resume_subscription(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<SubscriptionEntry>, ApiError> {
+
+    Ok(Json(topic_service.set_paused(topic_name, subscription_name, false)?))
+}
+
+/// Verifies the endpoint again, deliveries start once it echoes the challenge
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/verify")]
+pub fn verify_subscription(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<SubscriptionEntry>, ApiError> {
+
+    Ok(Json(topic_service.request_verification(topic_name, subscription_name)?))
+}
+
+/// Headers and credentials sent with deliveries, with secret values redacted
+#[get("/topics/<topic_name>/subscriptions/<subscription_name>/credentials")]
+pub fn get_credentials(
+    topic_name: &str,
+    subscription_name: &str,
+    topic_service: &State<Arc<TopicService>>,
+    credential_service: &State<Arc<CredentialService>>
+) -> Result<Json<DeliveryCredentials>, ApiError> {
+
+    topic_service.get_subscription(topic_name, subscription_name)?;
+    Ok(Json(credential_service.get_credentials(topic_name, subscription_name)?.redacted()))
+}
+
+/// Replaces all headers and credentials of the subscription
+#[put("/topics/<topic_name>/subscriptions/<subscription_name>/credentials", data = "<credentials>")]
+pub fn set_credentials(
+    topic_name: &str,
+    subscription_name: &str,
+    credentials: Json<DeliveryCredentials>,
+    credential_service: &State<Arc<CredentialService>>
+) -> Result<Json<DeliveryCredentials>, ApiError> {
+
+    credential_service.set_credentials(topic_name, subscription_name, &credentials)?;
+    Ok(Json(credentials.redacted()))
+}
+
+/// Recent delivery attempts, newest first. Can be narrowed down to a partition or an offset
+#[get("/topics/<topic_name>/subscriptions/<subscription_name>/attempts?<partition>&<offset>&<limit>")]
+pub fn get_attempts(
+    topic_name: &str,
+    subscription_name: &str,
+    partition: Option<u32>,
+    offset: Option<Offset>,
+    limit: Option<usize>,
+    topic_service: &State<Arc<TopicService>>,
+    history_service: &State<Arc<HistoryService>>
+) -> Result<Json<Vec<DeliveryAttempt>>, ApiError> {
+
+    topic_service.get_subscription(topic_name, subscription_name)?;
+    Ok(Json(history_service.attempts(topic_name, subscription_name, partition, offset, limit.unwrap_or(DEFAULT_ATTEMPTS))?))
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/redrive")]
+pub fn redrive_subscription(
+    topic_name: &str,
+    subscription_name: &str,
+    dead_letter_service: &State<Arc<DeadLetterService>>
+) -> Result<Json<Redrive>, ApiError> {
+
+    Ok(Json(dead_letter_service.redrive(topic_name, subscription_name)?))
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/secret/rotate", data = "<rotation>")]
+pub fn rotate_secret(
+    topic_name: &str,
+    subscription_name: &str,
+    rotation: Json<RotateSecretDTO>,
+    signing_service: &State<Arc<SigningService>>
+) -> Result<Json<RevealedSecret>, ApiError> {
+
+    Ok(Json(signing_service.rotate_secret(topic_name, subscription_name, rotation.overlap_seconds)?))
+}
+
+#[post("/topics/<topic_name>/subscriptions/<subscription_name>/reset", data = "<reset>")]
+pub fn reset_subscription(
+    topic_name: &str,
+    subscription_name: &str,
+    reset: Json<ResetDTO>,
+    offset_service: &State<Arc<OffsetService>>
+) -> Result<Json<OffsetReset>, ApiError> {
+
+    Ok(Json(offset_service.reset_subscription(topic_name, subscription_name, reset.target, reset.dry_run)?))
+}
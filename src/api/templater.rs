@@ -24,7 +24,7 @@ impl Templater {
         let mut file = OpenOptions::new()
             .read(true)
             .open(&file_name)
-            .expect(&("Failed to open file".to_owned() + &file_name));
+            .unwrap_or_else(|_| panic!("Failed to open file {file_name}"));
 
         // Read file into the buffer
         let mut buffer = vec![];
@@ -57,10 +57,10 @@ impl Templater {
 
         // 4. Add the remaining text
         result.push_str(std::str::from_utf8(&buffer[buffer_index..]).unwrap());
-        return result;
+        result
     }
 
-    fn parse(html: &Vec<u8>) -> Vec<Variable> {
+    fn parse(html: &[u8]) -> Vec<Variable> {
 
         let mut res = vec![];
         let mut is_reading = false;
@@ -89,7 +89,7 @@ impl Templater {
 
 #[test]
 fn parse_test() {
-    let str = String::from("Some text\nmore text\n <b> {my_variable} </b> \neven more text").bytes().collect();
+    let str: Vec<u8> = String::from("Some text\nmore text\n <b> {my_variable} </b> \neven more text").bytes().collect();
     let var = &Templater::parse(&str)[0];
 
     assert_eq!(var.name, String::from("my_variable"));
@@ -99,7 +99,7 @@ fn parse_test() {
 
 #[test]
 fn parse_space_test() {
-    let str = String::from("asd { ddd } dsa").bytes().collect();
+    let str: Vec<u8> = String::from("asd { ddd } dsa").bytes().collect();
     let var = &Templater::parse(&str)[0];
 
     assert_eq!(var.name, String::from("ddd"));
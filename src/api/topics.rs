@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use rocket::{serde::json::{Json, serde_json}, State};
+
+use crate::api::ApiError;
+use crate::topic::schema_service::{SchemaService, SchemaList, SchemaVersion};
+use crate::topic::topic_config::TopicConfig;
+use crate::topic::topic_service::TopicService;
+
+#[get("/topics/<topic_name>/config")]
+pub fn get_topic_config(
+    topic_name: &str,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<TopicConfig>, ApiError> {
+
+    Ok(Json(topic_service.get_topic(topic_name)?.config))
+}
+
+#[put("/topics/<topic_name>/config", data = "<config>")]
+pub fn update_topic_config(
+    topic_name: &str,
+    config: Json<TopicConfig>,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<TopicConfig>, ApiError> {
+
+    Ok(Json(topic_service.update_topic_config(topic_name, config.0)?))
+}
+
+#[get("/config/defaults")]
+pub fn get_default_config(topic_service: &State<Arc<TopicService>>) -> Result<Json<TopicConfig>, ApiError> {
+    Ok(Json(topic_service.get_default_config()?))
+}
+
+#[put("/config/defaults", data = "<config>")]
+pub fn update_default_config(
+    config: Json<TopicConfig>,
+    topic_service: &State<Arc<TopicService>>
+) -> Result<Json<TopicConfig>, ApiError> {
+
+    Ok(Json(topic_service.set_default_config(config.0)?))
+}
+
+#[get("/topics/<topic_name>/schemas")]
+pub fn get_schemas(
+    topic_name: &str,
+    schema_service: &State<Arc<SchemaService>>
+) -> Result<Json<SchemaList>, ApiError> {
+
+    Ok(Json(schema_service.get_schemas(topic_name)?))
+}
+
+#[get("/topics/<topic_name>/schemas/latest")]
+pub fn get_latest_schema(
+    topic_name: &str,
+    schema_service: &State<Arc<SchemaService>>
+) -> Result<Json<SchemaVersion>, ApiError> {
+
+    Ok(Json(schema_service.get_latest_schema(topic_name)?))
+}
+
+#[get("/topics/<topic_name>/schemas/<version>", rank = 2)]
+pub fn get_schema(
+    topic_name: &str,
+    version: u32,
+    schema_service: &State<Arc<SchemaService>>
+) -> Result<Json<SchemaVersion>, ApiError> {
+
+    Ok(Json(schema_service.get_schema(topic_name, version)?))
+}
+
+#[post("/topics/<topic_name>/schemas", data = "<schema>")]
+pub fn register_schema(
+    topic_name: &str,
+    schema: Json<serde_json::Value>,
+    schema_service: &State<Arc<SchemaService>>
+) -> Result<Json<SchemaVersion>, ApiError> {
+
+    Ok(Json(schema_service.register_schema(topic_name, schema.0)?))
+}
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use rocket::form::{Form, FromForm};
+use rocket::http::uri::Host;
+use rocket::response::status;
+use rocket::{Config, State};
+
+use crate::api::ApiError;
+use crate::delivery::websub_service::{HubRequest, WebSubService};
+
+/// Form of a WebSub request, its fields are `hub.mode`, `hub.topic` and so on
+#[derive(FromForm)]
+pub struct HubForm {
+    hub: HubRequest
+}
+
+///
+/// WebSub hub. Answers 202 right away, the request takes effect once the callback confirms it
+///
+#[post("/", data = "<request>")]
+pub fn hub(
+    request: Form<HubForm>,
+    host: Option<&Host<'_>>,
+    config: &Config,
+    websub_service: &State<Arc<WebSubService>>
+) -> Result<status::Accepted<()>, ApiError> {
+
+    // Deliveries link back to the hub, as seen by the subscriber
+    let hub_url = match host {
+        Some(host) => format!("http://{host}/websub"),
+        None => format!("http://{}:{}/websub", config.address, config.port)
+    };
+
+    websub_service.handle(&request.hub, &hub_url)?;
+    Ok(status::Accepted(()))
+}
@@ -0,0 +1,123 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use crate::partition::partition::Offset;
+
+// Upper bound of messages in a single batch request
+pub const MAX_BATCH_MESSAGES: usize = 1000;
+
+///
+/// Makes a subscription receive messages in batches - a JSON array in one POST - instead of one
+/// request per message. A batch is sent once it has `max_messages` messages or `max_bytes` of body,
+/// or with whatever accumulated once the oldest message waited `max_wait_ms`. A batch only holds
+/// messages of one partition, in offset order.
+///
+/// The whole batch is committed when the endpoint responds with 2xx. The endpoint can accept only
+/// a part of it by responding with `{"failed": [<offset>, ...]}` - listed messages are then
+/// retried as a smaller batch, according to the retry policy, and dead-lettered one by one once
+/// retries run out. Any other response fails the whole batch.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchPolicy {
+    pub max_messages: usize,
+    pub max_bytes: usize,
+    pub max_wait_ms: u64
+}
+
+impl Default for BatchPolicy {
+    fn default() -> Self {
+        BatchPolicy {
+            max_messages: 100,
+            max_bytes: 1024 * 1024,
+            max_wait_ms: 1000
+        }
+    }
+}
+
+/// Body of a 2xx response to a batch. Anything else - including no body - accepts the whole batch
+#[derive(Default, Deserialize)]
+pub struct BatchResponse {
+    #[serde(default)]
+    pub failed: Vec<Offset>
+}
+
+impl BatchPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_messages == 0 || self.max_messages > MAX_BATCH_MESSAGES {
+            return Err(format!("max_messages must be between 1 and {MAX_BATCH_MESSAGES}"));
+        }
+
+        if self.max_bytes == 0 {
+            return Err("max_bytes must be greater than 0".to_owned());
+        }
+
+        Ok(())
+    }
+
+    pub fn max_wait(&self) -> Duration {
+        Duration::from_millis(self.max_wait_ms)
+    }
+
+    ///
+    /// Splits serialized messages into batches, returned as ranges of indices with a flag telling
+    /// if the batch is full. Only the last one can be incomplete. A single message larger than
+    /// `max_bytes` gets a batch of its own
+    ///
+    pub fn split(&self, message_sizes: &[usize]) -> Vec<(Range<usize>, bool)> {
+        let mut batches = vec![];
+        let mut start = 0;
+
+        // Brackets of the array
+        let mut bytes = 2;
+
+        for (i, size) in message_sizes.iter().enumerate() {
+            // Comma between messages
+            let added = size + usize::from(i > start);
+
+            if i > start && bytes + added > self.max_bytes {
+                batches.push((start..i, true));
+                start = i;
+                bytes = 2 + size;
+            } else {
+                bytes += added;
+            }
+
+            if i + 1 - start == self.max_messages {
+                batches.push((start..i + 1, true));
+                start = i + 1;
+                bytes = 2;
+            }
+        }
+
+        if start < message_sizes.len() {
+            batches.push((start..message_sizes.len(), bytes >= self.max_bytes));
+        }
+
+        batches
+    }
+}
+
+#[test]
+fn test_split_by_count_and_size() {
+    let policy = BatchPolicy { max_messages: 3, max_bytes: 20, max_wait_ms: 0 };
+
+    assert_eq!(policy.split(&[]), vec![]);
+    assert_eq!(policy.split(&[1, 1, 1, 1]), vec![(0..3, true), (3..4, false)]);
+
+    // [5,5,5] is 2 + 15 + 2 commas = 19 bytes, one more message wouldn't fit
+    assert_eq!(policy.split(&[5, 5, 5]), vec![(0..3, true)]);
+    assert_eq!(policy.split(&[8, 8, 1]), vec![(0..2, true), (2..3, false)]);
+    assert_eq!(policy.split(&[50, 1]), vec![(0..1, true), (1..2, false)]);
+    assert_eq!(policy.split(&[50]), vec![(0..1, true)]);
+}
+
+#[test]
+fn test_validate() {
+    assert!(BatchPolicy::default().validate().is_ok());
+    assert!(BatchPolicy { max_messages: 0, ..Default::default() }.validate().is_err());
+    assert!(BatchPolicy { max_messages: MAX_BATCH_MESSAGES + 1, ..Default::default() }.validate().is_err());
+    assert!(BatchPolicy { max_bytes: 0, ..Default::default() }.validate().is_err());
+}
@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+///
+/// When the circuit of a subscription opens. After `failure_threshold` failed requests in a row
+/// nothing is sent to the endpoint for `open_ms`. Then a single trial request goes through
+/// (half-open) - if it succeeds deliveries go back to normal, otherwise the circuit opens again.
+/// Waiting for a closed circuit doesn't use up retry attempts.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerPolicy {
+    pub failure_threshold: u32,
+    pub open_ms: u64
+}
+
+impl Default for CircuitBreakerPolicy {
+    fn default() -> Self {
+        CircuitBreakerPolicy {
+            failure_threshold: 5,
+            open_ms: 30_000
+        }
+    }
+}
+
+impl CircuitBreakerPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.failure_threshold == 0 {
+            return Err("failure_threshold must be greater than 0".to_owned());
+        }
+
+        if self.open_ms == 0 {
+            return Err("open_ms must be greater than 0".to_owned());
+        }
+
+        Ok(())
+    }
+
+    pub fn open_duration(&self) -> Duration {
+        Duration::from_millis(self.open_ms)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Endpoint works, requests go through
+    #[default]
+    Closed,
+
+    /// Endpoint keeps failing, nothing is sent
+    Open,
+
+    /// Trial request is checking if the endpoint recovered
+    HalfOpen
+}
+
+/// How the subscription's endpoint is doing, as seen by the delivery engine
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SubscriptionHealth {
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+
+    /// Seconds since epoch
+    pub last_error_at: Option<u64>,
+
+    /// Seconds since epoch
+    pub last_success_at: Option<u64>,
+
+    /// Falling behind because of the subscription's own rate limit, not because of errors.
+    /// Cleared once the subscription catches up
+    #[serde(default)]
+    pub throttled: bool
+}
+
+///
+/// Circuit of a single subscription. Lives in the delivery worker, its state is
+/// copied to the subscription's health whenever it changes
+///
+#[derive(Default)]
+pub struct CircuitBreaker {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+
+    // Half-open circuit lets a single request through
+    trial_in_flight: bool
+}
+
+// How long to wait for the result of a trial request before asking again
+const TRIAL_WAIT: Duration = Duration::from_millis(50);
+
+impl CircuitBreaker {
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    ///
+    /// Reserves a request. If the circuit doesn't let it through returns how long to wait before asking again
+    ///
+    pub fn try_acquire(&mut self, policy: &CircuitBreakerPolicy, now: Instant) -> Result<(), Duration> {
+        match self.state {
+            CircuitState::Closed => Ok(()),
+
+            CircuitState::Open => {
+                let open_until = self.opened_at.unwrap_or(now) + policy.open_duration();
+                if now < open_until {
+                    return Err(open_until - now);
+                }
+
+                self.state = CircuitState::HalfOpen;
+                self.trial_in_flight = true;
+                Ok(())
+            }
+
+            CircuitState::HalfOpen if self.trial_in_flight => Err(TRIAL_WAIT),
+
+            CircuitState::HalfOpen => {
+                self.trial_in_flight = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns true if the state changed
+    pub fn on_success(&mut self) -> bool {
+        let changed = self.state != CircuitState::Closed;
+
+        self.state = CircuitState::Closed;
+        self.consecutive_failures = 0;
+        self.trial_in_flight = false;
+
+        changed
+    }
+
+    /// Returns true if the state changed
+    pub fn on_failure(&mut self, policy: &CircuitBreakerPolicy, now: Instant) -> bool {
+        self.consecutive_failures += 1;
+        self.trial_in_flight = false;
+
+        let opens = match self.state {
+            CircuitState::Closed => self.consecutive_failures >= policy.failure_threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false
+        };
+
+        if opens {
+            self.state = CircuitState::Open;
+            self.opened_at = Some(now);
+        }
+
+        opens
+    }
+}
+
+#[test]
+fn test_circuit_opens_and_recovers() {
+    let policy = CircuitBreakerPolicy { failure_threshold: 2, open_ms: 1000 };
+    let mut breaker = CircuitBreaker::default();
+    let now = Instant::now();
+
+    assert!(breaker.try_acquire(&policy, now).is_ok());
+    assert!(!breaker.on_failure(&policy, now));
+    assert!(breaker.on_failure(&policy, now));
+    assert_eq!(breaker.state(), CircuitState::Open);
+    assert_eq!(breaker.try_acquire(&policy, now + Duration::from_millis(400)), Err(Duration::from_millis(600)));
+
+    // Single trial once open time passes, failed trial opens the circuit again
+    let later = now + Duration::from_millis(1000);
+    assert!(breaker.try_acquire(&policy, later).is_ok());
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    assert_eq!(breaker.try_acquire(&policy, later), Err(TRIAL_WAIT));
+    assert!(breaker.on_failure(&policy, later));
+    assert!(breaker.try_acquire(&policy, later).is_err());
+
+    let even_later = later + Duration::from_millis(1000);
+    assert!(breaker.try_acquire(&policy, even_later).is_ok());
+    assert!(breaker.on_success());
+    assert_eq!(breaker.state(), CircuitState::Closed);
+    assert_eq!(breaker.consecutive_failures(), 0);
+}
+
+#[test]
+fn test_validate() {
+    assert!(CircuitBreakerPolicy::default().validate().is_ok());
+    assert!(CircuitBreakerPolicy { failure_threshold: 0, ..Default::default() }.validate().is_err());
+    assert!(CircuitBreakerPolicy { open_ms: 0, ..Default::default() }.validate().is_err());
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use kopperdb::kopper::KopperError;
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::{Serialize, Deserialize};
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use thiserror::Error;
+
+use crate::topic::topic_service::{TopicService, TopicServiceError, SubscriptionMode};
+use crate::storage::database::Database;
+
+// Shown instead of secret values
+const REDACTED: &str = "***";
+
+// Headers set by the delivery engine itself, subscribers can't override them
+const RESERVED_HEADERS: [&str; 7] = ["content-type", "content-length", "host", "connection", "transfer-encoding", "link", "x-hub-signature"];
+const RESERVED_PREFIX: &str = "x-czkawka-";
+
+#[derive(Debug, Error)]
+pub enum CredentialServiceError {
+    #[error(transparent)]
+    Topic(#[from] TopicServiceError),
+
+    #[error("Subscription {0} is a queue, only push deliveries carry credentials")]
+    NotPush(String),
+
+    #[error("Invalid credentials: {0}")]
+    Invalid(String),
+
+    #[error(transparent)]
+    Serde(#[from] serde_json::error::Error),
+
+    #[error("Internal database error, check logs")]
+    DatabaseError
+}
+
+impl CredentialServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            CredentialServiceError::Topic(error) => error.status(),
+            CredentialServiceError::NotPush(_) | CredentialServiceError::Invalid(_) => Status::BadRequest,
+            CredentialServiceError::Serde(_) | CredentialServiceError::DatabaseError => Status::InternalServerError,
+        }
+    }
+}
+
+/// Sent as the `Authorization` header of every delivery
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Auth {
+    Bearer { token: String },
+    Basic { username: String, password: String }
+}
+
+///
+/// Extra headers and credentials sent with every request to the subscription's endpoint -
+/// deliveries and verification alike - for receivers sitting behind gateways. Stored in the db
+/// under the `credentials/<topic name>/<subscription name>` key, away from the subscription,
+/// and only ever shown redacted
+///
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeliveryCredentials {
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub auth: Option<Auth>
+}
+
+impl DeliveryCredentials {
+    pub fn key(topic_name: &str, subscription_name: &str) -> String {
+        format!("credentials/{topic_name}/{subscription_name}")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.auth.is_none()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in &self.headers {
+            let header = HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("{name} isn't a valid header name"))?;
+            HeaderValue::from_str(value).map_err(|_| format!("value of header {name} isn't valid"))?;
+
+            let header = header.as_str();
+            if RESERVED_HEADERS.contains(&header) || header.starts_with(RESERVED_PREFIX) {
+                return Err(format!("header {name} is set by the broker"));
+            }
+
+            if header == "authorization" && self.auth.is_some() {
+                return Err("Authorization header can't be set together with auth".to_owned());
+            }
+        }
+
+        match &self.auth {
+            Some(Auth::Bearer { token }) if HeaderValue::from_str(token).is_err() || token.is_empty() => Err("bearer token isn't valid".to_owned()),
+            Some(Auth::Basic { username, .. }) if username.contains(':') => Err("basic auth username can't contain ':'".to_owned()),
+            _ => Ok(())
+        }
+    }
+
+    /// Same credentials with every secret replaced, safe to show
+    pub fn redacted(&self) -> DeliveryCredentials {
+        DeliveryCredentials {
+            headers: self.headers.keys().map(|name| (name.clone(), REDACTED.to_owned())).collect(),
+            auth: self.auth.as_ref().map(|auth| match auth {
+                Auth::Bearer { .. } => Auth::Bearer { token: REDACTED.to_owned() },
+                Auth::Basic { username, .. } => Auth::Basic { username: username.clone(), password: REDACTED.to_owned() }
+            })
+        }
+    }
+
+    /// Adds the headers and credentials to a request
+    pub fn apply(&self, mut request: reqwest::blocking::RequestBuilder) -> reqwest::blocking::RequestBuilder {
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        match &self.auth {
+            Some(Auth::Bearer { token }) => request.bearer_auth(token),
+            Some(Auth::Basic { username, password }) => request.basic_auth(username, Some(password)),
+            None => request
+        }
+    }
+}
+
+///
+/// Keeps outbound headers and credentials of push subscriptions
+///
+pub struct CredentialService {
+    topic_service: Arc<TopicService>,
+    db: Database
+}
+
+impl CredentialService {
+    pub fn new(topic_service: Arc<TopicService>, db: Database) -> Self {
+        CredentialService { topic_service, db }
+    }
+
+    ///
+    /// Saves credentials of a new subscription. Called from `subscribe_topic_with`, before the
+    /// subscription is saved, so its very first request carries them
+    ///
+    pub fn create_credentials(&self, topic_name: &str, subscription_name: &str, credentials: &DeliveryCredentials) -> Result<(), CredentialServiceError> {
+        credentials.validate().map_err(CredentialServiceError::Invalid)?;
+        self.save(topic_name, subscription_name, credentials)
+    }
+
+    /// Replaces credentials of an existing push subscription, the next request uses the new ones
+    pub fn set_credentials(&self, topic_name: &str, subscription_name: &str, credentials: &DeliveryCredentials) -> Result<(), CredentialServiceError> {
+        // Unsubscribe waits until they are saved, or they would be left behind
+        self.topic_service.with_subscription(topic_name, subscription_name, |subscription| {
+            if subscription.mode != SubscriptionMode::Push {
+                return Err(CredentialServiceError::NotPush(subscription_name.to_owned()));
+            }
+
+            self.create_credentials(topic_name, subscription_name, credentials)
+        })
+    }
+
+    pub fn get_credentials(&self, topic_name: &str, subscription_name: &str) -> Result<DeliveryCredentials, CredentialServiceError> {
+        match self.db.read(&DeliveryCredentials::key(topic_name, subscription_name)) {
+            Ok(serialized) => Ok(serde_json::from_str(&serialized)?),
+
+            // Subscriptions created before credentials were introduced
+            Err(KopperError::KeyDoesNotExist(_)) => Ok(DeliveryCredentials::default()),
+
+            Err(err) => {
+                println!("Kopper error when reading {err}");
+                Err(CredentialServiceError::DatabaseError)
+            }
+        }
+    }
+
+    fn save(&self, topic_name: &str, subscription_name: &str, credentials: &DeliveryCredentials) -> Result<(), CredentialServiceError> {
+        if let Err(err) = self.db.write(&DeliveryCredentials::key(topic_name, subscription_name), &serde_json::to_string(credentials)?) {
+            println!("Kopper error when writing {err}");
+            return Err(CredentialServiceError::DatabaseError);
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_validate_and_redact() {
+    let credentials = |headers: &[(&str, &str)], auth| DeliveryCredentials {
+        headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+        auth
+    };
+    let bearer = || Some(Auth::Bearer { token: "t0ken".to_owned() });
+
+    assert!(credentials(&[("X-Api-Key", "abc")], bearer()).validate().is_ok());
+    assert!(credentials(&[("Authorization", "Custom abc")], None).validate().is_ok());
+    assert!(credentials(&[("Authorization", "Custom abc")], bearer()).validate().is_err());
+    assert!(credentials(&[("Content-Type", "text/plain")], None).validate().is_err());
+    assert!(credentials(&[("X-Czkawka-Signature", "v1=forged")], None).validate().is_err());
+    assert!(credentials(&[("Bad Name", "abc")], None).validate().is_err());
+    assert!(credentials(&[("X-Api-Key", "new\nline")], None).validate().is_err());
+
+    let basic = Some(Auth::Basic { username: "user".to_owned(), password: "secret".to_owned() });
+    let redacted = credentials(&[("X-Api-Key", "abc")], basic).redacted();
+    assert_eq!(redacted.headers["X-Api-Key"], REDACTED);
+    assert_eq!(redacted.auth, Some(Auth::Basic { username: "user".to_owned(), password: REDACTED.to_owned() }));
+}
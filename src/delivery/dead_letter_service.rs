@@ -0,0 +1,149 @@
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use rocket::serde::json::serde_json;
+
+use crate::partition::partition::Offset;
+use crate::topic::log_service::{LogService, LogEntry};
+use crate::topic::publisher_service::{MessagePayload, PublisherService, PublisherServiceError, PublishedMessage};
+use crate::topic::topic_service::{TopicService, TopicServiceError, SubscriptionEntry};
+
+// Headers added to every dead-lettered message, so it can be traced back (and re-driven) to where it came from
+pub const ORIGINAL_TOPIC_HEADER: &str = "x-dlq-original-topic";
+pub const ORIGINAL_PARTITION_HEADER: &str = "x-dlq-original-partition";
+pub const ORIGINAL_OFFSET_HEADER: &str = "x-dlq-original-offset";
+pub const SUBSCRIPTION_HEADER: &str = "x-dlq-subscription";
+pub const LAST_ERROR_HEADER: &str = "x-dlq-last-error";
+pub const ATTEMPTS_HEADER: &str = "x-dlq-attempts";
+
+/// Re-driven messages go back to the original topic, but only the named subscription gets them
+pub const REDRIVE_HEADER: &str = "x-czkawka-redrive-subscription";
+
+// How many entries are read from a dead letter partition at once
+const READ_BATCH: usize = 100;
+
+/// Outcome of a re-drive
+#[derive(Serialize)]
+pub struct Redrive {
+    pub redriven: usize
+}
+
+///
+/// Moves messages that can't be delivered to the dead letter topic of their subscription,
+/// and brings them back once the subscriber is fixed.
+///
+pub struct DeadLetterService {
+    topic_service: Arc<TopicService>,
+    log_service: Arc<LogService>,
+    publisher_service: Arc<PublisherService>,
+
+    // Two re-drives of the same subscription running at once would both republish the same messages
+    redrive_lock: Mutex<()>
+}
+
+impl DeadLetterService {
+    pub fn new(topic_service: Arc<TopicService>, log_service: Arc<LogService>, publisher_service: Arc<PublisherService>) -> Self {
+        DeadLetterService { topic_service, log_service, publisher_service, redrive_lock: Mutex::new(()) }
+    }
+
+    ///
+    /// Publishes the message to the subscription's dead letter topic, with headers recording
+    /// where it came from and why it's there. Key, headers and value of the message are kept.
+    /// `entry` is the message as read from the topic's log
+    ///
+    pub fn dead_letter(
+        &self,
+        topic_name: &str,
+        subscription: &SubscriptionEntry,
+        partition: u32,
+        entry: &LogEntry,
+        last_error: &str,
+        attempts: u32
+    ) -> Result<PublishedMessage, PublisherServiceError> {
+
+        let dead_letter_topic = subscription.dead_letter_topic.as_deref().ok_or_else(|| TopicServiceError::InvalidSubscription(
+            format!("subscription {} has no dead letter topic", subscription.name)))?;
+
+        let mut dead_letter: MessagePayload = serde_json::from_str(&entry.value).map_err(|err| {
+            println!("Can't read message {topic_name}/{partition}/{}: {err}", entry.offset);
+            PublisherServiceError::Internal
+        })?;
+
+        dead_letter.schema_version = None;
+
+        dead_letter.headers.remove(REDRIVE_HEADER);
+        dead_letter.headers.extend([
+            (ORIGINAL_TOPIC_HEADER.to_owned(), topic_name.to_owned()),
+            (ORIGINAL_PARTITION_HEADER.to_owned(), partition.to_string()),
+            (ORIGINAL_OFFSET_HEADER.to_owned(), entry.offset.to_string()),
+            (SUBSCRIPTION_HEADER.to_owned(), subscription.name.clone()),
+            (LAST_ERROR_HEADER.to_owned(), last_error.to_owned()),
+            (ATTEMPTS_HEADER.to_owned(), attempts.to_string())
+        ]);
+
+        self.publisher_service.publish_message(dead_letter_topic, dead_letter)
+    }
+
+    ///
+    /// Publishes messages dead-lettered by the subscription back to its topic. Only that subscription
+    /// gets them again. Every message is re-driven once - later calls only pick up new dead letters.
+    ///
+    pub fn redrive(&self, topic_name: &str, subscription_name: &str) -> Result<Redrive, PublisherServiceError> {
+        let _guard = self.redrive_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let subscription = self.topic_service.get_subscription(topic_name, subscription_name)?;
+        let dead_letter_topic = match &subscription.dead_letter_topic {
+            Some(dead_letter_topic) => self.topic_service.get_topic(dead_letter_topic)?,
+            None => return Err(TopicServiceError::InvalidSubscription(
+                format!("subscription {subscription_name} has no dead letter topic")).into()),
+        };
+
+        let mut redriven = 0;
+
+        for partition in 0..dead_letter_topic.config.partitions {
+            let mut position = subscription.redriven.get(&partition).copied().unwrap_or(0);
+
+            loop {
+                let entries = self.log_service.read(&dead_letter_topic, partition, position, READ_BATCH).map_err(|err| {
+                    println!("Can't read partition {partition} of {}: {err}", dead_letter_topic.name);
+                    PublisherServiceError::Internal
+                })?;
+
+                let Some(last) = entries.last() else { break };
+                let next_position = last.offset + 1;
+
+                for entry in entries {
+                    let Ok(mut message) = serde_json::from_str::<MessagePayload>(&entry.value) else { continue };
+
+                    // Dead letter topic can be shared by many subscriptions
+                    if message.headers.get(ORIGINAL_TOPIC_HEADER).map(String::as_str) != Some(topic_name)
+                        || message.headers.get(SUBSCRIPTION_HEADER).map(String::as_str) != Some(subscription_name) {
+                        continue;
+                    }
+
+                    message.headers.retain(|name, _| !name.starts_with("x-dlq-"));
+                    message.headers.insert(REDRIVE_HEADER.to_owned(), subscription_name.to_owned());
+
+                    if let Err(err) = self.publisher_service.publish_message(topic_name, message) {
+                        // Messages before this one are republished already, the next re-drive starts with it
+                        self.save_progress(topic_name, subscription_name, partition, entry.offset)?;
+                        return Err(err);
+                    }
+                    redriven += 1;
+                }
+
+                position = next_position;
+                self.save_progress(topic_name, subscription_name, partition, position)?;
+            }
+        }
+
+        Ok(Redrive { redriven })
+    }
+
+    fn save_progress(&self, topic_name: &str, subscription_name: &str, partition: u32, position: Offset) -> Result<(), TopicServiceError> {
+        self.topic_service.update_subscription(topic_name, subscription_name, |subscription| {
+            subscription.redriven.insert(partition, position);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,1026 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+use rocket::serde::json::serde_json;
+use thiserror::Error;
+
+use crate::delivery::batch_policy::BatchResponse;
+use crate::delivery::circuit_breaker::CircuitBreaker;
+use crate::delivery::credential_service::{CredentialService, DeliveryCredentials};
+use crate::delivery::dead_letter_service::{DeadLetterService, REDRIVE_HEADER};
+use crate::delivery::history_service::{DeliveryAttempt, HistoryService};
+use crate::delivery::rate_limiter::RateLimiter;
+use crate::delivery::signing_service::{SigningService, SigningSecrets, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::delivery::verification::{verify_endpoint, VerificationStatus};
+use crate::delivery::websub_service::{WebSubLease, HUB_SIGNATURE_HEADER};
+use crate::partition::partition::Offset;
+use crate::topic::log_service::{LogService, LogEntry};
+use crate::topic::offset_service::{OffsetService, OffsetServiceError};
+use crate::topic::publisher_service::MessagePayload;
+use crate::topic::topic_service::{TopicService, TopicServiceError, TopicEntry, SubscriptionEntry, SubscriptionMode};
+
+// How often the engine looks for new subscriptions
+const SUPERVISOR_INTERVAL: Duration = Duration::from_millis(200);
+
+// How long a worker sleeps when there's nothing new to deliver
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long a worker waits before trying to load its subscription again
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Lets the subscriber tell retries from first deliveries
+const ATTEMPT_HEADER: &str = "X-Czkawka-Delivery-Attempt";
+
+// How many entries are read from a partition at once
+const READ_BATCH: usize = 100;
+
+// Longest sleep while waiting for the circuit or the rate limit, the subscription is checked in between
+const WAIT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often successful deliveries are written to the subscription's health. Failures are written every time
+const HEALTH_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+///
+/// Body of the request sent to the subscriber's endpoint, one per message
+///
+#[derive(Serialize)]
+pub struct PushedMessage<'a> {
+    pub topic: &'a str,
+    pub partition: u32,
+    pub offset: Offset,
+    pub timestamp: u64,
+    pub key: Option<&'a str>,
+    pub headers: &'a BTreeMap<String, String>,
+    pub value: &'a serde_json::Value,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>
+}
+
+#[derive(Debug, Error)]
+pub enum DeliveryError {
+    #[error("Request failed: {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error("Endpoint responded with {0}")]
+    Status(reqwest::StatusCode),
+
+    #[error("Endpoint reported offsets {0:?} of the batch as failed")]
+    Rejected(Vec<Offset>)
+}
+
+///
+/// Pushes messages of every topic to its subscribers' endpoints.
+///
+/// Each subscription gets its own worker thread, which tails all partitions of the topic and POSTs
+/// messages one by one (or in batches, see `BatchPolicy`), in offset order. Subscriptions with
+/// concurrency deliver several keys at once, each key still in offset order. Position in a partition
+/// only moves past a message after the endpoint responds with 2xx, otherwise the same message is
+/// retried according to the subscription's retry policy - and moved to its dead letter topic when
+/// retries run out. Endpoints that keep failing get a break, see `CircuitBreakerPolicy`.
+///
+pub struct DeliveryEngine {
+    topic_service: Arc<TopicService>,
+    log_service: Arc<LogService>,
+    dead_letter_service: Arc<DeadLetterService>,
+    offset_service: Arc<OffsetService>,
+    signing_service: Arc<SigningService>,
+    credential_service: Arc<CredentialService>,
+    history_service: Arc<HistoryService>,
+
+    // Worker threads, by (topic name, subscription name)
+    workers: Mutex<HashMap<(String, String), JoinHandle<()>>>
+}
+
+impl DeliveryEngine {
+    ///
+    /// Creates the engine and starts a background thread that spawns workers for new subscriptions
+    ///
+    pub fn start(
+        topic_service: Arc<TopicService>,
+        log_service: Arc<LogService>,
+        dead_letter_service: Arc<DeadLetterService>,
+        offset_service: Arc<OffsetService>,
+        signing_service: Arc<SigningService>,
+        credential_service: Arc<CredentialService>,
+        history_service: Arc<HistoryService>
+    ) -> Arc<Self> {
+        let engine = Arc::new(DeliveryEngine {
+            topic_service,
+            log_service,
+            dead_letter_service,
+            offset_service,
+            signing_service,
+            credential_service,
+            history_service,
+            workers: Mutex::new(HashMap::new())
+        });
+
+        let supervisor = engine.clone();
+        std::thread::spawn(move || loop {
+            supervisor.spawn_missing_workers();
+            std::thread::sleep(SUPERVISOR_INTERVAL);
+        });
+
+        engine
+    }
+
+    fn spawn_missing_workers(&self) {
+        let topics = match self.topic_service.get_topics() {
+            Ok(topics) => topics,
+            Err(err) => {
+                println!("Delivery engine can't read topics: {err}");
+                return;
+            }
+        };
+
+        let mut workers = self.workers.lock().unwrap();
+
+        // Workers quit on their own when their subscription is gone
+        workers.retain(|_, handle| !handle.is_finished());
+
+        for topic in topics.0 {
+            for subscription in topic.subscribers {
+                // Queues are pulled by consumers themselves
+                if subscription.mode != SubscriptionMode::Push {
+                    continue;
+                }
+
+                let key = (topic.name.clone(), subscription.name.clone());
+                if workers.contains_key(&key) {
+                    continue;
+                }
+
+                let worker = SubscriptionWorker {
+                    topic_name: topic.name.clone(),
+                    topic_service: self.topic_service.clone(),
+                    log_service: self.log_service.clone(),
+                    dead_letter_service: self.dead_letter_service.clone(),
+                    offset_service: self.offset_service.clone(),
+                    signing_service: self.signing_service.clone(),
+                    credential_service: self.credential_service.clone(),
+                    history_service: self.history_service.clone(),
+                    breaker: Mutex::new(CircuitBreaker::default()),
+                    limiter: Mutex::new(RateLimiter::default()),
+                    health_saved_at: Mutex::new(None),
+                    throttled: AtomicBool::new(subscription.health.throttled),
+                    subscription_name: subscription.name
+                };
+
+                workers.insert(key, std::thread::spawn(move || {
+                    worker.run();
+
+                    // Subscription is gone, one created with the same name starts with a clean history
+                    worker.history_service.forget(&worker.topic_name, &worker.subscription_name);
+                }));
+            }
+        }
+    }
+}
+
+struct SubscriptionWorker {
+    topic_name: String,
+    subscription_name: String,
+    topic_service: Arc<TopicService>,
+    log_service: Arc<LogService>,
+    dead_letter_service: Arc<DeadLetterService>,
+    offset_service: Arc<OffsetService>,
+    signing_service: Arc<SigningService>,
+    credential_service: Arc<CredentialService>,
+    history_service: Arc<HistoryService>,
+
+    // Shared by all delivery threads of the subscription
+    breaker: Mutex<CircuitBreaker>,
+    limiter: Mutex<RateLimiter>,
+    health_saved_at: Mutex<Option<Instant>>,
+    throttled: AtomicBool
+}
+
+impl SubscriptionWorker {
+    fn run(&self) {
+
+        // Blocking client has to be created outside of async runtime, i.e. on this thread
+        let client = match reqwest::blocking::Client::builder().build() {
+            Ok(client) => client,
+            Err(err) => {
+                println!("Can't create http client for {}/{}: {err}", self.topic_name, self.subscription_name);
+                return;
+            }
+        };
+
+        // When incomplete batches started waiting, by partition
+        let mut waiting_since = HashMap::new();
+
+        let mut verification_attempt = 1;
+
+        loop {
+            let (topic, subscription) = match self.load() {
+                Ok(Some(loaded)) => loaded,
+                Ok(None) => return, // Subscription is gone, so is the worker
+                Err(err) => {
+                    println!("Can't load subscription {}/{}: {err}", self.topic_name, self.subscription_name);
+                    std::thread::sleep(RETRY_INTERVAL);
+                    continue;
+                }
+            };
+
+            if let Some(lease) = &subscription.websub {
+                if lease.unsubscribing {
+                    match self.confirm_unsubscribe(&client, &subscription) {
+                        true => return,
+                        false => continue
+                    }
+                }
+
+                if let Some(renewed) = lease.renewed() {
+                    self.confirm_renewal(&client, &subscription, renewed);
+                    continue;
+                }
+
+                // Lease runs from the verification, a pending one can't expire yet
+                let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+                if subscription.verification != VerificationStatus::Pending && lease.is_expired(now) {
+                    println!("WebSub lease of {}/{} expired", self.topic_name, self.subscription_name);
+                    self.remove();
+                    return;
+                }
+            }
+
+            // Paused worker stays around, so resuming doesn't have to wait for the supervisor
+            if subscription.paused {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+
+            match subscription.verification {
+                VerificationStatus::Verified => (),
+                VerificationStatus::Pending => {
+                    self.verify(&client, &subscription, &mut verification_attempt);
+                    continue;
+                }
+                VerificationStatus::Failed => {
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            }
+
+            let mut secrets = match self.signing_service.get_secrets(&self.topic_name, &self.subscription_name) {
+                Ok(secrets) => secrets,
+                Err(err) => {
+                    println!("Can't load signing secrets of {}/{}: {err}", self.topic_name, self.subscription_name);
+                    std::thread::sleep(RETRY_INTERVAL);
+                    continue;
+                }
+            };
+
+            let committed = match self.offset_service.committed_offsets(&self.topic_name, &subscription) {
+                Ok(committed) => committed,
+                Err(err) => {
+                    println!("Can't load committed offsets of {}/{}: {err}", self.topic_name, self.subscription_name);
+                    std::thread::sleep(RETRY_INTERVAL);
+                    continue;
+                }
+            };
+
+            match self.deliver_available(&client, &topic, &subscription, &committed, &mut secrets, &mut waiting_since) {
+                Ok(0) => {
+                    // Caught up, whatever held the subscription back doesn't anymore
+                    self.set_throttled(false);
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+                Err(Interrupted::Paused) => std::thread::sleep(POLL_INTERVAL),
+                Ok(_) => (),
+                Err(Interrupted::Gone) => return
+            }
+        }
+    }
+
+    ///
+    /// Asks the endpoint to confirm the subscription and saves the outcome. Failed attempts are
+    /// retried after the retry policy's backoff, until the policy gives up
+    ///
+    fn verify(&self, client: &reqwest::blocking::Client, subscription: &SubscriptionEntry, attempt: &mut u32) {
+        let result = self.handshake(client, subscription);
+
+        let status = match &result {
+            Ok(()) => VerificationStatus::Verified,
+            Err(_) if subscription.retry_policy.is_exhausted(*attempt) => VerificationStatus::Failed,
+            Err(_) => VerificationStatus::Pending
+        };
+
+        if let Err(err) = &result {
+            println!("Verification of {} for {}/{} failed, attempt {attempt}: {err}",
+                subscription.endpoint, self.topic_name, subscription.name);
+        }
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let saved = self.topic_service.update_subscription(&self.topic_name, &self.subscription_name, |saved| {
+            // Endpoint changed in the meantime, the new one is verified on its own
+            if saved.endpoint != subscription.endpoint || saved.verification != VerificationStatus::Pending {
+                return Ok(());
+            }
+
+            saved.verification = status;
+
+            // Lease runs from the moment the callback confirmed it
+            if let (Ok(()), Some(lease)) = (&result, saved.websub.as_mut()) {
+                lease.expires_at = now + lease.lease_seconds;
+            }
+
+            if let Err(err) = &result {
+                saved.health.last_error = Some(err.clone());
+                saved.health.last_error_at = Some(now);
+            }
+
+            Ok(())
+        });
+
+        match saved {
+            Ok(()) | Err(TopicServiceError::TopicNotFound(_) | TopicServiceError::SubscriptionNotFound(..)) => (),
+            Err(err) => println!("Can't save verification of {}/{}: {err}", self.topic_name, self.subscription_name)
+        }
+
+        match status {
+            VerificationStatus::Pending => {
+                std::thread::sleep(subscription.retry_policy.backoff(*attempt));
+                *attempt += 1;
+            }
+            _ => *attempt = 1
+        }
+    }
+
+    ///
+    /// Asks the WebSub callback to confirm it wants to unsubscribe. Returns true if the subscription
+    /// was removed, otherwise it stays as it was
+    ///
+    fn confirm_unsubscribe(&self, client: &reqwest::blocking::Client, subscription: &SubscriptionEntry) -> bool {
+        if let Err(err) = self.handshake(client, subscription) {
+            println!("Unsubscribe of {} from {} wasn't confirmed: {err}", subscription.endpoint, self.topic_name);
+
+            let kept = self.topic_service.update_subscription(&self.topic_name, &self.subscription_name, |subscription| {
+                if let Some(lease) = subscription.websub.as_mut() {
+                    lease.unsubscribing = false;
+                }
+                Ok(())
+            });
+
+            // Worker goes away only if the subscription did
+            return matches!(kept, Err(TopicServiceError::TopicNotFound(_) | TopicServiceError::SubscriptionNotFound(..)));
+        }
+
+        self.remove();
+        true
+    }
+
+    ///
+    /// Asks the WebSub callback to confirm it subscribed again. The renewed lease and the staged
+    /// secret replace the current ones only if it does, otherwise the renewal is dropped
+    ///
+    fn confirm_renewal(&self, client: &reqwest::blocking::Client, subscription: &SubscriptionEntry, renewed: WebSubLease) {
+        let renewing = SubscriptionEntry { websub: Some(renewed.clone()), ..subscription.clone() };
+        let result = self.handshake(client, &renewing);
+
+        if let Err(err) = &result {
+            println!("Renewal of {} for {}/{} wasn't confirmed: {err}", subscription.endpoint, self.topic_name, subscription.name);
+        }
+
+        let pending = subscription.websub.as_ref().and_then(|lease| lease.renewal.clone());
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        let saved = self.topic_service.update_subscription(&self.topic_name, &self.subscription_name, |saved| {
+            // Subscribed again in the meantime, that renewal is confirmed on its own
+            let Some(lease) = saved.websub.as_mut().filter(|lease| lease.renewal == pending) else {
+                return Ok(());
+            };
+
+            // Signing service logs the details
+            match result {
+                Ok(()) => {
+                    self.signing_service.apply_staged_secret(&self.topic_name, &self.subscription_name)
+                        .map_err(|_| TopicServiceError::DatabaseError)?;
+
+                    *lease = WebSubLease { expires_at: now + renewed.lease_seconds, unsubscribing: lease.unsubscribing, ..renewed };
+                    saved.verification = VerificationStatus::Verified;
+                }
+                Err(_) => {
+                    self.signing_service.discard_staged_secret(&self.topic_name, &self.subscription_name)
+                        .map_err(|_| TopicServiceError::DatabaseError)?;
+
+                    lease.renewal = None;
+                }
+            }
+
+            Ok(())
+        });
+
+        match saved {
+            Ok(()) | Err(TopicServiceError::TopicNotFound(_) | TopicServiceError::SubscriptionNotFound(..)) => (),
+            Err(err) => {
+                println!("Can't save renewal of {}/{}: {err}", self.topic_name, self.subscription_name);
+                std::thread::sleep(RETRY_INTERVAL);
+            }
+        }
+    }
+
+    /// Verification request, sent with the subscription's credentials like any other request
+    fn handshake(&self, client: &reqwest::blocking::Client, subscription: &SubscriptionEntry) -> Result<(), String> {
+        let credentials = self.credential_service.get_credentials(&self.topic_name, &self.subscription_name)
+            .map_err(|err| format!("Can't load delivery credentials: {err}"))?;
+
+        verify_endpoint(client, &self.topic_name, subscription, &credentials)
+    }
+
+    fn remove(&self) {
+        match self.topic_service.unsubscribe_topic(&self.topic_name, &self.subscription_name) {
+            Ok(_) | Err(TopicServiceError::TopicNotFound(_) | TopicServiceError::SubscriptionNotFound(..)) => (),
+            Err(err) => println!("Can't remove subscription {}/{}: {err}", self.topic_name, self.subscription_name)
+        }
+    }
+
+    ///
+    /// Delivers what's available in all partitions, up to a batch per partition, starting at
+    /// the committed offsets. Returns number of delivered messages
+    ///
+    fn deliver_available(
+        &self,
+        client: &reqwest::blocking::Client,
+        topic: &TopicEntry,
+        subscription: &SubscriptionEntry,
+        committed_offsets: &BTreeMap<u32, Offset>,
+        secrets: &mut SigningSecrets,
+        waiting_since: &mut HashMap<u32, Instant>
+    ) -> Result<usize, Interrupted> {
+
+        let mut delivered = 0;
+        let mut subscription = subscription.clone();
+        let filter = subscription.filter();
+
+        // Batches have to fit into a single read, or they would never fill up
+        let read_batch = subscription.batch.as_ref().map_or(READ_BATCH, |batch| batch.max_messages.max(READ_BATCH));
+
+        for partition in 0..topic.config.partitions {
+            let committed = committed_offsets.get(&partition).copied().unwrap_or(0);
+
+            let entries = match self.log_service.read(topic, partition, committed, read_batch) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    println!("Can't read partition {partition} of {}: {err}", topic.name);
+                    continue;
+                }
+            };
+
+            let Some(last) = entries.last() else { continue };
+            let mut position = last.offset + 1;
+
+            let mut messages = vec![];
+            for entry in entries {
+                let message: MessagePayload = match serde_json::from_str(&entry.value) {
+                    Ok(message) => message,
+                    Err(err) => {
+                        // Can't happen unless the log is corrupted. Retrying won't help
+                        println!("Skipping unreadable message {}/{partition}/{}: {err}", topic.name, entry.offset);
+                        continue;
+                    }
+                };
+
+                // Re-driven dead letters are only meant for the subscription that failed them
+                let redriven_for_other = message.headers.get(REDRIVE_HEADER)
+                    .is_some_and(|target| target != &subscription.name);
+
+                // Filtered out messages count as processed, the offset moves past them
+                let filtered_out = filter.as_ref().is_some_and(|filter| !filter.matches(message.key.as_deref(), &message.headers, &message.value));
+
+                if !redriven_for_other && !filtered_out {
+                    messages.push((entry, message));
+                }
+            }
+
+            // Set when the subscription got paused mid-delivery, the rest of the messages waits for resume
+            let mut paused = false;
+
+            match subscription.batch.clone() {
+                None if subscription.concurrency > 1 => {
+                    let (acked, interrupted) = self.deliver_concurrently(client, &subscription, secrets, partition, &messages);
+                    delivered += acked.len();
+
+                    // Position only moves past messages acked without gaps, the rest is delivered again
+                    if let Some((entry, _)) = messages.iter().find(|(entry, _)| !acked.contains(&entry.offset)) {
+                        position = entry.offset;
+                    }
+
+                    match interrupted {
+                        Some(Interrupted::Paused) => paused = true,
+                        Some(gone) => return Err(gone),
+                        None => ()
+                    }
+                }
+
+                None => for (entry, message) in &messages {
+                    match self.deliver(client, &mut subscription, secrets, partition, entry, message) {
+                        Ok(()) => delivered += 1,
+                        Err(Interrupted::Paused) => {
+                            position = entry.offset;
+                            paused = true;
+                            break;
+                        }
+                        Err(gone) => return Err(gone)
+                    }
+                },
+
+                Some(batch) => {
+                    let bodies: Vec<String> = messages.iter()
+                        .map(|(entry, message)| self.pushed_message(&subscription, partition, entry, message))
+                        .collect();
+                    let sizes: Vec<usize> = bodies.iter().map(String::len).collect();
+
+                    for (range, full) in batch.split(&sizes) {
+                        // Incomplete batch waits for more messages, up to max_wait since it was first seen
+                        let started = *waiting_since.entry(partition).or_insert_with(Instant::now);
+                        if !full && started.elapsed() < batch.max_wait() {
+                            position = messages[range.start].0.offset;
+                            break;
+                        }
+
+                        match self.deliver_batch(client, &mut subscription, secrets, partition, &messages[range.clone()], &bodies[range.clone()]) {
+                            Ok(()) => delivered += range.len(),
+                            Err(Interrupted::Paused) => {
+                                position = messages[range.start].0.offset;
+                                paused = true;
+                                break;
+                            }
+                            Err(gone) => return Err(gone)
+                        }
+                        waiting_since.remove(&partition);
+                    }
+                }
+            }
+
+            if position != committed {
+                // Committed once per batch - after a crash at most one batch is delivered again. If the offset
+                // was moved while we were delivering, the next round starts from there
+                match self.offset_service.commit_subscription_offset(&topic.name, &subscription.name, partition, committed, position) {
+                    Ok(_) => (),
+                    Err(OffsetServiceError::Topic(TopicServiceError::TopicNotFound(_) | TopicServiceError::SubscriptionNotFound(..))) => return Err(Interrupted::Gone),
+                    Err(err) => println!("Can't commit offset {position} of {}/{partition} for {}: {err}", topic.name, subscription.name)
+                }
+            }
+
+            if paused {
+                return Err(Interrupted::Paused);
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    /// Body of the request carrying the message. WebSub callbacks get only the value, as the content of the topic
+    fn pushed_message(&self, subscription: &SubscriptionEntry, partition: u32, entry: &LogEntry, message: &MessagePayload) -> String {
+        if subscription.websub.is_some() {
+            return serde_json::to_string(&message.value).unwrap_or_default();
+        }
+
+        let body = PushedMessage {
+            topic: &self.topic_name,
+            partition,
+            offset: entry.offset,
+            timestamp: entry.timestamp,
+            key: message.key.as_deref(),
+            headers: &message.headers,
+            value: &message.value,
+            schema_version: message.schema_version
+        };
+
+        serde_json::to_string(&body).unwrap_or_default()
+    }
+
+    ///
+    /// Pushes the message until the endpoint accepts it. Once retries are exhausted the message
+    /// is moved to the dead letter topic, if the subscription has one
+    ///
+    fn deliver(
+        &self,
+        client: &reqwest::blocking::Client,
+        subscription: &mut SubscriptionEntry,
+        secrets: &mut SigningSecrets,
+        partition: u32,
+        entry: &LogEntry,
+        message: &MessagePayload
+    ) -> Result<(), Interrupted> {
+
+        let body = self.pushed_message(subscription, partition, entry, message);
+
+        // Subscription is reloaded between attempts, it might have changed
+        let mut attempt = 1;
+        loop {
+            let outgoing = Outgoing { partition, offsets: vec![entry.offset], body: &body };
+            let Err(err) = self.send(client, subscription, secrets, &outgoing, attempt)? else { return Ok(()) };
+
+            println!("Delivery of {}/{partition}/{} to {} failed, attempt {attempt}: {err}", 
+                self.topic_name, entry.offset, subscription.name);
+
+            if subscription.retry_policy.is_exhausted(attempt) && subscription.dead_letter_topic.is_some() {
+                match self.dead_letter_service.dead_letter(&self.topic_name, subscription, partition, entry, &err.to_string(), attempt) {
+                    Ok(_) => return Ok(()),
+                    Err(err) => println!("Can't dead-letter {}/{partition}/{}: {err}", self.topic_name, entry.offset)
+                }
+            }
+
+            std::thread::sleep(subscription.retry_policy.backoff(attempt));
+            attempt += 1;
+            self.reload(subscription, secrets)?;
+        }
+    }
+
+    ///
+    /// Delivers messages in parallel, up to the subscription's concurrency. Messages with the same
+    /// key go through a single thread, one by one in offset order. Returns offsets of delivered
+    /// messages, and why delivery stopped early if it did
+    ///
+    fn deliver_concurrently(
+        &self,
+        client: &reqwest::blocking::Client,
+        subscription: &SubscriptionEntry,
+        secrets: &SigningSecrets,
+        partition: u32,
+        messages: &[(LogEntry, MessagePayload)]
+    ) -> (HashSet<Offset>, Option<Interrupted>) {
+
+        // Messages of a key, in offset order. Messages without a key get a lane of their own
+        let mut lanes: Vec<Vec<usize>> = vec![];
+        let mut lane_of_key: HashMap<&str, usize> = HashMap::new();
+
+        for (i, (_, message)) in messages.iter().enumerate() {
+            match message.key.as_deref() {
+                Some(key) => {
+                    let lane = *lane_of_key.entry(key).or_insert_with(|| {
+                        lanes.push(vec![]);
+                        lanes.len() - 1
+                    });
+                    lanes[lane].push(i);
+                }
+                None => lanes.push(vec![i])
+            }
+        }
+
+        let threads = subscription.concurrency.min(lanes.len());
+        let lanes = Mutex::new(lanes.into_iter());
+        let acked = Mutex::new(HashSet::new());
+        let interrupted = Mutex::new(None);
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    let mut subscription = subscription.clone();
+                    let mut secrets = secrets.clone();
+
+                    // Lane is taken in a separate statement, so the lock isn't held while delivering
+                    loop {
+                        let lane = lanes.lock().unwrap().next();
+                        let Some(lane) = lane else { return };
+
+                        for i in lane {
+                            if interrupted.lock().unwrap().is_some() {
+                                return;
+                            }
+
+                            let (entry, message) = &messages[i];
+                            match self.deliver(client, &mut subscription, &mut secrets, partition, entry, message) {
+                                Ok(()) => { acked.lock().unwrap().insert(entry.offset); }
+                                Err(err) => {
+                                    interrupted.lock().unwrap().get_or_insert(err);
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        (acked.into_inner().unwrap(), interrupted.into_inner().unwrap())
+    }
+
+    ///
+    /// Pushes the batch until the endpoint accepts all of it. Messages the endpoint reports as failed
+    /// are retried without the rest, and dead-lettered one by one once retries are exhausted
+    ///
+    fn deliver_batch(
+        &self,
+        client: &reqwest::blocking::Client,
+        subscription: &mut SubscriptionEntry,
+        secrets: &mut SigningSecrets,
+        partition: u32,
+        messages: &[(LogEntry, MessagePayload)],
+        bodies: &[String]
+    ) -> Result<(), Interrupted> {
+
+        let mut remaining: Vec<usize> = (0..messages.len()).collect();
+
+        let mut attempt = 1;
+        loop {
+            let body = format!("[{}]", remaining.iter().map(|&i| bodies[i].as_str()).collect::<Vec<_>>().join(","));
+
+            let offsets = remaining.iter().map(|&i| messages[i].0.offset).collect();
+            let outgoing = Outgoing { partition, offsets, body: &body };
+
+            let err = match self.send(client, subscription, secrets, &outgoing, attempt)? {
+                Ok(response) => {
+                    let failed = response.text().ok()
+                        .and_then(|text| serde_json::from_str::<BatchResponse>(&text).ok())
+                        .unwrap_or_default()
+                        .failed;
+                    remaining.retain(|&i| failed.contains(&messages[i].0.offset));
+
+                    if remaining.is_empty() {
+                        return Ok(());
+                    }
+
+                    DeliveryError::Rejected(remaining.iter().map(|&i| messages[i].0.offset).collect())
+                }
+                Err(err) => err
+            };
+
+            println!("Delivery of a batch of {} messages from {}/{partition} to {} failed, attempt {attempt}: {err}",
+                remaining.len(), self.topic_name, subscription.name);
+
+            if subscription.retry_policy.is_exhausted(attempt) && subscription.dead_letter_topic.is_some() {
+                remaining.retain(|&i| {
+                    let entry = &messages[i].0;
+                    match self.dead_letter_service.dead_letter(&self.topic_name, subscription, partition, entry, &err.to_string(), attempt) {
+                        Ok(_) => false,
+                        Err(err) => {
+                            println!("Can't dead-letter {}/{partition}/{}: {err}", self.topic_name, entry.offset);
+                            true
+                        }
+                    }
+                });
+
+                if remaining.is_empty() {
+                    return Ok(());
+                }
+            }
+
+            std::thread::sleep(subscription.retry_policy.backoff(attempt));
+            attempt += 1;
+            self.reload(subscription, secrets)?;
+        }
+    }
+
+    /// Loads the subscription and its secrets again between attempts, they might have changed during a long outage
+    fn reload(&self, subscription: &mut SubscriptionEntry, secrets: &mut SigningSecrets) -> Result<(), Interrupted> {
+        match self.load() {
+            Ok(Some((_, reloaded))) => *subscription = reloaded,
+            Ok(None) => return Err(Interrupted::Gone),
+            Err(_) => ()
+        };
+
+        if subscription.paused {
+            return Err(Interrupted::Paused);
+        }
+
+        if let Ok(reloaded) = self.signing_service.get_secrets(&self.topic_name, &self.subscription_name) {
+            *secrets = reloaded;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Pushes the body once the rate limit and the circuit let it through and records the outcome.
+    /// Time spent waiting for either doesn't count as an attempt
+    ///
+    fn send(
+        &self,
+        client: &reqwest::blocking::Client,
+        subscription: &mut SubscriptionEntry,
+        secrets: &mut SigningSecrets,
+        outgoing: &Outgoing,
+        attempt: u32
+    ) -> Result<Result<reqwest::blocking::Response, DeliveryError>, Interrupted> {
+
+        // Loaded before anything is reserved, failing here isn't the endpoint's fault
+        let credentials = loop {
+            match self.credential_service.get_credentials(&self.topic_name, &self.subscription_name) {
+                Ok(credentials) => break credentials,
+                Err(err) => {
+                    println!("Can't load delivery credentials of {}/{}: {err}", self.topic_name, self.subscription_name);
+                    std::thread::sleep(RETRY_INTERVAL);
+                    self.reload(subscription, secrets)?;
+                }
+            }
+        };
+
+        self.wait_for_rate_limit(subscription, secrets)?;
+
+        loop {
+            let acquired = self.breaker.lock().unwrap().try_acquire(&subscription.circuit_breaker, Instant::now());
+            match acquired {
+                Ok(()) => break,
+                Err(wait) => {
+                    std::thread::sleep(wait.min(WAIT_CHECK_INTERVAL));
+                    if let Err(interrupted) = self.reload(subscription, secrets) {
+                        self.limiter.lock().unwrap().release();
+                        return Err(interrupted);
+                    }
+                }
+            }
+        }
+
+        let started = Instant::now();
+        let result = self.push(client, subscription, secrets, &credentials, outgoing.body, attempt);
+        self.limiter.lock().unwrap().release();
+        self.record_attempt(outgoing, attempt, &result, started.elapsed());
+        self.record_health(subscription, result.as_ref().err());
+
+        Ok(result)
+    }
+
+    ///
+    /// Reserves a request within the subscription's rate limit. Having to wait means the subscription
+    /// falls behind because of its own limit, so it's marked as throttled
+    ///
+    fn wait_for_rate_limit(&self, subscription: &mut SubscriptionEntry, secrets: &mut SigningSecrets) -> Result<(), Interrupted> {
+        // Waits are short, the subscription is reloaded only now and then to notice pauses and new limits
+        let mut reloaded_at = Instant::now();
+
+        loop {
+            let acquired = self.limiter.lock().unwrap().try_acquire(&subscription.rate_limit, Instant::now());
+            match acquired {
+                Ok(()) => return Ok(()),
+                Err(wait) => {
+                    self.set_throttled(true);
+                    std::thread::sleep(wait.min(WAIT_CHECK_INTERVAL));
+
+                    if reloaded_at.elapsed() >= WAIT_CHECK_INTERVAL {
+                        self.reload(subscription, secrets)?;
+                        reloaded_at = Instant::now();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Saves the throttled flag of the subscription's health when it changes
+    fn set_throttled(&self, throttled: bool) {
+        if self.throttled.swap(throttled, Ordering::Relaxed) == throttled {
+            return;
+        }
+
+        let saved = self.topic_service.update_subscription(&self.topic_name, &self.subscription_name, |subscription| {
+            subscription.health.throttled = throttled;
+            Ok(())
+        });
+
+        match saved {
+            Ok(()) | Err(TopicServiceError::TopicNotFound(_) | TopicServiceError::SubscriptionNotFound(..)) => (),
+            Err(err) => println!("Can't save health of {}/{}: {err}", self.topic_name, self.subscription_name)
+        }
+    }
+
+    ///
+    /// Adds the request to the history of every message it carried. A batch the endpoint accepted
+    /// only partly is recorded with the status it responded with, rejected messages show up again
+    /// with the next attempt
+    ///
+    fn record_attempt(&self, outgoing: &Outgoing, attempt: u32, result: &Result<reqwest::blocking::Response, DeliveryError>, latency: Duration) {
+        let status = match result {
+            Ok(response) => Some(response.status().as_u16()),
+            Err(DeliveryError::Status(status)) => Some(status.as_u16()),
+            Err(_) => None
+        };
+
+        let error = result.as_ref().err().map(ToString::to_string);
+        let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        self.history_service.record(&self.topic_name, &self.subscription_name, outgoing.offsets.iter().map(|&offset| DeliveryAttempt {
+            partition: outgoing.partition,
+            offset,
+            attempt,
+            timestamp,
+            status,
+            latency_ms: latency.as_millis().try_into().unwrap_or(u64::MAX),
+            error: error.clone()
+        }));
+    }
+
+    ///
+    /// Moves the circuit according to the outcome of a request and saves the subscription's health.
+    /// A batch the endpoint accepted only partly still counts as a success, the endpoint is up
+    ///
+    fn record_health(&self, subscription: &SubscriptionEntry, error: Option<&DeliveryError>) {
+        let (changed, had_failures, circuit, consecutive_failures) = {
+            let mut breaker = self.breaker.lock().unwrap();
+            let had_failures = breaker.consecutive_failures() > 0;
+            let changed = match error {
+                None => breaker.on_success(),
+                Some(_) => breaker.on_failure(&subscription.circuit_breaker, Instant::now())
+            };
+            (changed, had_failures, breaker.state(), breaker.consecutive_failures())
+        };
+
+        // Steady stream of successes would rewrite the topic list on every message
+        {
+            let mut saved_at = self.health_saved_at.lock().unwrap();
+            let due = saved_at.is_none_or(|saved_at| saved_at.elapsed() >= HEALTH_SAVE_INTERVAL);
+            if error.is_none() && !changed && !had_failures && !due {
+                return;
+            }
+            *saved_at = Some(Instant::now());
+        }
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let saved = self.topic_service.update_subscription(&self.topic_name, &self.subscription_name, |subscription| {
+            let health = &mut subscription.health;
+            health.circuit = circuit;
+            health.consecutive_failures = consecutive_failures;
+
+            match error {
+                None => health.last_success_at = Some(now),
+                Some(error) => {
+                    health.last_error = Some(error.to_string());
+                    health.last_error_at = Some(now);
+                }
+            }
+
+            Ok(())
+        });
+
+        match saved {
+            Ok(()) | Err(TopicServiceError::TopicNotFound(_) | TopicServiceError::SubscriptionNotFound(..)) => (),
+            Err(err) => println!("Can't save health of {}/{}: {err}", self.topic_name, self.subscription_name)
+        }
+    }
+
+    fn push(
+        &self,
+        client: &reqwest::blocking::Client,
+        subscription: &SubscriptionEntry,
+        secrets: &SigningSecrets,
+        credentials: &DeliveryCredentials,
+        body: &str,
+        attempt: u32
+    ) -> Result<reqwest::blocking::Response, DeliveryError> {
+
+        // Signed again on every attempt, receivers reject stale timestamps
+        let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        let mut request = credentials.apply(client.post(&subscription.endpoint))
+            .timeout(subscription.retry_policy.timeout())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(ATTEMPT_HEADER, attempt);
+
+        match &subscription.websub {
+            Some(lease) => {
+                request = request.header(reqwest::header::LINK, format!("<{}>; rel=\"hub\", <{}>; rel=\"self\"", lease.hub_url, lease.topic_url));
+
+                if let Some(signature) = secrets.hub_signature(timestamp, body) {
+                    request = request.header(HUB_SIGNATURE_HEADER, signature);
+                }
+            }
+
+            None => if let Some(signature) = secrets.sign(timestamp, body) {
+                request = request
+                    .header(TIMESTAMP_HEADER, timestamp)
+                    .header(SIGNATURE_HEADER, signature);
+            }
+        }
+
+        let response = request.body(body.to_owned()).send()?;
+
+        match response.status().is_success() {
+            true => Ok(response),
+            false => Err(DeliveryError::Status(response.status()))
+        }
+    }
+
+    fn load(&self) -> Result<Option<(TopicEntry, SubscriptionEntry)>, TopicServiceError> {
+        let topic = match self.topic_service.get_topic(&self.topic_name) {
+            Ok(topic) => topic,
+            Err(TopicServiceError::TopicNotFound(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let subscription = topic.subscribers.iter().find(|x| x.name == self.subscription_name).cloned();
+        Ok(subscription.map(|subscription| (topic, subscription)))
+    }
+}
+
+/// Body of a request, with the messages it carries
+struct Outgoing<'a> {
+    partition: u32,
+    offsets: Vec<Offset>,
+    body: &'a str
+}
+
+/// Why a delivery stopped before the endpoint accepted the message
+enum Interrupted {
+    /// Worker should stop, its subscription was removed
+    Gone,
+
+    /// Subscription was paused, the message is delivered once it's resumed
+    Paused
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde::{Serialize, Deserialize};
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use thiserror::Error;
+
+use crate::partition::partition::{Offset, Partition, PartitionError};
+
+// Attempts older than this are dropped
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Upper bound of attempts kept per subscription, the oldest go first
+const MAX_ATTEMPTS: usize = 1000;
+
+#[derive(Debug, Error)]
+pub enum HistoryServiceError {
+    #[error("Internal error when reading delivery history, check logs")]
+    Internal
+}
+
+impl HistoryServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            HistoryServiceError::Internal => Status::InternalServerError,
+        }
+    }
+}
+
+/// Single request carrying a message to the subscriber's endpoint
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeliveryAttempt {
+    pub partition: u32,
+    pub offset: Offset,
+
+    /// Counting from 1, as sent in the attempt header
+    pub attempt: u32,
+
+    /// Seconds since epoch
+    pub timestamp: u64,
+
+    /// Missing if no response came back
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+    pub error: Option<String>
+}
+
+///
+/// Recent delivery attempts of every push subscription, so it's possible to tell what happened to
+/// a message the subscriber says it never got. Every subscription has its own log on disk, at
+/// `<path>/<topic name>/<subscription name>`, so the history survives restarts. It's bounded by
+/// `MAX_ATTEMPTS` and pruned by `MAX_AGE`. A batch request is recorded once for each message it carried.
+///
+pub struct HistoryService {
+    path: String,
+
+    // By (topic name, subscription name). Opened (or recovered) the first time they're used
+    logs: Mutex<HashMap<(String, String), Partition>>
+}
+
+impl HistoryService {
+    pub fn new(path: &str) -> Self {
+        HistoryService { path: path.to_owned(), logs: Mutex::new(HashMap::new()) }
+    }
+
+    ///
+    /// Saves the attempts. History is only there to help debugging, so failing to save it
+    /// doesn't stop deliveries - it's logged instead
+    ///
+    pub fn record(&self, topic_name: &str, subscription_name: &str, attempts: impl IntoIterator<Item = DeliveryAttempt>) {
+        let mut logs = self.logs.lock().unwrap();
+
+        let result = self.log(&mut logs, topic_name, subscription_name).and_then(|log| {
+            for attempt in attempts {
+                log.produce(&serde_json::to_string(&attempt).map_err(|err| anyhow::anyhow!(err))?)?;
+            }
+
+            // Pruning rewrites the log, so it waits until there's plenty to drop
+            match len(log) > 2 * MAX_ATTEMPTS {
+                true => prune(log, now()).map(|_| ()),
+                false => Ok(())
+            }
+        });
+
+        if let Err(err) = result {
+            println!("Can't record delivery attempts of {topic_name}/{subscription_name}: {err}");
+        }
+    }
+
+    ///
+    /// Attempts of the subscription, newest first, optionally only those of a partition or of
+    /// messages with the offset. At most `limit` are returned
+    ///
+    pub fn attempts(
+        &self,
+        topic_name: &str,
+        subscription_name: &str,
+        partition: Option<u32>,
+        offset: Option<Offset>,
+        limit: usize
+    ) -> Result<Vec<DeliveryAttempt>, HistoryServiceError> {
+
+        let mut logs = self.logs.lock().unwrap();
+        let history = self.log(&mut logs, topic_name, subscription_name).and_then(|log| read(log)).map_err(|err| {
+            println!("Can't read delivery attempts of {topic_name}/{subscription_name}: {err}");
+            HistoryServiceError::Internal
+        })?;
+
+        // Log can hold more than that until it's pruned
+        let oldest_kept = now().saturating_sub(MAX_AGE.as_secs());
+
+        Ok(history.into_iter()
+            .rev()
+            .take(MAX_ATTEMPTS)
+            .filter(|attempt| attempt.timestamp >= oldest_kept)
+            .filter(|attempt| partition.is_none_or(|partition| attempt.partition == partition))
+            .filter(|attempt| offset.is_none_or(|offset| attempt.offset == offset))
+            .take(limit)
+            .collect())
+    }
+
+    /// Removes the history of a removed subscription, so one created with the same name starts clean
+    pub fn forget(&self, topic_name: &str, subscription_name: &str) {
+        let mut logs = self.logs.lock().unwrap();
+        logs.remove(&(topic_name.to_owned(), subscription_name.to_owned()));
+
+        let path = format!("{}/{topic_name}/{subscription_name}", self.path);
+        if let Err(err) = std::fs::remove_dir_all(&path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                println!("Can't remove delivery history at {path}: {err}");
+            }
+        }
+    }
+
+    fn log<'a>(&self, logs: &'a mut HashMap<(String, String), Partition>, topic_name: &str, subscription_name: &str) -> Result<&'a mut Partition, PartitionError> {
+        let key = (topic_name.to_owned(), subscription_name.to_owned());
+
+        if !logs.contains_key(&key) {
+            let mut log = Partition::new(&format!("{}/{topic_name}/{subscription_name}", self.path))?;
+
+            // Broker could've been down for long
+            prune(&mut log, now())?;
+            logs.insert(key.clone(), log);
+        }
+
+        Ok(logs.get_mut(&key).unwrap())
+    }
+}
+
+/// Number of attempts in the log. Pruning only removes the oldest ones, so there are no gaps
+fn len(log: &Partition) -> usize {
+    (log.next_offset() - log.first_offset().unwrap_or(0)) as usize
+}
+
+/// Drops attempts past `MAX_ATTEMPTS` and older than `MAX_AGE`, except for the segment being written to
+fn prune(log: &mut Partition, now: u64) -> Result<usize, PartitionError> {
+    let oldest_kept = now.saturating_sub(MAX_AGE.as_secs());
+    let mut excess = len(log).saturating_sub(MAX_ATTEMPTS);
+
+    log.retain(|entry, _| {
+        if excess > 0 {
+            excess -= 1;
+            return false;
+        }
+
+        entry.timestamp >= oldest_kept
+    })
+}
+
+/// All attempts in the log, oldest first
+fn read(log: &Partition) -> Result<Vec<DeliveryAttempt>, PartitionError> {
+    let mut attempts = vec![];
+    let mut offset = match log.first_offset() {
+        Ok(offset) => offset,
+        Err(PartitionError::NoFirstOffset) => return Ok(attempts),
+        Err(err) => return Err(err)
+    };
+
+    while offset < log.next_offset() {
+        let collection = log.consume(offset)?;
+
+        while let Some(entry) = collection.next()? {
+            attempts.push(serde_json::from_str(entry.value).map_err(|err| anyhow::anyhow!(err))?);
+            offset = entry.offset + 1;
+        }
+    }
+
+    Ok(attempts)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[test]
+fn test_bounded_and_pruned() {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    let attempt = |offset, timestamp| DeliveryAttempt {
+        partition: 0, offset, attempt: 1, timestamp, status: Some(200), latency_ms: 1, error: None
+    };
+
+    let name: String = rand::thread_rng().sample_iter(&Alphanumeric).take(20).map(char::from).collect();
+    let path = format!("testfiles/history/{name}");
+
+    let history = HistoryService::new(&path);
+    let old = now() - MAX_AGE.as_secs() - 1;
+    history.record("orders", "billing", [attempt(0, old)]);
+    history.record("orders", "billing", (1..=MAX_ATTEMPTS as u64 + 1).map(|offset| attempt(offset, now())));
+
+    let attempts = history.attempts("orders", "billing", None, None, usize::MAX).unwrap();
+    assert_eq!(attempts.len(), MAX_ATTEMPTS);
+    assert_eq!(attempts[0].offset, MAX_ATTEMPTS as u64 + 1);
+    assert_eq!(attempts.last().unwrap().offset, 2);
+
+    assert_eq!(history.attempts("orders", "billing", Some(0), Some(5), 10).unwrap().len(), 1);
+    assert_eq!(history.attempts("orders", "billing", None, Some(5), 10).unwrap().len(), 1);
+    assert!(history.attempts("orders", "billing", Some(1), Some(5), 10).unwrap().is_empty());
+    assert_eq!(history.attempts("orders", "billing", None, None, 10).unwrap().len(), 10);
+
+    // Survives a restart, and gets pruned down on disk once there's enough to drop
+    drop(history);
+    let history = HistoryService::new(&path);
+    assert_eq!(history.attempts("orders", "billing", None, Some(5), 10).unwrap().len(), 1);
+
+    history.record("orders", "billing", (0..=MAX_ATTEMPTS as u64).map(|offset| attempt(offset, now())));
+    let log = Partition::new(&format!("{path}/orders/billing")).unwrap();
+    assert!(len(&log) < 2 * MAX_ATTEMPTS);
+
+    history.forget("orders", "billing");
+    assert!(history.attempts("orders", "billing", None, None, 10).unwrap().is_empty());
+}
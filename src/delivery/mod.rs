@@ -0,0 +1,14 @@
+pub mod batch_policy;
+pub mod circuit_breaker;
+pub mod credential_service;
+pub mod dead_letter_service;
+pub mod delivery_engine;
+pub mod history_service;
+pub mod rate_limiter;
+pub mod retry_policy;
+pub mod signing_service;
+pub mod verification;
+pub mod websub_service;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+// Upper bound of the request rate a subscription can ask for
+pub const MAX_REQUESTS_PER_SECOND: f64 = 10_000.0;
+
+// How long to wait for a free slot before asking again, when all allowed requests are in flight
+const IN_FLIGHT_WAIT: Duration = Duration::from_millis(10);
+
+///
+/// Limits how hard the delivery engine pushes to the subscription's endpoint. Requests are
+/// taken from a token bucket that holds `burst` tokens and refills at `requests_per_second`,
+/// and at most `max_in_flight` requests wait for a response at once - which only matters with
+/// `concurrency` above it. Missing fields don't limit anything. Retries count like any other
+/// request, waiting for a token doesn't use up attempts.
+///
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    pub requests_per_second: Option<f64>,
+
+    /// Defaults to a second worth of requests
+    pub burst: Option<u32>,
+    pub max_in_flight: Option<usize>
+}
+
+impl RateLimit {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(rate) = self.requests_per_second {
+            if !(rate > 0.0 && rate <= MAX_REQUESTS_PER_SECOND) {
+                return Err(format!("requests_per_second must be greater than 0 and at most {MAX_REQUESTS_PER_SECOND}"));
+            }
+        }
+
+        if self.burst == Some(0) {
+            return Err("burst must be greater than 0".to_owned());
+        }
+
+        if self.max_in_flight == Some(0) {
+            return Err("max_in_flight must be greater than 0".to_owned());
+        }
+
+        Ok(())
+    }
+
+    fn burst(&self, rate: f64) -> f64 {
+        self.burst.map_or(rate.ceil().max(1.0), f64::from)
+    }
+}
+
+///
+/// Token bucket and in-flight count of a single subscription. Lives in the delivery worker,
+/// shared by all its delivery threads
+///
+#[derive(Default)]
+pub struct RateLimiter {
+    // Bucket starts full
+    tokens: Option<f64>,
+    refilled_at: Option<Instant>,
+    in_flight: usize
+}
+
+impl RateLimiter {
+    ///
+    /// Reserves a request. If the limit doesn't let it through returns how long to wait before asking
+    /// again. Every reserved request has to be released once it's done
+    ///
+    pub fn try_acquire(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        if limit.max_in_flight.is_some_and(|max_in_flight| self.in_flight >= max_in_flight) {
+            return Err(IN_FLIGHT_WAIT);
+        }
+
+        if let Some(rate) = limit.requests_per_second {
+            let burst = limit.burst(rate);
+            let elapsed = self.refilled_at.map_or(Duration::ZERO, |refilled_at| now.saturating_duration_since(refilled_at));
+            let tokens = self.tokens.map_or(burst, |tokens| (tokens + elapsed.as_secs_f64() * rate).min(burst));
+
+            self.refilled_at = Some(now);
+            if tokens < 1.0 {
+                self.tokens = Some(tokens);
+                return Err(Duration::from_secs_f64((1.0 - tokens) / rate));
+            }
+
+            self.tokens = Some(tokens - 1.0);
+        }
+
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    pub fn release(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+    }
+}
+
+#[test]
+fn test_token_bucket() {
+    let limit = RateLimit { requests_per_second: Some(10.0), burst: Some(2), max_in_flight: None };
+    let mut limiter = RateLimiter::default();
+    let now = Instant::now();
+
+    // Full bucket lets a burst through, then one request every 100 ms
+    assert!(limiter.try_acquire(&limit, now).is_ok());
+    assert!(limiter.try_acquire(&limit, now).is_ok());
+    let wait = limiter.try_acquire(&limit, now).unwrap_err();
+    assert!(wait > Duration::from_millis(99) && wait <= Duration::from_millis(100));
+
+    assert!(limiter.try_acquire(&limit, now + Duration::from_millis(50)).is_err());
+    assert!(limiter.try_acquire(&limit, now + Duration::from_millis(100)).is_ok());
+}
+
+#[test]
+fn test_max_in_flight() {
+    let limit = RateLimit { max_in_flight: Some(1), ..Default::default() };
+    let mut limiter = RateLimiter::default();
+    let now = Instant::now();
+
+    assert!(limiter.try_acquire(&limit, now).is_ok());
+    assert_eq!(limiter.try_acquire(&limit, now), Err(IN_FLIGHT_WAIT));
+    limiter.release();
+    assert!(limiter.try_acquire(&limit, now).is_ok());
+}
+
+#[test]
+fn test_validate() {
+    assert!(RateLimit::default().validate().is_ok());
+    assert!(RateLimit { requests_per_second: Some(0.5), burst: Some(1), max_in_flight: Some(2) }.validate().is_ok());
+    assert!(RateLimit { requests_per_second: Some(0.0), ..Default::default() }.validate().is_err());
+    assert!(RateLimit { requests_per_second: Some(f64::NAN), ..Default::default() }.validate().is_err());
+    assert!(RateLimit { burst: Some(0), ..Default::default() }.validate().is_err());
+    assert!(RateLimit { max_in_flight: Some(0), ..Default::default() }.validate().is_err());
+}
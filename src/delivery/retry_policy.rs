@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+///
+/// How a subscription retries failed deliveries. Waits between attempts grow exponentially,
+/// from `initial_backoff_ms` up to `max_backoff_ms`. Once `max_attempts` are used up the message
+/// is still retried, but only every `max_backoff_ms`, so nothing is dropped.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+
+    /// Fraction of the backoff that's randomized, between 0 and 1. Keeps retries
+    /// of many subscriptions from hitting a recovering endpoint at the same moment
+    pub jitter: f64,
+
+    /// Timeout of a single delivery request
+    pub timeout_ms: u64
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 10,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 60_000,
+            jitter: 0.2,
+            timeout_ms: 10_000
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("max_attempts must be greater than 0".to_owned());
+        }
+
+        if self.initial_backoff_ms > self.max_backoff_ms {
+            return Err("initial_backoff_ms can't be greater than max_backoff_ms".to_owned());
+        }
+
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err("jitter must be between 0 and 1".to_owned());
+        }
+
+        if self.timeout_ms == 0 {
+            return Err("timeout_ms must be greater than 0".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// True if the given attempt was the last one allowed
+    pub fn is_exhausted(&self, attempt: u32) -> bool {
+        attempt >= self.max_attempts
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    ///
+    /// How long to wait after the given attempt (counting from 1) failed. Never more than `max_backoff_ms`,
+    /// even with jitter
+    ///
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = match self.is_exhausted(attempt) {
+            true => self.max_backoff_ms,
+            false => self.initial_backoff_ms
+                .saturating_mul(1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX))
+                .min(self.max_backoff_ms)
+        };
+
+        let jitter = match self.jitter > 0.0 {
+            true => rand::thread_rng().gen_range(-self.jitter..=self.jitter),
+            false => 0.0
+        };
+
+        Duration::from_millis(((backoff as f64 * (1.0 + jitter)) as u64).min(self.max_backoff_ms))
+    }
+}
+
+#[test]
+fn test_backoff_grows_exponentially() {
+    let policy = RetryPolicy { max_attempts: 5, initial_backoff_ms: 100, max_backoff_ms: 1000, jitter: 0.0, timeout_ms: 1 };
+
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(4), Duration::from_millis(800));
+    assert_eq!(policy.backoff(5), Duration::from_millis(1000)); // Exhausted
+    assert_eq!(policy.backoff(100), Duration::from_millis(1000));
+}
+
+#[test]
+fn test_backoff_jitter() {
+    let policy = RetryPolicy { max_attempts: 5, initial_backoff_ms: 1000, max_backoff_ms: 1000, jitter: 0.5, timeout_ms: 1 };
+
+    for _ in 0..100 {
+        let backoff = policy.backoff(1);
+        assert!(backoff >= Duration::from_millis(500) && backoff <= Duration::from_millis(1000));
+    }
+}
+
+#[test]
+fn test_validate() {
+    assert!(RetryPolicy::default().validate().is_ok());
+    assert!(RetryPolicy { max_attempts: 0, ..Default::default() }.validate().is_err());
+    assert!(RetryPolicy { jitter: 1.5, ..Default::default() }.validate().is_err());
+    assert!(RetryPolicy { initial_backoff_ms: 10, max_backoff_ms: 1, ..Default::default() }.validate().is_err());
+}
@@ -0,0 +1,320 @@
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use hmac::{Hmac, Mac};
+use kopperdb::kopper::KopperError;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use thiserror::Error;
+
+use crate::topic::topic_service::{TopicService, TopicServiceError, SubscriptionMode};
+use crate::storage::database::Database;
+
+/// Seconds since epoch when the request was signed, part of the signed content
+pub const TIMESTAMP_HEADER: &str = "X-Czkawka-Timestamp";
+
+/// `v1=<hex HMAC-SHA256>` for every valid secret, comma separated
+pub const SIGNATURE_HEADER: &str = "X-Czkawka-Signature";
+
+// Makes secrets easy to recognize, e.g. when they leak to a log
+const SECRET_PREFIX: &str = "whsec_";
+
+/// How long the previous secret keeps working after a rotation, unless asked otherwise
+pub const DEFAULT_OVERLAP_SECONDS: u64 = 24 * 60 * 60;
+
+// Longest time an old secret can stay valid after a rotation
+pub const MAX_OVERLAP_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Error)]
+pub enum SigningServiceError {
+    #[error(transparent)]
+    Topic(#[from] TopicServiceError),
+
+    #[error("Subscription {0} is a queue, only push deliveries are signed")]
+    NotPush(String),
+
+    #[error("Overlap can be at most {MAX_OVERLAP_SECONDS} seconds, got {0}")]
+    InvalidOverlap(u64),
+
+    #[error(transparent)]
+    Serde(#[from] serde_json::error::Error),
+
+    #[error("Internal database error, check logs")]
+    DatabaseError
+}
+
+impl SigningServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            SigningServiceError::Topic(error) => error.status(),
+            SigningServiceError::NotPush(_) | SigningServiceError::InvalidOverlap(_) => Status::BadRequest,
+            SigningServiceError::Serde(_) | SigningServiceError::DatabaseError => Status::InternalServerError,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SigningSecret {
+    secret: String,
+
+    /// Seconds since epoch
+    created_at: u64,
+
+    /// Set when the secret was rotated out, seconds since epoch
+    expires_at: Option<u64>
+}
+
+impl SigningSecret {
+    fn is_valid_at(&self, timestamp: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > timestamp)
+    }
+}
+
+///
+/// Secrets of one subscription, newest first. During a rotation overlap there's more than one.
+/// Stored in the db under the `secrets/<topic name>/<subscription name>` key, away from the
+/// subscription itself, so they never show up when subscriptions are listed
+///
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SigningSecrets(Vec<SigningSecret>);
+
+impl SigningSecrets {
+    pub fn key(topic_name: &str, subscription_name: &str) -> String {
+        format!("secrets/{topic_name}/{subscription_name}")
+    }
+
+    /// Where secrets wait until the subscriber confirms they should replace the current ones
+    pub fn staged_key(topic_name: &str, subscription_name: &str) -> String {
+        format!("staged-secrets/{topic_name}/{subscription_name}")
+    }
+
+    fn prune(&mut self, now: u64) {
+        self.0.retain(|secret| secret.is_valid_at(now));
+    }
+
+    ///
+    /// Value of the signature header for a request sent at `timestamp`, with one signature per
+    /// secret that is still valid. None if the subscription has no secrets
+    ///
+    pub fn sign(&self, timestamp: u64, body: &str) -> Option<String> {
+        let signatures: Vec<String> = self.0.iter()
+            .filter(|secret| secret.is_valid_at(timestamp))
+            .map(|secret| format!("v1={}", signature(&secret.secret, timestamp, body)))
+            .collect();
+
+        match signatures.is_empty() {
+            true => None,
+            false => Some(signatures.join(","))
+        }
+    }
+
+    /// Value of the WebSub `X-Hub-Signature` header - `sha256=<hex>` of the body alone, keyed with the newest secret
+    pub fn hub_signature(&self, timestamp: u64, body: &str) -> Option<String> {
+        let secret = self.0.iter().find(|secret| secret.is_valid_at(timestamp))?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.secret.as_bytes()).expect("HMAC takes keys of any size");
+        mac.update(body.as_bytes());
+        Some(format!("sha256={}", hex::encode(mac.finalize().into_bytes())))
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of `<timestamp>.<body>`, keyed with the whole secret
+pub fn signature(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Newly generated secret. This is the only time it's shown
+#[derive(Serialize)]
+pub struct RevealedSecret {
+    pub secret: String,
+
+    /// Until when the previous secret still works, seconds since epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_expires_at: Option<u64>
+}
+
+///
+/// Keeps the secrets push deliveries are signed with, so subscribers can tell our requests
+/// from forged ones. Receivers recompute the signature over the timestamp header and the body,
+/// and should reject requests with old timestamps to stop replays.
+///
+pub struct SigningService {
+    topic_service: Arc<TopicService>,
+    db: Database,
+
+    // Rotation is a read-modify-write of the secret list
+    metadata_lock: Mutex<()>
+}
+
+impl SigningService {
+    pub fn new(topic_service: Arc<TopicService>, db: Database) -> Self {
+        SigningService { topic_service, db, metadata_lock: Mutex::new(()) }
+    }
+
+    ///
+    /// Generates the first secret of a new push subscription. Called from `subscribe_topic_with`,
+    /// before the subscription is saved, so its very first delivery is signed
+    ///
+    pub fn create_secret(&self, topic_name: &str, subscription_name: &str) -> Result<RevealedSecret, SigningServiceError> {
+        let _guard = self.metadata_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let secret = generate_secret();
+        let secrets = SigningSecrets(vec![SigningSecret { secret: secret.clone(), created_at: now(), expires_at: None }]);
+        self.save(topic_name, subscription_name, &secrets)?;
+
+        Ok(RevealedSecret { secret, previous_expires_at: None })
+    }
+
+    ///
+    /// Sets a secret chosen by the subscriber, as WebSub's `hub.secret`, or drops all secrets
+    /// of the subscription. There's no overlap, the subscriber knows the secret already
+    ///
+    pub fn replace_secret(&self, topic_name: &str, subscription_name: &str, secret: Option<&str>) -> Result<(), SigningServiceError> {
+        let _guard = self.metadata_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let secrets = secret.into_iter()
+            .map(|secret| SigningSecret { secret: secret.to_owned(), created_at: now(), expires_at: None })
+            .collect();
+
+        self.save(topic_name, subscription_name, &SigningSecrets(secrets))
+    }
+
+    ///
+    /// Keeps a secret from a WebSub renewal, or no secret if the renewal has none, aside from the
+    /// current ones. Nothing changes for deliveries until `apply_staged_secret`
+    ///
+    pub fn stage_secret(&self, topic_name: &str, subscription_name: &str, secret: Option<&str>) -> Result<(), SigningServiceError> {
+        let _guard = self.metadata_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let secrets = secret.into_iter()
+            .map(|secret| SigningSecret { secret: secret.to_owned(), created_at: now(), expires_at: None })
+            .collect();
+
+        self.write(&SigningSecrets::staged_key(topic_name, subscription_name), &SigningSecrets(secrets))
+    }
+
+    /// Staged secret replaces the current ones. Current ones stay if nothing was staged
+    pub fn apply_staged_secret(&self, topic_name: &str, subscription_name: &str) -> Result<(), SigningServiceError> {
+        let _guard = self.metadata_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let staged_key = SigningSecrets::staged_key(topic_name, subscription_name);
+        let staged: SigningSecrets = match self.db.read(&staged_key) {
+            Ok(serialized) => serde_json::from_str(&serialized)?,
+            Err(KopperError::KeyDoesNotExist(_)) => return Ok(()),
+            Err(err) => {
+                println!("Kopper error when reading {err}");
+                return Err(SigningServiceError::DatabaseError);
+            }
+        };
+
+        self.save(topic_name, subscription_name, &staged)?;
+        self.discard(&staged_key)
+    }
+
+    pub fn discard_staged_secret(&self, topic_name: &str, subscription_name: &str) -> Result<(), SigningServiceError> {
+        let _guard = self.metadata_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.discard(&SigningSecrets::staged_key(topic_name, subscription_name))
+    }
+
+    ///
+    /// Replaces the subscription's secret with a new one. Deliveries are signed with both for
+    /// `overlap_seconds`, which gives the subscriber time to switch
+    ///
+    pub fn rotate_secret(&self, topic_name: &str, subscription_name: &str, overlap_seconds: u64) -> Result<RevealedSecret, SigningServiceError> {
+        if overlap_seconds > MAX_OVERLAP_SECONDS {
+            return Err(SigningServiceError::InvalidOverlap(overlap_seconds));
+        }
+
+        // Secrets written after the subscription was removed would outlive it
+        self.topic_service.with_subscription(topic_name, subscription_name, |subscription| {
+            if let SubscriptionMode::Queue { .. } = subscription.mode {
+                return Err(SigningServiceError::NotPush(subscription_name.to_owned()));
+            }
+
+            let _guard = self.metadata_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+            let now = now();
+            let mut secrets = self.get_secrets(topic_name, subscription_name)?;
+            secrets.prune(now);
+
+            // Older secrets keep an earlier expiry if they had one
+            let previous_expires_at = now + overlap_seconds;
+            for old in secrets.0.iter_mut() {
+                old.expires_at = Some(old.expires_at.map_or(previous_expires_at, |expires_at| expires_at.min(previous_expires_at)));
+            }
+
+            let secret = generate_secret();
+            secrets.0.insert(0, SigningSecret { secret: secret.clone(), created_at: now, expires_at: None });
+            secrets.prune(now);
+
+            let had_previous = secrets.0.len() > 1;
+            self.save(topic_name, subscription_name, &secrets)?;
+
+            Ok(RevealedSecret { secret, previous_expires_at: had_previous.then_some(previous_expires_at) })
+        })
+    }
+
+    pub fn get_secrets(&self, topic_name: &str, subscription_name: &str) -> Result<SigningSecrets, SigningServiceError> {
+        match self.db.read(&SigningSecrets::key(topic_name, subscription_name)) {
+            Ok(serialized) => Ok(serde_json::from_str(&serialized)?),
+
+            // Subscriptions created before signing was introduced, rotation gives them a secret
+            Err(KopperError::KeyDoesNotExist(_)) => Ok(SigningSecrets::default()),
+
+            Err(err) => {
+                println!("Kopper error when reading {err}");
+                Err(SigningServiceError::DatabaseError)
+            }
+        }
+    }
+
+    fn save(&self, topic_name: &str, subscription_name: &str, secrets: &SigningSecrets) -> Result<(), SigningServiceError> {
+        self.write(&SigningSecrets::key(topic_name, subscription_name), secrets)
+    }
+
+    fn write(&self, key: &str, secrets: &SigningSecrets) -> Result<(), SigningServiceError> {
+        if let Err(err) = self.db.write(key, &serde_json::to_string(secrets)?) {
+            println!("Kopper error when writing {err}");
+            return Err(SigningServiceError::DatabaseError);
+        }
+
+        Ok(())
+    }
+
+    fn discard(&self, key: &str) -> Result<(), SigningServiceError> {
+        if let Err(err) = self.db.delete(key) {
+            println!("Kopper error when deleting {key}: {err}");
+            return Err(SigningServiceError::DatabaseError);
+        }
+
+        Ok(())
+    }
+}
+
+fn generate_secret() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    format!("{SECRET_PREFIX}{}", hex::encode(bytes))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[test]
+fn test_signatures_during_rotation() {
+    let secret = |secret: &str, expires_at| SigningSecret { secret: secret.to_owned(), created_at: 0, expires_at };
+    let secrets = SigningSecrets(vec![secret("new", None), secret("old", Some(100))]);
+
+    let both = format!("v1={},v1={}", signature("new", 99, "{}"), signature("old", 99, "{}"));
+    assert_eq!(secrets.sign(99, "{}"), Some(both));
+    assert_eq!(secrets.sign(100, "{}"), Some(format!("v1={}", signature("new", 100, "{}"))));
+    assert_eq!(SigningSecrets::default().sign(100, "{}"), None);
+
+    // Timestamp is signed too, so an old signature can't be reused with a fresh timestamp
+    assert_ne!(signature("new", 0, "{}"), signature("new", 1, "{}"));
+}
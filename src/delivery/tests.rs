@@ -0,0 +1,1177 @@
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::Client;
+use rocket::serde::json::serde_json;
+use sha2::Sha256;
+
+use crate::delivery::signing_service::signature;
+use crate::delivery::websub_service::subscription_name;
+use crate::tests::{get_client, get_client_at, new_db_path, create_topic};
+
+#[derive(Clone, Debug)]
+pub struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: String
+}
+
+type Handler = dyn Fn(&ReceivedRequest) -> (u16, String) + Send + Sync;
+
+///
+/// Minimal http server standing in for a subscriber. Records every request
+/// and answers with whatever the handler returns. Unless started with
+/// `without_verification`, it consents to verification requests on its own
+/// and doesn't record them
+///
+pub struct TestReceiver {
+    pub url: String,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>
+}
+
+impl TestReceiver {
+    pub fn start() -> Self {
+        TestReceiver::with_handler(|_| (200, String::new()))
+    }
+
+    pub fn with_handler<F>(handler: F) -> Self 
+    where 
+        F: Fn(&ReceivedRequest) -> (u16, String) + Send + Sync + 'static
+    {
+        TestReceiver::listen(handler, true)
+    }
+
+    /// Verification requests go to the handler and are recorded like any other
+    pub fn without_verification<F>(handler: F) -> Self 
+    where 
+        F: Fn(&ReceivedRequest) -> (u16, String) + Send + Sync + 'static
+    {
+        TestReceiver::listen(handler, false)
+    }
+
+    fn listen<F>(handler: F, verify: bool) -> Self 
+    where 
+        F: Fn(&ReceivedRequest) -> (u16, String) + Send + Sync + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let shared_requests = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let requests = shared_requests.clone();
+                let handler = handler.clone();
+                std::thread::spawn(move || TestReceiver::serve(stream, requests, handler, verify));
+            }
+        });
+
+        TestReceiver { url, requests }
+    }
+
+    ///
+    /// Waits until at least `count` requests arrive, returns all of them
+    ///
+    pub fn wait_for(&self, count: usize) -> Vec<ReceivedRequest> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        while Instant::now() < deadline {
+            let requests = self.requests.lock().unwrap().clone();
+            if requests.len() >= count {
+                return requests;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        panic!("Expected {count} requests, got {}", self.requests.lock().unwrap().len());
+    }
+
+    fn serve(stream: TcpStream, requests: Arc<Mutex<Vec<ReceivedRequest>>>, handler: Arc<Handler>, verify: bool) {
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+
+        // Keep-alive: serve requests until the client closes the connection
+        loop {
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                return;
+            }
+
+            let mut parts = request_line.split_whitespace();
+            let method = parts.next().unwrap_or_default().to_owned();
+            let path = parts.next().unwrap_or_default().to_owned();
+
+            let mut headers = HashMap::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                match line.trim_end().split_once(": ") {
+                    Some((name, value)) => headers.insert(name.to_lowercase(), value.to_owned()),
+                    None => break,
+                };
+            }
+
+            let length = headers.get("content-length").map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let request = ReceivedRequest { method, path, headers, body: String::from_utf8(body).unwrap() };
+            let (status, response_body) = match challenge(&request) {
+                Some(challenge) if verify => (200, challenge.to_owned()),
+                _ => {
+                    let response = handler(&request);
+                    requests.lock().unwrap().push(request);
+                    response
+                }
+            };
+
+            let response = format!("HTTP/1.1 {status} Whatever\r\ncontent-length: {}\r\n\r\n{response_body}", response_body.len());
+            if writer.write_all(response.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Challenge of a verification request
+pub fn challenge(request: &ReceivedRequest) -> Option<&str> {
+    if request.method != "GET" {
+        return None;
+    }
+
+    let (_, query) = request.path.split_once('?')?;
+    query.split('&').find_map(|pair| pair.strip_prefix("czkawka.challenge="))
+}
+
+pub fn subscribe(client: &Client, topic: &str, name: &str, endpoint: &str) {
+    subscribe_with(client, topic, name, endpoint, "");
+}
+
+///
+/// Subscribes with extra settings, given as json fields, e.g. `"retry_policy": {...}`
+///
+pub fn subscribe_with(client: &Client, topic: &str, name: &str, endpoint: &str, settings: &str) {
+    let settings = match settings.is_empty() {
+        true => String::new(),
+        false => format!(", {settings}")
+    };
+
+    let response = client.post(format!("/api/topics/{topic}/subscriptions"))
+                                            .header(ContentType::JSON)
+                                            .body(format!(r#"{{"name": "{name}", "endpoint": "{endpoint}"{settings}}}"#))
+                                            .dispatch();
+
+    assert_eq!(response.status(), Status::Ok);
+}
+
+pub fn publish(client: &Client, topic: &str, body: &str) {
+    let response = client.post(format!("/publish/publish/{topic}"))
+                                            .header(ContentType::JSON)
+                                            .body(body)
+                                            .dispatch();
+
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn delivers_in_offset_order() {
+    let client = get_client();
+    let receiver = TestReceiver::start();
+
+    create_topic(&client, "orders");
+    subscribe(&client, "orders", "billing", &receiver.url);
+
+    for i in 0..5 {
+        publish(&client, "orders", &format!(r#"{{"key": "k", "value": {{"id": {i}}}}}"#));
+    }
+
+    let requests = receiver.wait_for(5);
+    for (i, request) in requests.iter().enumerate() {
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/hook");
+        assert_eq!(request.headers["content-type"], "application/json");
+        assert!(request.body.contains(&format!("\"offset\":{i}")));
+        assert!(request.body.contains(&format!("\"value\":{{\"id\":{i}}}")));
+    }
+}
+
+#[test]
+fn retries_until_success() {
+    let client = get_client();
+    let failures = Arc::new(Mutex::new(1));
+
+    // First request fails, the rest succeed
+    let receiver = TestReceiver::with_handler(move |_| {
+        let mut failures = failures.lock().unwrap();
+        match *failures {
+            0 => (200, String::new()),
+            _ => { *failures -= 1; (503, String::new()) }
+        }
+    });
+
+    create_topic(&client, "orders");
+    subscribe(&client, "orders", "billing", &receiver.url);
+    publish(&client, "orders", r#"{"value": "first"}"#);
+    publish(&client, "orders", r#"{"value": "second"}"#);
+
+    // Failed message is delivered again before moving on
+    let requests = receiver.wait_for(3);
+    assert!(requests[0].body.contains("first"));
+    assert!(requests[1].body.contains("first"));
+    assert!(requests[2].body.contains("second"));
+}
+
+#[test]
+fn rejects_invalid_subscription() {
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let subscribe = |body: &'static str| client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(body)
+                                            .dispatch()
+                                            .status();
+
+    assert_eq!(subscribe(r#"{"name": "billing", "endpoint": "not a url"}"#), Status::BadRequest);
+    assert_eq!(subscribe(r#"{"name": "billing", "endpoint": "ftp://localhost/x"}"#), Status::BadRequest);
+    assert_eq!(subscribe(r#"{"name": "../billing", "endpoint": "http://localhost/x"}"#), Status::BadRequest);
+    assert_eq!(subscribe(r#"{"name": "billing", "endpoint": "http://localhost/x"}"#), Status::Ok);
+    assert_eq!(subscribe(r#"{"name": "billing", "endpoint": "http://localhost/y"}"#), Status::Conflict);
+}
+
+#[test]
+fn retries_with_backoff_and_attempt_header() {
+    let client = get_client();
+    let failures = Arc::new(Mutex::new(2));
+
+    let receiver = TestReceiver::with_handler(move |_| {
+        let mut failures = failures.lock().unwrap();
+        match *failures {
+            0 => (200, String::new()),
+            _ => { *failures -= 1; (500, String::new()) }
+        }
+    });
+
+    create_topic(&client, "orders");
+    subscribe_with(&client, "orders", "billing", &receiver.url, 
+        r#""retry_policy": {"max_attempts": 5, "initial_backoff_ms": 10, "max_backoff_ms": 20, "jitter": 0}"#);
+
+    publish(&client, "orders", r#"{"value": "first"}"#);
+
+    let attempts: Vec<String> = receiver.wait_for(3).iter().map(|request| request.headers["x-czkawka-delivery-attempt"].clone()).collect();
+    assert_eq!(attempts, vec!["1", "2", "3"]);
+}
+
+#[test]
+fn rejects_invalid_retry_policy() {
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let response = client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"name": "billing", "endpoint": "http://localhost/x", "retry_policy": {"jitter": 2}}"#)
+                                            .dispatch();
+
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+fn dead_letters_and_redrives() {
+    let client = get_client();
+    let healthy = Arc::new(Mutex::new(false));
+
+    let shared_healthy = healthy.clone();
+    let billing = TestReceiver::with_handler(move |_| match *shared_healthy.lock().unwrap() {
+        true => (200, String::new()),
+        false => (500, String::new())
+    });
+    let shipping = TestReceiver::start();
+    let inspector = TestReceiver::start();
+
+    create_topic(&client, "orders");
+    create_topic(&client, "orders.dlq");
+    subscribe_with(&client, "orders", "billing", &billing.url, r#"
+        "retry_policy": {"max_attempts": 2, "initial_backoff_ms": 10, "max_backoff_ms": 10, "jitter": 0},
+        "dead_letter_topic": "orders.dlq""#);
+    subscribe(&client, "orders", "shipping", &shipping.url);
+    subscribe(&client, "orders.dlq", "inspector", &inspector.url);
+
+    publish(&client, "orders", r#"{"key": "k", "headers": {"trace": "abc"}, "value": "first"}"#);
+
+    // Retries run out, message lands in the dead letter topic with its history
+    let dead_letter = &inspector.wait_for(1)[0].body;
+    assert!(dead_letter.contains(r#""key":"k""#));
+    assert!(dead_letter.contains(r#""trace":"abc""#));
+    assert!(dead_letter.contains(r#""x-dlq-original-topic":"orders""#));
+    assert!(dead_letter.contains(r#""x-dlq-original-partition":"0""#));
+    assert!(dead_letter.contains(r#""x-dlq-original-offset":"0""#));
+    assert!(dead_letter.contains(r#""x-dlq-subscription":"billing""#));
+    assert!(dead_letter.contains(r#""x-dlq-attempts":"2""#));
+    assert!(dead_letter.contains(r#""x-dlq-last-error":"Endpoint responded with 500"#));
+
+    *healthy.lock().unwrap() = true;
+
+    let redrive = |client: &Client| client.post("/api/topics/orders/subscriptions/billing/redrive").dispatch().into_string().unwrap();
+    assert_eq!(redrive(&client), r#"{"redriven":1}"#);
+
+    // Re-driven message only goes to the subscription that failed it
+    let requests = billing.wait_for(3);
+    assert!(requests[2].body.contains(r#""x-czkawka-redrive-subscription":"billing""#));
+    assert!(!requests[2].body.contains("x-dlq-"));
+
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(shipping.wait_for(1).len(), 1);
+
+    // Nothing new to re-drive
+    assert_eq!(redrive(&client), r#"{"redriven":0}"#);
+}
+
+#[test]
+fn redrive_continues_after_failure() {
+    let client = get_client();
+    let healthy = Arc::new(AtomicBool::new(false));
+
+    let shared_healthy = healthy.clone();
+    let billing = TestReceiver::with_handler(move |_| match shared_healthy.load(Ordering::SeqCst) {
+        true => (200, String::new()),
+        false => (500, String::new())
+    });
+    let inspector = TestReceiver::start();
+
+    create_topic(&client, "orders");
+    create_topic(&client, "orders.dlq");
+    subscribe_with(&client, "orders", "billing", &billing.url, r#"
+        "retry_policy": {"max_attempts": 1, "initial_backoff_ms": 10, "max_backoff_ms": 10, "jitter": 0},
+        "dead_letter_topic": "orders.dlq""#);
+    subscribe(&client, "orders.dlq", "inspector", &inspector.url);
+
+    publish(&client, "orders", r#"{"value": "first"}"#);
+    publish(&client, "orders", r#"{"value": 2}"#);
+    inspector.wait_for(2);
+    healthy.store(true, Ordering::SeqCst);
+
+    let post = |url: &str, body: &str| client.post(url.to_owned()).header(ContentType::JSON).body(body).dispatch();
+    let redrive = || client.post("/api/topics/orders/subscriptions/billing/redrive").dispatch();
+
+    // Second dead letter doesn't match the schema registered since, the first one is re-driven anyway
+    post("/api/topics/orders/schemas", r#"{"type": "string"}"#);
+    assert_eq!(redrive().status(), Status::UnprocessableEntity);
+
+    let config = client.get("/api/topics/orders/config").dispatch().into_string().unwrap();
+    client.put("/api/topics/orders/config").header(ContentType::JSON).body(config.replace(r#""schema_compatibility":"backward""#, r#""schema_compatibility":"none""#)).dispatch();
+    post("/api/topics/orders/schemas", r#"{"type": ["string", "number"]}"#);
+
+    // Only the rest is re-driven
+    assert_eq!(redrive().into_string().unwrap(), r#"{"redriven":1}"#);
+
+    // Pull consumers already read the originals, copies are skipped
+    let fetched: serde_json::Value = serde_json::from_str(&client.get("/api/topics/orders/partitions/0/messages").dispatch().into_string().unwrap()).unwrap();
+    assert_eq!(fetched["messages"].as_array().unwrap().len(), 2);
+    assert_eq!(fetched["next_offset"], 4);
+}
+
+#[test]
+fn rejects_invalid_dead_letter_topic() {
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let subscribe = |dead_letter_topic: &str| client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(format!(r#"{{"name": "billing", "endpoint": "http://localhost/x", "dead_letter_topic": "{dead_letter_topic}"}}"#))
+                                            .dispatch()
+                                            .status();
+
+    assert_eq!(subscribe("missing"), Status::BadRequest);
+    assert_eq!(subscribe("orders"), Status::BadRequest);
+
+    let response = client.post("/api/topics/orders/subscriptions/billing/redrive").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+fn committed_offset(client: &Client, topic: &str, subscription: &str) -> String {
+    client.get(format!("/publish/offset/{topic}/{subscription}")).dispatch().into_string().unwrap()
+}
+
+#[test]
+fn resumes_from_committed_offset_after_restart() {
+    let db_path = new_db_path();
+    let receiver = TestReceiver::start();
+
+    let client = get_client_at(&db_path);
+    create_topic(&client, "orders");
+    subscribe(&client, "orders", "billing", &receiver.url);
+    publish(&client, "orders", r#"{"value": "first"}"#);
+    publish(&client, "orders", r#"{"value": "second"}"#);
+    receiver.wait_for(2);
+
+    let expected = r#"{"topic":"orders","subscription":"billing","partitions":[{"partition":0,"committed_offset":2,"high_watermark":2,"lag":0}],"lag":0}"#;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while committed_offset(&client, "orders", "billing") != expected {
+        assert!(Instant::now() < deadline, "Offset not committed: {}", committed_offset(&client, "orders", "billing"));
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    drop(client);
+
+    // Restarted broker doesn't deliver the same messages again
+    let client = get_client_at(&db_path);
+    publish(&client, "orders", r#"{"value": "third"}"#);
+
+    let requests = receiver.wait_for(3);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(receiver.wait_for(3).len(), 3);
+    assert!(requests[2].body.contains("third"));
+}
+
+#[test]
+fn reports_lag() {
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    // Endpoint never answers successfully, nothing gets committed
+    let receiver = TestReceiver::with_handler(|_| (500, String::new()));
+    subscribe(&client, "orders", "billing", &receiver.url);
+    publish(&client, "orders", r#"{"value": "first"}"#);
+    publish(&client, "orders", r#"{"value": "second"}"#);
+
+    assert_eq!(committed_offset(&client, "orders", "billing"), 
+        r#"{"topic":"orders","subscription":"billing","partitions":[{"partition":0,"committed_offset":0,"high_watermark":2,"lag":2}],"lag":2}"#);
+
+    let response = client.get("/publish/offset/orders/missing").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn resets_subscription() {
+    let client = get_client();
+    let receiver = TestReceiver::start();
+
+    create_topic(&client, "orders");
+    subscribe(&client, "orders", "billing", &receiver.url);
+    for i in 0..3 {
+        publish(&client, "orders", &format!(r#"{{"value": {i}}}"#));
+    }
+    receiver.wait_for(3);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !committed_offset(&client, "orders", "billing").contains(r#""committed_offset":3"#) {
+        assert!(Instant::now() < deadline, "Offset not committed");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let reset = |body: &str| client.post("/api/topics/orders/subscriptions/billing/reset")
+                                            .header(ContentType::JSON)
+                                            .body(body)
+                                            .dispatch()
+                                            .into_string()
+                                            .unwrap();
+
+    // Dry run only shows where the subscription would end up
+    assert_eq!(reset(r#"{"to": "offset", "offset": 1, "dry_run": true}"#), 
+        r#"{"dry_run":true,"partitions":[{"partition":0,"previous_offset":3,"new_offset":1}]}"#);
+    assert_eq!(reset(r#"{"to": "offset", "offset": 10, "dry_run": true}"#), 
+        r#"{"dry_run":true,"partitions":[{"partition":0,"previous_offset":3,"new_offset":3}]}"#);
+    assert_eq!(reset(r#"{"to": "timestamp", "timestamp": 0, "dry_run": true}"#), 
+        r#"{"dry_run":true,"partitions":[{"partition":0,"previous_offset":3,"new_offset":0}]}"#);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(receiver.wait_for(3).len(), 3);
+
+    // Rewound subscription gets the messages again
+    assert_eq!(reset(r#"{"to": "offset", "offset": 1}"#), 
+        r#"{"dry_run":false,"partitions":[{"partition":0,"previous_offset":3,"new_offset":1}]}"#);
+
+    let requests = receiver.wait_for(5);
+    assert!(requests[3].body.contains(r#""offset":1"#));
+    assert!(requests[4].body.contains(r#""offset":2"#));
+}
+
+#[test]
+fn filters_messages() {
+    let client = get_client();
+    let receiver = TestReceiver::start();
+
+    create_topic(&client, "orders");
+    subscribe_with(&client, "orders", "billing", &receiver.url, r#""filter": "type == \"order.created\" && total > 100""#);
+
+    publish(&client, "orders", r#"{"value": {"type": "order.created", "total": 50}}"#);
+    publish(&client, "orders", r#"{"value": {"type": "order.deleted", "total": 200}}"#);
+    publish(&client, "orders", r#"{"value": {"type": "order.created", "total": 150}}"#);
+    publish(&client, "orders", r#"{"headers": {"type": "order.created"}, "value": {"total": 300}}"#);
+
+    let requests = receiver.wait_for(2);
+    assert!(requests[0].body.contains(r#""offset":2"#));
+    assert!(requests[1].body.contains(r#""offset":3"#));
+
+    // Skipped messages are committed too
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !committed_offset(&client, "orders", "billing").contains(r#""lag":0}"#) {
+        assert!(Instant::now() < deadline, "Offset not committed");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(receiver.wait_for(2).len(), 2);
+}
+
+#[test]
+fn rejects_invalid_filter() {
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let response = client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"name": "billing", "endpoint": "http://localhost/x", "filter": "type =="}"#)
+                                            .dispatch();
+
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response.into_string().unwrap().contains("filter"));
+}
+
+#[test]
+fn signs_deliveries_and_rotates_secret() {
+    let client = get_client();
+    let receiver = TestReceiver::start();
+
+    create_topic(&client, "orders");
+
+    let post = |uri: &str, body: String| client.post(uri.to_owned())
+                                            .header(ContentType::JSON)
+                                            .body(body)
+                                            .dispatch()
+                                            .into_json::<serde_json::Value>()
+                                            .unwrap();
+
+    let created = post("/api/topics/orders/subscriptions", format!(r#"{{"name": "billing", "endpoint": "{}"}}"#, receiver.url));
+    let secret = created["signing_secret"].as_str().unwrap().to_owned();
+    assert!(secret.starts_with("whsec_"));
+
+    // Secret is only shown on creation
+    let subscription = client.get("/api/topics/orders/subscriptions/billing").dispatch().into_string().unwrap();
+    assert!(!subscription.contains(&secret));
+
+    let expected_signature = |request: &ReceivedRequest, secret: &str| {
+        let timestamp: u64 = request.headers["x-czkawka-timestamp"].parse().unwrap();
+        format!("v1={}", signature(secret, timestamp, &request.body))
+    };
+
+    publish(&client, "orders", r#"{"value": "first"}"#);
+    let requests = receiver.wait_for(1);
+    assert_eq!(requests[0].headers["x-czkawka-signature"], expected_signature(&requests[0], &secret));
+
+    // Both secrets sign deliveries during the overlap
+    let rotated = post("/api/topics/orders/subscriptions/billing/secret/rotate", r#"{"overlap_seconds": 3600}"#.to_owned());
+    let new_secret = rotated["secret"].as_str().unwrap().to_owned();
+    assert!(rotated["previous_expires_at"].is_u64());
+
+    publish(&client, "orders", r#"{"value": "second"}"#);
+    let requests = receiver.wait_for(2);
+    assert_eq!(requests[1].headers["x-czkawka-signature"],
+        format!("{},{}", expected_signature(&requests[1], &new_secret), expected_signature(&requests[1], &secret)));
+
+    // Without overlap the old secret stops working right away
+    let rotated = post("/api/topics/orders/subscriptions/billing/secret/rotate", r#"{"overlap_seconds": 0}"#.to_owned());
+    let newest_secret = rotated["secret"].as_str().unwrap().to_owned();
+
+    publish(&client, "orders", r#"{"value": "third"}"#);
+    let requests = receiver.wait_for(3);
+    assert_eq!(requests[2].headers["x-czkawka-signature"], expected_signature(&requests[2], &newest_secret));
+
+    let response = client.post("/api/topics/orders/subscriptions/billing/secret/rotate")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"overlap_seconds": 99999999}"#)
+                                            .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+fn delivers_in_batches() {
+    let client = get_client();
+    let receiver = TestReceiver::start();
+
+    create_topic(&client, "orders");
+    for i in 0..4 {
+        publish(&client, "orders", &format!(r#"{{"value": {i}}}"#));
+    }
+    subscribe_with(&client, "orders", "billing", &receiver.url, r#""batch": {"max_messages": 3, "max_wait_ms": 300}"#);
+
+    // Full batch goes right away, the rest once it waited long enough
+    let requests = receiver.wait_for(2);
+    let batch: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+    let offsets: Vec<u64> = batch.as_array().unwrap().iter().map(|message| message["offset"].as_u64().unwrap()).collect();
+    assert_eq!(offsets, vec![0, 1, 2]);
+    assert!(requests[1].body.starts_with('['));
+    assert!(requests[1].body.contains(r#""offset":3"#));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !committed_offset(&client, "orders", "billing").contains(r#""committed_offset":4"#) {
+        assert!(Instant::now() < deadline, "Offset not committed");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let response = client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"name": "other", "endpoint": "http://localhost/x", "batch": {"max_messages": 0}}"#)
+                                            .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+fn retries_failed_part_of_batch() {
+    let client = get_client();
+    let calls = Arc::new(Mutex::new(0));
+    let counter = calls.clone();
+
+    // First response rejects a single message of the batch
+    let receiver = TestReceiver::with_handler(move |_| {
+        let mut calls = counter.lock().unwrap();
+        *calls += 1;
+        match *calls {
+            1 => (200, r#"{"failed": [1]}"#.to_owned()),
+            _ => (200, String::new())
+        }
+    });
+
+    create_topic(&client, "orders");
+    for i in 0..3 {
+        publish(&client, "orders", &format!(r#"{{"value": {i}}}"#));
+    }
+    subscribe_with(&client, "orders", "billing", &receiver.url,
+        r#""batch": {"max_messages": 3}, "retry_policy": {"initial_backoff_ms": 10, "jitter": 0}"#);
+
+    let requests = receiver.wait_for(2);
+    assert!(requests[0].body.contains(r#""offset":0"#) && requests[0].body.contains(r#""offset":2"#));
+    assert!(requests[1].body.contains(r#""offset":1"#));
+    assert!(!requests[1].body.contains(r#""offset":0"#) && !requests[1].body.contains(r#""offset":2"#));
+    assert_eq!(requests[1].headers["x-czkawka-delivery-attempt"], "2");
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !committed_offset(&client, "orders", "billing").contains(r#""committed_offset":3"#) {
+        assert!(Instant::now() < deadline, "Offset not committed");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn pauses_edits_and_removes_subscription() {
+    let client = get_client();
+    let receiver = TestReceiver::start();
+    let new_receiver = TestReceiver::start();
+
+    create_topic(&client, "orders");
+    subscribe(&client, "orders", "billing", &receiver.url);
+    publish(&client, "orders", r#"{"value": "first"}"#);
+    receiver.wait_for(1);
+
+    let post = |uri: &str| client.post(uri.to_owned()).dispatch().into_string().unwrap();
+    assert!(post("/api/topics/orders/subscriptions/billing/pause").contains(r#""paused":true"#));
+
+    // Paused subscription keeps its offset and gets nothing
+    publish(&client, "orders", r#"{"value": "second"}"#);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(receiver.wait_for(1).len(), 1);
+    assert!(committed_offset(&client, "orders", "billing").contains(r#""committed_offset":1"#));
+
+    let response = client.patch("/api/topics/orders/subscriptions/billing")
+                                            .header(ContentType::JSON)
+                                            .body(format!(r#"{{"endpoint": "{}"}}"#, new_receiver.url))
+                                            .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = client.patch("/api/topics/orders/subscriptions/billing")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"endpoint": "ftp://nope"}"#)
+                                            .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // Resumed where it stopped, at the new endpoint
+    assert!(post("/api/topics/orders/subscriptions/billing/resume").contains(r#""paused":false"#));
+    let requests = new_receiver.wait_for(1);
+    assert!(requests[0].body.contains("second"));
+
+    let response = client.delete("/api/topics/orders/subscriptions/billing").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(client.get("/api/topics/orders/subscriptions/billing").dispatch().status(), Status::NotFound);
+    assert_eq!(client.delete("/api/topics/orders/subscriptions/billing").dispatch().status(), Status::NotFound);
+
+    publish(&client, "orders", r#"{"value": "third"}"#);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(new_receiver.wait_for(1).len(), 1);
+}
+
+#[test]
+fn delivers_keys_in_parallel_and_in_order() {
+    let client = get_client();
+    let in_flight = Arc::new(Mutex::new((0, 0)));
+    let counter = in_flight.clone();
+
+    // Tracks how many requests are handled at once, and the most seen
+    let receiver = TestReceiver::with_handler(move |_| {
+        {
+            let mut in_flight = counter.lock().unwrap();
+            in_flight.0 += 1;
+            in_flight.1 = in_flight.1.max(in_flight.0);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        counter.lock().unwrap().0 -= 1;
+        (200, String::new())
+    });
+
+    create_topic(&client, "orders");
+    for i in 0..6 {
+        let key = ["a", "b", "c"][i % 3];
+        publish(&client, "orders", &format!(r#"{{"key": "{key}", "value": {i}}}"#));
+    }
+    subscribe_with(&client, "orders", "billing", &receiver.url, r#""concurrency": 3"#);
+
+    let requests = receiver.wait_for(6);
+    assert!(in_flight.lock().unwrap().1 > 1, "Messages weren't delivered in parallel");
+
+    // Requests are recorded once handled, so per key they're in the order they were delivered
+    for key in ["a", "b", "c"] {
+        let offsets: Vec<u64> = requests.iter()
+            .map(|request| serde_json::from_str::<serde_json::Value>(&request.body).unwrap())
+            .filter(|message| message["key"] == key)
+            .map(|message| message["offset"].as_u64().unwrap())
+            .collect();
+
+        assert_eq!(offsets.len(), 2);
+        assert!(offsets[0] < offsets[1], "Key {key} delivered out of order: {offsets:?}");
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !committed_offset(&client, "orders", "billing").contains(r#""committed_offset":6"#) {
+        assert!(Instant::now() < deadline, "Offset not committed");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let response = client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"name": "other", "endpoint": "http://localhost/x", "concurrency": 2, "batch": {}}"#)
+                                            .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+fn opens_circuit_for_failing_endpoint() {
+    let client = get_client();
+    let arrivals = Arc::new(Mutex::new(vec![]));
+    let recorded = arrivals.clone();
+
+    // Endpoint is down for the first three requests
+    let receiver = TestReceiver::with_handler(move |_| {
+        let mut arrivals = recorded.lock().unwrap();
+        arrivals.push(Instant::now());
+        match arrivals.len() {
+            1..=3 => (503, String::new()),
+            _ => (200, String::new())
+        }
+    });
+
+    create_topic(&client, "orders");
+    subscribe_with(&client, "orders", "billing", &receiver.url, r#"
+        "retry_policy": {"max_attempts": 10, "initial_backoff_ms": 10, "max_backoff_ms": 10, "jitter": 0},
+        "circuit_breaker": {"failure_threshold": 2, "open_ms": 500}"#);
+
+    publish(&client, "orders", r#"{"value": "first"}"#);
+    receiver.wait_for(2);
+    std::thread::sleep(Duration::from_millis(100));
+
+    let subscription = || client.get("/api/topics/orders/subscriptions/billing").dispatch().into_string().unwrap();
+    let health = subscription();
+    assert!(health.contains(r#""circuit":"open""#));
+    assert!(health.contains(r#""last_error":"Endpoint responded with 503 Service Unavailable""#));
+    assert_eq!(receiver.wait_for(2).len(), 2);
+
+    // Single trial after each open period, the failed one opens the circuit again
+    let requests = receiver.wait_for(4);
+    let arrivals = arrivals.lock().unwrap().clone();
+    assert!(arrivals[2] - arrivals[1] >= Duration::from_millis(500));
+    assert!(arrivals[3] - arrivals[2] >= Duration::from_millis(500));
+
+    // Waiting for the circuit doesn't use up attempts
+    let attempts: Vec<&str> = requests.iter().map(|request| request.headers["x-czkawka-delivery-attempt"].as_str()).collect();
+    assert_eq!(attempts, vec!["1", "2", "3", "4"]);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !subscription().contains(r#""circuit":"closed""#) {
+        assert!(Instant::now() < deadline, "Circuit didn't close");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let health = subscription();
+    assert!(health.contains(r#""consecutive_failures":0"#));
+    assert!(!health.contains(r#""last_success_at":null"#));
+}
+
+#[test]
+fn rejects_invalid_circuit_breaker() {
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let response = client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"name": "billing", "endpoint": "http://localhost/x", "circuit_breaker": {"failure_threshold": 0}}"#)
+                                            .dispatch();
+
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+fn verifies_endpoint_before_delivering() {
+    let client = get_client();
+    let consents = Arc::new(AtomicBool::new(false));
+    let consent = consents.clone();
+
+    // Answers verification with the challenge only once it consents
+    let receiver = TestReceiver::without_verification(move |request| match challenge(request) {
+        Some(challenge) if consent.load(Ordering::SeqCst) => (200, challenge.to_owned()),
+        Some(_) => (200, "sure".to_owned()),
+        None => (200, String::new())
+    });
+
+    create_topic(&client, "orders");
+    subscribe_with(&client, "orders", "billing", &receiver.url,
+        r#""retry_policy": {"max_attempts": 2, "initial_backoff_ms": 10, "max_backoff_ms": 10, "jitter": 0}"#);
+    publish(&client, "orders", r#"{"value": "first"}"#);
+
+    let requests = receiver.wait_for(2);
+    assert!(requests[0].path.contains("czkawka.mode=subscribe&czkawka.topic=orders&czkawka.subscription=billing"));
+    std::thread::sleep(Duration::from_millis(300));
+
+    // Nothing is delivered to an endpoint that didn't consent
+    let subscription = client.get("/api/topics/orders/subscriptions/billing").dispatch().into_string().unwrap();
+    assert!(subscription.contains(r#""verification":"failed""#));
+    assert!(subscription.contains("didn't echo the verification challenge"));
+    assert!(receiver.wait_for(2).iter().all(|request| request.method == "GET"));
+
+    consents.store(true, Ordering::SeqCst);
+    let response = client.post("/api/topics/orders/subscriptions/billing/verify").dispatch();
+    assert!(response.into_string().unwrap().contains(r#""verification":"pending""#));
+
+    let requests = receiver.wait_for(4);
+    assert_eq!(requests[2].method, "GET");
+    assert!(requests[3].body.contains("first"));
+    assert!(client.get("/api/topics/orders/subscriptions/billing").dispatch().into_string().unwrap().contains(r#""verification":"verified""#));
+
+    // New endpoint has to consent too
+    let response = client.patch("/api/topics/orders/subscriptions/billing")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"endpoint": "http://localhost:1/hook"}"#)
+                                            .dispatch();
+    assert!(response.into_string().unwrap().contains(r#""verification":"pending""#));
+
+    // Queues have no endpoint to verify
+    let response = client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"name": "workers", "mode": {"type": "queue"}}"#)
+                                            .dispatch();
+    assert!(response.into_string().unwrap().contains(r#""verification":"verified""#));
+    assert_eq!(client.post("/api/topics/orders/subscriptions/workers/verify").dispatch().status(), Status::BadRequest);
+}
+
+/// Value of a query parameter, as sent by the hub
+fn query_param<'a>(request: &'a ReceivedRequest, name: &str) -> Option<&'a str> {
+    let (_, query) = request.path.split_once('?')?;
+    query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn hub_request(client: &Client, body: String) -> Status {
+    client.post("/websub")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+            .status()
+}
+
+fn websub_receiver() -> TestReceiver {
+    TestReceiver::without_verification(|request| match query_param(request, "hub.challenge") {
+        Some(challenge) => (200, challenge.to_owned()),
+        None => (200, String::new())
+    })
+}
+
+#[test]
+fn acts_as_websub_hub() {
+    let client = get_client();
+    let receiver = websub_receiver();
+    let callback = receiver.url.replace(':', "%3A").replace('/', "%2F");
+    let topic_url = "http%3A%2F%2Fbroker%2Fwebsub%2Forders";
+
+    create_topic(&client, "orders");
+    publish(&client, "orders", r#"{"value": {"id": 0}}"#);
+
+    let subscribe = format!("hub.mode=subscribe&hub.topic={topic_url}&hub.callback={callback}&hub.lease_seconds=60&hub.secret=s3cret");
+    assert_eq!(hub_request(&client, subscribe), Status::Accepted);
+
+    // Only content published after subscribing is distributed
+    publish(&client, "orders", r#"{"value": {"id": 1}}"#);
+    let requests = receiver.wait_for(2);
+    assert_eq!(query_param(&requests[0], "hub.mode"), Some("subscribe"));
+    assert_eq!(query_param(&requests[0], "hub.topic"), Some(topic_url));
+    assert_eq!(query_param(&requests[0], "hub.lease_seconds"), Some("60"));
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+    mac.update(br#"{"id":1}"#);
+    assert_eq!(requests[1].body, r#"{"id":1}"#);
+    assert_eq!(requests[1].headers["x-hub-signature"], format!("sha256={}", hex::encode(mac.finalize().into_bytes())));
+    assert!(requests[1].headers["link"].contains(r#"<http://broker/websub/orders>; rel="self""#));
+    assert!(requests[1].headers["link"].contains(r#"rel="hub""#));
+
+    let subscription = format!("/api/topics/orders/subscriptions/{}", subscription_name(&receiver.url));
+    assert!(client.get(&subscription).dispatch().into_string().unwrap().contains(r#""lease_seconds":60"#));
+
+    let unsubscribe = format!("hub.mode=unsubscribe&hub.topic={topic_url}&hub.callback={callback}");
+    assert_eq!(hub_request(&client, unsubscribe), Status::Accepted);
+    assert_eq!(query_param(&receiver.wait_for(3)[2], "hub.mode"), Some("unsubscribe"));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while client.get(&subscription).dispatch().status() != Status::NotFound {
+        assert!(Instant::now() < deadline, "Subscription wasn't removed");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let unsubscribe = format!("hub.mode=unsubscribe&hub.topic={topic_url}&hub.callback={callback}");
+    assert_eq!(hub_request(&client, unsubscribe), Status::NotFound);
+    assert_eq!(hub_request(&client, format!("hub.mode=subscribe&hub.topic=missing&hub.callback={callback}")), Status::NotFound);
+}
+
+#[test]
+fn renews_websub_lease_once_confirmed() {
+    let client = get_client();
+
+    // Callback confirms everything but the forged renewal
+    let receiver = TestReceiver::without_verification(|request| match query_param(request, "hub.challenge") {
+        Some(_) if query_param(request, "hub.lease_seconds") == Some("120") => (404, String::new()),
+        Some(challenge) => (200, challenge.to_owned()),
+        None => (200, String::new())
+    });
+    let callback = receiver.url.replace(':', "%3A").replace('/', "%2F");
+    let subscription = format!("/api/topics/orders/subscriptions/{}", subscription_name(&receiver.url));
+
+    let hub_signature = |secret: &str, body: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body.as_bytes());
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    };
+
+    create_topic(&client, "orders");
+    assert_eq!(hub_request(&client, format!("hub.mode=subscribe&hub.topic=orders&hub.callback={callback}&hub.lease_seconds=60&hub.secret=old")), Status::Accepted);
+    receiver.wait_for(1);
+
+    // Renewal nobody confirmed changes nothing, deliveries are still signed with the old secret
+    assert_eq!(hub_request(&client, format!("hub.mode=subscribe&hub.topic=orders&hub.callback={callback}&hub.lease_seconds=120&hub.secret=forged")), Status::Accepted);
+    receiver.wait_for(2);
+    publish(&client, "orders", r#"{"value": {"id": 1}}"#);
+
+    let requests = receiver.wait_for(3);
+    assert_eq!(requests[2].headers["x-hub-signature"], hub_signature("old", r#"{"id":1}"#));
+    let saved = client.get(&subscription).dispatch().into_string().unwrap();
+    assert!(saved.contains(r#""lease_seconds":60"#) && !saved.contains("renewal"));
+
+    // Confirmed renewal brings its lease and secret
+    assert_eq!(hub_request(&client, format!("hub.mode=subscribe&hub.topic=orders&hub.callback={callback}&hub.lease_seconds=90&hub.secret=new")), Status::Accepted);
+    receiver.wait_for(4);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !client.get(&subscription).dispatch().into_string().unwrap().contains(r#""lease_seconds":90"#) {
+        assert!(Instant::now() < deadline, "Renewal wasn't applied");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    publish(&client, "orders", r#"{"value": {"id": 2}}"#);
+    assert_eq!(receiver.wait_for(5)[4].headers["x-hub-signature"], hub_signature("new", r#"{"id":2}"#));
+}
+
+#[test]
+fn expires_websub_leases() {
+    let client = get_client();
+    let receiver = websub_receiver();
+    let callback = receiver.url.replace(':', "%3A").replace('/', "%2F");
+
+    create_topic(&client, "orders");
+    assert_eq!(hub_request(&client, format!("hub.mode=subscribe&hub.topic=orders&hub.callback={callback}&hub.lease_seconds=1")), Status::Accepted);
+    receiver.wait_for(1);
+
+    let subscription = format!("/api/topics/orders/subscriptions/{}", subscription_name(&receiver.url));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while client.get(&subscription).dispatch().status() != Status::NotFound {
+        assert!(Instant::now() < deadline, "Lease didn't expire");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn sends_custom_headers_and_credentials() {
+    let client = get_client();
+
+    // Gateway in front of the receiver, verification included
+    let receiver = TestReceiver::without_verification(|request| {
+        let authorized = request.headers.get("authorization").is_some_and(|auth| auth != "Bearer wrong");
+        match (authorized, challenge(request)) {
+            (false, _) => (401, String::new()),
+            (true, Some(challenge)) => (200, challenge.to_owned()),
+            (true, None) => (200, String::new())
+        }
+    });
+
+    create_topic(&client, "orders");
+    subscribe_with(&client, "orders", "billing", &receiver.url,
+        r#""headers": {"X-Api-Key": "k3y"}, "auth": {"type": "bearer", "token": "t0ken"}"#);
+    publish(&client, "orders", r#"{"value": "first"}"#);
+
+    let requests = receiver.wait_for(2);
+    assert_eq!(requests[0].method, "GET");
+    for request in &requests {
+        assert_eq!(request.headers["authorization"], "Bearer t0ken");
+        assert_eq!(request.headers["x-api-key"], "k3y");
+    }
+
+    // Secrets never show up, neither in the api nor in the admin panel
+    let credentials = client.get("/api/topics/orders/subscriptions/billing/credentials").dispatch().into_string().unwrap();
+    assert_eq!(credentials, r#"{"headers":{"X-Api-Key":"***"},"auth":{"type":"bearer","token":"***"}}"#);
+    let subscription = client.get("/api/topics/orders/subscriptions/billing").dispatch().into_string().unwrap();
+    assert!(!subscription.contains("t0ken") && !subscription.contains("k3y"));
+    let page = client.get("/admin/module/topic/orders").dispatch().into_string().unwrap();
+    assert!(page.contains("X-Api-Key: ***") && !page.contains("t0ken") && !page.contains("k3y"));
+
+    let set_credentials = |body: &'static str| client.put("/api/topics/orders/subscriptions/billing/credentials")
+                                            .header(ContentType::JSON)
+                                            .body(body)
+                                            .dispatch()
+                                            .status();
+
+    assert_eq!(set_credentials(r#"{"auth": {"type": "basic", "username": "user", "password": "pass"}}"#), Status::Ok);
+    assert_eq!(set_credentials(r#"{"headers": {"Content-Type": "text/plain"}}"#), Status::BadRequest);
+
+    publish(&client, "orders", r#"{"value": "second"}"#);
+    let requests = receiver.wait_for(3);
+    assert_eq!(requests[2].headers["authorization"], "Basic dXNlcjpwYXNz");
+    assert!(!requests[2].headers.contains_key("x-api-key"));
+
+    // Queues aren't pushed to
+    let response = client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"name": "workers", "mode": {"type": "queue"}, "headers": {"X-Api-Key": "k3y"}}"#)
+                                            .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[test]
+fn records_delivery_attempts() {
+    let client = get_client();
+    let failures = Arc::new(Mutex::new(1));
+
+    let receiver = TestReceiver::with_handler(move |_| {
+        let mut failures = failures.lock().unwrap();
+        match *failures {
+            0 => (200, String::new()),
+            _ => { *failures -= 1; (503, String::new()) }
+        }
+    });
+
+    create_topic(&client, "orders");
+    subscribe_with(&client, "orders", "billing", &receiver.url,
+        r#""retry_policy": {"initial_backoff_ms": 10, "max_backoff_ms": 10, "jitter": 0}"#);
+    publish(&client, "orders", r#"{"value": "first"}"#);
+    publish(&client, "orders", r#"{"value": "second"}"#);
+    receiver.wait_for(3);
+    std::thread::sleep(Duration::from_millis(100));
+
+    let attempts: serde_json::Value = serde_json::from_str(&client.get("/api/topics/orders/subscriptions/billing/attempts?offset=0")
+        .dispatch()
+        .into_string()
+        .unwrap()).unwrap();
+
+    // Newest first
+    assert_eq!(attempts.as_array().unwrap().len(), 2);
+    assert_eq!(attempts[0]["attempt"], 2);
+    assert_eq!(attempts[0]["status"], 200);
+    assert!(attempts[0]["error"].is_null());
+    assert_eq!(attempts[1]["attempt"], 1);
+    assert_eq!(attempts[1]["status"], 503);
+    assert_eq!(attempts[1]["error"], "Endpoint responded with 503 Service Unavailable");
+    assert!(attempts[1]["latency_ms"].is_u64() && attempts[1]["timestamp"].is_u64());
+
+    let attempts = client.get("/api/topics/orders/subscriptions/billing/attempts?limit=1").dispatch().into_string().unwrap();
+    assert!(attempts.contains(r#""offset":1"#) && !attempts.contains(r#""offset":0"#));
+    assert_eq!(client.get("/api/topics/orders/subscriptions/missing/attempts").dispatch().status(), Status::NotFound);
+
+    // Empty form fields mean no filter
+    let page = client.get("/admin/topics/orders/attempts?subscription=billing&partition=&offset=0").dispatch().into_string().unwrap();
+    assert!(page.contains("Endpoint responded with 503") && page.contains("0/0"));
+}
+
+#[test]
+fn throttles_deliveries_to_rate_limit() {
+    let client = get_client();
+    let arrivals = Arc::new(Mutex::new((vec![], 0, 0)));
+    let recorded = arrivals.clone();
+
+    // Records when requests come, how many are handled at once, and the most seen
+    let receiver = TestReceiver::with_handler(move |_| {
+        {
+            let mut arrivals = recorded.lock().unwrap();
+            arrivals.0.push(Instant::now());
+            arrivals.1 += 1;
+            arrivals.2 = arrivals.2.max(arrivals.1);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        recorded.lock().unwrap().1 -= 1;
+        (200, String::new())
+    });
+
+    create_topic(&client, "orders");
+    for i in 0..6 {
+        let key = ["a", "b", "c"][i % 3];
+        publish(&client, "orders", &format!(r#"{{"key": "{key}", "value": {i}}}"#));
+    }
+    subscribe_with(&client, "orders", "billing", &receiver.url, r#"
+        "concurrency": 3,
+        "rate_limit": {"requests_per_second": 10, "burst": 2, "max_in_flight": 2}"#);
+
+    // Falls behind because of its own limit, not because of errors
+    let subscription = || client.get("/api/topics/orders/subscriptions/billing").dispatch().into_string().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !subscription().contains(r#""throttled":true"#) {
+        assert!(Instant::now() < deadline, "Subscription wasn't throttled");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(subscription().contains(r#""circuit":"closed""#));
+
+    receiver.wait_for(6);
+    let (arrivals, _, most_in_flight) = arrivals.lock().unwrap().clone();
+    assert!(most_in_flight <= 2, "{most_in_flight} requests in flight");
+
+    // Burst of two, then one request every 100 ms
+    assert!(arrivals[5] - arrivals[0] >= Duration::from_millis(350), "Took only {:?}", arrivals[5] - arrivals[0]);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !subscription().contains(r#""throttled":false"#) {
+        assert!(Instant::now() < deadline, "Throttled subscription didn't catch up");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let response = client.patch("/api/topics/orders/subscriptions/billing")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"rate_limit": {"requests_per_second": 0}}"#)
+                                            .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = client.patch("/api/topics/orders/subscriptions/billing")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"rate_limit": {"max_in_flight": 1}}"#)
+                                            .dispatch();
+    assert!(response.into_string().unwrap().contains(r#""rate_limit":{"requests_per_second":null,"burst":null,"max_in_flight":1}"#));
+
+    let response = client.post("/api/topics/orders/subscriptions")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"name": "queue", "endpoint": "", "mode": {"type": "queue", "visibility_timeout_ms": 200}, "rate_limit": {"requests_per_second": 5}}"#)
+                                            .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+}
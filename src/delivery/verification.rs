@@ -0,0 +1,82 @@
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::delivery::credential_service::DeliveryCredentials;
+use crate::topic::topic_service::SubscriptionEntry;
+
+// Query parameters of the verification request
+const MODE_PARAM: &str = "czkawka.mode";
+const TOPIC_PARAM: &str = "czkawka.topic";
+const SUBSCRIPTION_PARAM: &str = "czkawka.subscription";
+const CHALLENGE_PARAM: &str = "czkawka.challenge";
+
+///
+/// Whether the endpoint agreed to receive the subscription's messages. Before the first delivery,
+/// and after every endpoint change, the endpoint gets a GET with the topic, the subscription and
+/// a random challenge in the query. It has to respond with 2xx and the challenge as the body.
+/// WebSub callbacks get the `hub.*` parameters of the spec instead.
+/// Failed attempts are retried according to the retry policy, once `max_attempts` are used up
+/// the subscription is marked as failed until verification is requested again.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationStatus {
+    /// Waiting for the endpoint to echo the challenge, nothing is delivered
+    Pending,
+
+    /// Subscriptions created before verification existed were already receiving messages
+    #[default]
+    Verified,
+
+    /// Endpoint didn't echo the challenge, nothing is delivered
+    Failed
+}
+
+///
+/// Sends the verification request to the subscription's endpoint. Returns why the endpoint
+/// didn't consent, if it didn't. Pending unsubscribes of WebSub callbacks are verified the same way
+///
+pub fn verify_endpoint(
+    client: &reqwest::blocking::Client,
+    topic_name: &str,
+    subscription: &SubscriptionEntry,
+    credentials: &DeliveryCredentials
+) -> Result<(), String> {
+
+    let challenge = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+
+    let mut url = reqwest::Url::parse(&subscription.endpoint).map_err(|err| err.to_string())?;
+    match &subscription.websub {
+        Some(lease) if lease.unsubscribing => url.query_pairs_mut()
+            .append_pair("hub.mode", "unsubscribe")
+            .append_pair("hub.topic", &lease.topic_url)
+            .append_pair("hub.challenge", &challenge),
+
+        Some(lease) => url.query_pairs_mut()
+            .append_pair("hub.mode", "subscribe")
+            .append_pair("hub.topic", &lease.topic_url)
+            .append_pair("hub.challenge", &challenge)
+            .append_pair("hub.lease_seconds", &lease.lease_seconds.to_string()),
+
+        None => url.query_pairs_mut()
+            .append_pair(MODE_PARAM, "subscribe")
+            .append_pair(TOPIC_PARAM, topic_name)
+            .append_pair(SUBSCRIPTION_PARAM, &subscription.name)
+            .append_pair(CHALLENGE_PARAM, &challenge)
+    };
+
+    let response = credentials.apply(client.get(url))
+        .timeout(subscription.retry_policy.timeout())
+        .send()
+        .map_err(|err| format!("Verification request failed: {err}"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("Endpoint responded to verification with {status}"));
+    }
+
+    match response.text() {
+        Ok(body) if body.trim() == challenge => Ok(()),
+        _ => Err("Endpoint didn't echo the verification challenge".to_owned())
+    }
+}
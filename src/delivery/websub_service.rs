@@ -0,0 +1,262 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rocket::form::{FromForm, FromFormField};
+use rocket::http::Status;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::delivery::signing_service::{SigningService, SigningServiceError};
+use crate::topic::offset_service::{OffsetService, OffsetServiceError, ResetTarget};
+use crate::topic::topic_service::{check_name, TopicService, TopicServiceError, SubscriptionEntry};
+
+/// Signature of content distribution requests, when the subscriber gave a `hub.secret`
+pub const HUB_SIGNATURE_HEADER: &str = "X-Hub-Signature";
+
+/// Lease given when the subscriber doesn't ask for one
+pub const DEFAULT_LEASE_SECONDS: u64 = 10 * 24 * 60 * 60;
+
+// Longer leases are cut down to this
+pub const MAX_LEASE_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+// Upper bound of hub.secret set by the spec
+const MAX_SECRET_BYTES: usize = 200;
+
+// Subscriptions created by the hub are named after their callback
+const NAME_PREFIX: &str = "websub-";
+
+#[derive(Debug, Error)]
+pub enum WebSubServiceError {
+    #[error(transparent)]
+    Topic(#[from] TopicServiceError),
+
+    #[error(transparent)]
+    Signing(#[from] SigningServiceError),
+
+    #[error(transparent)]
+    Offset(#[from] OffsetServiceError),
+
+    #[error("hub.topic {0} doesn't name a topic")]
+    InvalidTopic(String),
+
+    #[error("hub.secret can be at most {MAX_SECRET_BYTES} bytes")]
+    InvalidSecret,
+
+    #[error("{0} isn't subscribed to the topic through the hub")]
+    NotSubscribed(String)
+}
+
+impl WebSubServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            WebSubServiceError::Topic(error) => error.status(),
+            WebSubServiceError::Signing(error) => error.status(),
+            WebSubServiceError::Offset(error) => error.status(),
+            WebSubServiceError::InvalidTopic(_) | WebSubServiceError::InvalidSecret => Status::BadRequest,
+            WebSubServiceError::NotSubscribed(_) => Status::NotFound
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromFormField)]
+pub enum HubMode {
+    Subscribe,
+    Unsubscribe
+}
+
+/// `hub.*` fields of a subscription request
+#[derive(FromForm)]
+pub struct HubRequest {
+    pub mode: HubMode,
+    pub topic: String,
+    pub callback: String,
+    pub lease_seconds: Option<u64>,
+    pub secret: Option<String>
+}
+
+///
+/// Subscription made through the WebSub hub. It's removed once the lease expires, unless the
+/// subscriber renews it by subscribing again
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebSubLease {
+    /// `hub.topic` as the subscriber sent it, deliveries link back to it
+    pub topic_url: String,
+    pub hub_url: String,
+    pub lease_seconds: u64,
+
+    /// Seconds since epoch. Counted again from the moment the callback is verified
+    pub expires_at: u64,
+
+    /// Subscriber asked to unsubscribe, the subscription is removed once the callback confirms
+    #[serde(default)]
+    pub unsubscribing: bool,
+
+    /// Subscriber subscribed again, the lease and secret are replaced once the callback confirms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renewal: Option<WebSubRenewal>
+}
+
+/// Lease asked for by a renewal. Its secret is staged by the signing service
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebSubRenewal {
+    pub topic_url: String,
+    pub hub_url: String,
+    pub lease_seconds: u64
+}
+
+impl WebSubLease {
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Lease as it will be once the pending renewal is confirmed
+    pub fn renewed(&self) -> Option<WebSubLease> {
+        let renewal = self.renewal.clone()?;
+
+        Some(WebSubLease {
+            topic_url: renewal.topic_url,
+            hub_url: renewal.hub_url,
+            lease_seconds: renewal.lease_seconds,
+            expires_at: self.expires_at,
+            unsubscribing: self.unsubscribing,
+            renewal: None
+        })
+    }
+}
+
+///
+/// Lets off-the-shelf WebSub subscribers use the broker as their hub. Hub requests become
+/// ordinary push subscriptions: the callback is verified with `hub.challenge` like any other
+/// endpoint, and gets the plain message value, signed with `X-Hub-Signature` if a `hub.secret`
+/// was given. `hub.topic` is the topic name, or any URL whose last path segment is the name.
+///
+pub struct WebSubService {
+    topic_service: Arc<TopicService>,
+    signing_service: Arc<SigningService>,
+    offset_service: Arc<OffsetService>
+}
+
+impl WebSubService {
+    pub fn new(topic_service: Arc<TopicService>, signing_service: Arc<SigningService>, offset_service: Arc<OffsetService>) -> Self {
+        WebSubService { topic_service, signing_service, offset_service }
+    }
+
+    /// Subscribes or unsubscribes the callback. Either only takes effect once the callback confirms it
+    pub fn handle(&self, request: &HubRequest, hub_url: &str) -> Result<SubscriptionEntry, WebSubServiceError> {
+        match request.mode {
+            HubMode::Subscribe => self.subscribe(request, hub_url),
+            HubMode::Unsubscribe => self.unsubscribe(request)
+        }
+    }
+
+    ///
+    /// Creates a pending subscription for the callback, which gets only messages published from now
+    /// on. Subscribing again renews the lease, the new lease and secret take over once the callback
+    /// confirms it. Until then the subscription keeps delivering as it did
+    ///
+    fn subscribe(&self, request: &HubRequest, hub_url: &str) -> Result<SubscriptionEntry, WebSubServiceError> {
+        let topic_name = topic_name(&request.topic)?;
+        let subscription_name = subscription_name(&request.callback);
+
+        if request.secret.as_ref().is_some_and(|secret| secret.len() > MAX_SECRET_BYTES) {
+            return Err(WebSubServiceError::InvalidSecret);
+        }
+
+        let renewal = WebSubRenewal {
+            topic_url: request.topic.clone(),
+            hub_url: hub_url.to_owned(),
+            lease_seconds: request.lease_seconds.unwrap_or(DEFAULT_LEASE_SECONDS).clamp(1, MAX_LEASE_SECONDS)
+        };
+
+        let renewed = self.topic_service.update_subscription(&topic_name, &subscription_name, |subscription| {
+            let Some(lease) = subscription.websub.as_mut() else {
+                return Err(TopicServiceError::SubscriptionExists(topic_name.clone(), subscription_name.clone()));
+            };
+
+            // Signing service logs the details
+            self.signing_service.stage_secret(&topic_name, &subscription_name, request.secret.as_deref())
+                .map_err(|_| TopicServiceError::DatabaseError)?;
+
+            lease.renewal = Some(renewal.clone());
+            Ok(subscription.clone())
+        });
+
+        match renewed {
+            Err(TopicServiceError::SubscriptionNotFound(..)) => (),
+            renewed => return Ok(renewed?)
+        }
+
+        let lease = WebSubLease {
+            topic_url: renewal.topic_url,
+            hub_url: renewal.hub_url,
+            lease_seconds: renewal.lease_seconds,
+            expires_at: now() + renewal.lease_seconds,
+            unsubscribing: false,
+            renewal: None
+        };
+
+        let subscription = SubscriptionEntry {
+            websub: Some(lease),
+            ..SubscriptionEntry::new(&subscription_name, &request.callback)
+        };
+
+        // Secret is saved together with the subscription, so the first delivery is signed with it
+        self.topic_service.subscribe_topic_with(&topic_name, subscription, |subscription| {
+            self.signing_service.replace_secret(&topic_name, &subscription.name, request.secret.as_deref())
+                .map_err(WebSubServiceError::from)
+        })?;
+        self.offset_service.reset_subscription(&topic_name, &subscription_name, ResetTarget::Latest, false)?;
+
+        Ok(self.topic_service.get_subscription(&topic_name, &subscription_name)?)
+    }
+
+    fn unsubscribe(&self, request: &HubRequest) -> Result<SubscriptionEntry, WebSubServiceError> {
+        let topic_name = topic_name(&request.topic)?;
+        let subscription_name = subscription_name(&request.callback);
+
+        let unsubscribing = self.topic_service.update_subscription(&topic_name, &subscription_name, |subscription| {
+            match subscription.websub.as_mut() {
+                Some(lease) => lease.unsubscribing = true,
+                None => return Ok(None)
+            }
+            Ok(Some(subscription.clone()))
+        });
+
+        match unsubscribing {
+            Ok(Some(subscription)) => Ok(subscription),
+            Ok(None) | Err(TopicServiceError::SubscriptionNotFound(..)) => Err(WebSubServiceError::NotSubscribed(request.callback.clone())),
+            Err(err) => Err(err.into())
+        }
+    }
+}
+
+/// Name of the topic `hub.topic` points to
+fn topic_name(topic_url: &str) -> Result<String, WebSubServiceError> {
+    let name = topic_url.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+
+    match check_name(name) {
+        Ok(()) => Ok(name.to_owned()),
+        Err(_) => Err(WebSubServiceError::InvalidTopic(topic_url.to_owned()))
+    }
+}
+
+/// Every callback has one subscription per topic, so renewals and unsubscribes find it by the callback
+pub fn subscription_name(callback: &str) -> String {
+    let digest = hex::encode(Sha256::digest(callback.as_bytes()));
+    format!("{NAME_PREFIX}{}", &digest[..16])
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[test]
+fn test_topic_name() {
+    assert_eq!(topic_name("orders").unwrap(), "orders");
+    assert_eq!(topic_name("https://broker.example/websub/topics/orders/").unwrap(), "orders");
+    assert!(topic_name("").is_err());
+    assert_eq!(subscription_name("http://a/hook"), subscription_name("http://a/hook"));
+    assert_ne!(subscription_name("http://a/hook"), subscription_name("http://b/hook"));
+}
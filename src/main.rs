@@ -7,6 +7,7 @@ mod storage;
 mod topic;
 mod router;
 mod partition;
+mod delivery;
 
 #[cfg(test)]
 mod tests;
@@ -14,9 +15,11 @@ mod tests;
 use router::router;
 
 const KOPPERDB_FOLDER: &str = "kopper_database";
+const LOG_FOLDER: &str = "log_database";
+const HISTORY_FOLDER: &str = "history_database";
 const PORT: u16 = 8081;
 
 #[launch]
 fn rocket() -> _ {
-    router(&rocket::config::Config { port: PORT, ..Default::default()}, KOPPERDB_FOLDER)
+    router(&rocket::config::Config { port: PORT, ..Default::default()}, KOPPERDB_FOLDER, LOG_FOLDER, HISTORY_FOLDER)
 }
\ No newline at end of file
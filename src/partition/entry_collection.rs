@@ -46,7 +46,7 @@ pub struct EntryCollection {
 }
 
 impl EntryCollection {
-    pub fn next(&self) -> Result<Option<PartitionEntry>, PartitionError> {
+    pub fn next(&self) -> Result<Option<PartitionEntry<'_>>, PartitionError> {
         if self.address.get() == self.data.len() {
             return Ok(None);
         }
@@ -5,7 +5,7 @@ use kopperdb::from_error;
 use crate::partition::entry_collection::*;
 
 pub type Offset = u64;
-const SEG_LENGTH: usize = 4096;
+const DEFAULT_SEG_LENGTH: usize = 4096;
 
 #[derive(thiserror::Error, Debug)]
 pub enum PartitionError {
@@ -52,7 +52,9 @@ from_error!(PartitionError::Internal, std::io::Error, std::time::SystemTimeError
 /// ```
 pub struct Partition {
     index: BTreeMap<Offset, IndexEntry>,
-    next_offset: Offset
+    next_offset: Offset,
+    seg_length: usize,
+    path: String
 }
 
 struct IndexEntry {
@@ -69,9 +71,17 @@ impl Partition {
     /// If a partition exists at the path already, it'll be recovered    
     ///
     pub fn new(path: &str) -> Result<Self, PartitionError> {
+        Partition::with_seg_length(path, DEFAULT_SEG_LENGTH)
+    }
+
+    ///
+    /// Same as `new`, but with custom size of segments, i.e. chunks of the log
+    /// that are read from disk at once
+    ///
+    pub fn with_seg_length(path: &str, seg_length: usize) -> Result<Self, PartitionError> {
         
         // There are two possible states when creating Partition:
-        match Partition::recover(path)? {
+        match Partition::recover(path, seg_length)? {
             Some(partition) => {
                 
                 // 1. The log is already on disk 
@@ -80,11 +90,27 @@ impl Partition {
             None => {
 
                 // 2. The log is not on the disk, or there's only an empty file
-                Partition::create_new(path)
+                Partition::create_new(path, seg_length)
             }
         }
     }
 
+    ///
+    /// Changes the size of segments. Only affects segments created from now on
+    ///
+    pub fn set_seg_length(&mut self, seg_length: usize) {
+        self.seg_length = seg_length;
+    }
+
+    ///
+    /// Makes sure everything produced so far is flushed to disk
+    ///
+    pub fn sync(&self) -> Result<(), PartitionError> {
+        // All segments share the same file, so it's enough to sync the last one
+        self.index.last_key_value().unwrap().1.file.sync_data()?;
+        Ok(())
+    }
+
     ///
     /// Adds a new message to the end of partition
     ///
@@ -102,11 +128,14 @@ impl Partition {
         let mut last_index_entry = self.index.last_entry().unwrap();
 
         // Create new segment if needed
-        if last_index_entry.get_mut().size + new_partition_entry.len() > SEG_LENGTH {
+        if last_index_entry.get_mut().size + new_partition_entry.len() > self.seg_length {
             let file = last_index_entry.get_mut().file.try_clone()?;
+
+            // New segment starts right where the previous one ends
+            let address = last_index_entry.get().address + last_index_entry.get().size;
             self.index.insert(offset, IndexEntry {
                 file,
-                address: 0,
+                address,
                 size: 0
             });
 
@@ -159,12 +188,82 @@ impl Partition {
         }
 
         // It's ok to unwrap because there's always an item in index
-        Ok(self.index.first_key_value().unwrap().0.clone())
+        Ok(*self.index.first_key_value().unwrap().0)
+    }
+
+    ///
+    /// Returns the offset the next produced message will get, i.e. the high-water mark
+    ///
+    pub fn next_offset(&self) -> Offset {
+        self.next_offset
+    }
+
+    ///
+    /// Returns the size of the log on disk, in bytes
+    ///
+    pub fn size(&self) -> usize {
+        self.index.values().map(|index_entry| index_entry.size).sum()
     }
 
-    // TODO: pub fn set_retention_period(time) {}
+    ///
+    /// Rewrites the log without entries `keep` returns false for. `keep` gets every entry along with
+    /// its size in bytes, oldest first. Only full segments are cleaned, the one being written to
+    /// is left alone. Kept entries don't change their offsets. Returns how many entries were removed
+    ///
+    pub fn retain<F>(&mut self, mut keep: F) -> Result<usize, PartitionError>
+    where
+        F: FnMut(&PartitionEntry, usize) -> bool
+    {
+        // It's ok to unwrap because there's always an item in index
+        let active = self.index.last_key_value().unwrap().1;
+        if self.index.len() == 1 {
+            return Ok(0);
+        }
+
+        // Segments follow each other in the file, starting at its beginning
+        let mut file = active.file.try_clone()?;
+        let mut buffer = vec![0u8; active.address + active.size];
+        file.seek(io::SeekFrom::Start(0))?;
+        file.read_exact(&mut buffer)?;
+
+        let active_segment = buffer.split_off(active.address);
+        let full_segments = EntryCollection::new(buffer, 0);
+
+        let mut cleaned = vec![];
+        let mut removed = 0;
+        let mut size_read = 0;
+        while let Some(entry) = full_segments.next()? {
+            let size = full_segments.size_read() - size_read;
+            size_read = full_segments.size_read();
+
+            match keep(&entry, size) {
+                true => cleaned.append(&mut PartitionEntry::serialize(entry.offset, entry.timestamp, entry.value)?),
+                false => removed += 1
+            }
+        }
+
+        if removed == 0 {
+            return Ok(0);
+        }
+
+        cleaned.extend_from_slice(&active_segment);
 
-    fn create_new(path: &str) -> Result<Self, PartitionError> {
+        // Written next to the partition folder, recovery would pick it up from the inside. Renaming
+        // replaces the log at once, a crash leaves either the old or the new one
+        let cleaned_path = format!("{}.cleaned", self.path);
+        let mut cleaned_file = File::create(&cleaned_path)?;
+        cleaned_file.write_all(&cleaned)?;
+        cleaned_file.sync_all()?;
+        std::fs::rename(&cleaned_path, format!("{}/0", self.path))?;
+
+        // Index points into the old file, so it's rebuilt from the new one
+        *self = Partition::recover(&self.path, self.seg_length)?
+            .ok_or_else(|| anyhow::anyhow!("Log at {} is empty after cleaning", self.path))?;
+
+        Ok(removed)
+    }
+
+    fn create_new(path: &str, seg_length: usize) -> Result<Self, PartitionError> {
 
         let first_file_path = format!("{}/0", path);
 
@@ -174,6 +273,7 @@ impl Partition {
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(&first_file_path)?;
 
         // Create the first index
@@ -189,11 +289,13 @@ impl Partition {
 
         Ok(Partition {
             index: btree,
-            next_offset: 0
+            next_offset: 0,
+            seg_length,
+            path: path.to_owned()
         })
     }
 
-    fn recover(path: &str) -> Result<Option<Self>, PartitionError> {
+    fn recover(path: &str, seg_length: usize) -> Result<Option<Self>, PartitionError> {
 
         // Create a folder if it doesn't exist
         std::fs::create_dir_all(path)?;
@@ -221,6 +323,7 @@ impl Partition {
             let mut last_seg_cutoff = 0;
             let mut current_seg_size = 0;
             let mut first_offset_of_segment = None;
+            let mut previous_offset = None;
             while let Some(entry) = collection.next()? {
 
                 if first_offset_of_segment.is_none() {
@@ -231,8 +334,9 @@ impl Partition {
                     highest_offset = entry.offset;
                 }
 
-                if collection.size_read() - last_seg_cutoff <= SEG_LENGTH {
+                if collection.size_read() - last_seg_cutoff <= seg_length {
                     current_seg_size = collection.size_read() - last_seg_cutoff;
+                    previous_offset = Some(entry.offset);
                     continue;
                 }
                 
@@ -243,9 +347,13 @@ impl Partition {
                     size: current_seg_size,
                 });
 
-                last_seg_cutoff = current_seg_size;
+                last_seg_cutoff += current_seg_size;
                 current_seg_size = collection.size_read() - last_seg_cutoff;
-                first_offset_of_segment = Some(entry.offset);
+
+                // Cleaning leaves gaps between entries. Segment starts right after the previous one,
+                // so consuming an offset from a gap finds the next entry
+                first_offset_of_segment = Some(previous_offset.map_or(entry.offset, |offset: Offset| offset + 1));
+                previous_offset = Some(entry.offset);
             }
 
             if let Some(offset) = first_offset_of_segment {
@@ -268,6 +376,8 @@ impl Partition {
         Ok(Some(Partition {
             index,
             next_offset: highest_offset + 1,
+            seg_length,
+            path: path.to_owned()
         }))
     }
 }
\ No newline at end of file
@@ -149,7 +149,77 @@ fn recover_multiple_segments() -> Result<(), PartitionError> {
     let offset: u64 = p.produce(std::str::from_utf8(&vec![b'B'; 1200]).unwrap())?; // This one should be in next seg
 
     let p = Partition::new(&path)?;
-    assert_eq!(p.consume(offset)?.next()?.unwrap().value.chars().nth(0), Some('B'));
+    assert_eq!(p.consume(offset)?.next()?.unwrap().value.chars().next(), Some('B'));
     Ok(())
 }
 
+
+#[test]
+fn custom_seg_length() -> Result<(), PartitionError> {
+    let mut p = Partition::with_seg_length(&new_path(), 100)?;
+
+    // Each entry takes roughly 60 bytes, so every one of them gets its own segment
+    p.produce(std::str::from_utf8(&[b'A'; 50]).unwrap())?;
+    let offset = p.produce(std::str::from_utf8(&[b'B'; 50]).unwrap())?;
+
+    let collection = p.consume(offset)?;
+    assert_eq!(collection.next()?.unwrap().value.chars().next(), Some('B'));
+    assert!(collection.size_read() < 100);
+    Ok(())
+}
+
+#[test]
+fn recover_many_segments() -> Result<(), PartitionError> {
+    let path = new_path();
+    let mut p = Partition::with_seg_length(&path, 100)?;
+
+    for letter in b'A'..=b'F' {
+        p.produce(std::str::from_utf8(&[letter; 50]).unwrap())?;
+    }
+
+    let p = Partition::with_seg_length(&path, 100)?;
+    for (offset, letter) in ('A'..='F').enumerate() {
+        assert_eq!(p.consume(offset as u64)?.next()?.unwrap().value.chars().next(), Some(letter));
+    }
+    Ok(())
+}
+
+fn offsets(p: &Partition) -> Result<Vec<u64>, PartitionError> {
+    let mut offsets = vec![];
+    let mut offset = p.first_offset()?;
+
+    while offset < p.next_offset() {
+        let collection = p.consume(offset)?;
+        while let Some(entry) = collection.next()? {
+            offsets.push(entry.offset);
+            offset = entry.offset + 1;
+        }
+    }
+
+    Ok(offsets)
+}
+
+#[test]
+fn retain_keeps_offsets() -> Result<(), PartitionError> {
+    let path = new_path();
+    let mut p = Partition::with_seg_length(&path, 100)?;
+
+    // Each entry takes roughly 30 bytes, segments hold three of them
+    for letter in b'A'..=b'J' {
+        p.produce(std::str::from_utf8(&[letter; 20]).unwrap())?;
+    }
+
+    // Last segment is being written to, it's left alone
+    assert_eq!(p.retain(|entry, size| entry.offset % 2 == 1 && size < 100)?, 5);
+    assert_eq!(offsets(&p)?, vec![1, 3, 5, 7, 9]);
+    assert_eq!(p.first_offset()?, 1);
+
+    // Offsets from the gaps lead to the next entry
+    assert_eq!(p.consume(4)?.next()?.unwrap().value.chars().next(), Some('F'));
+    assert_eq!(p.produce("K")?, 10);
+
+    let p = Partition::with_seg_length(&path, 100)?;
+    assert_eq!(offsets(&p)?, vec![1, 3, 5, 7, 9, 10]);
+    assert_eq!(p.consume(2)?.next()?.unwrap().value.chars().next(), Some('D'));
+    Ok(())
+}
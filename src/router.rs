@@ -2,24 +2,52 @@ use std::sync::Arc;
 
 use rocket::{Rocket, Build};
 use rocket::fs::{FileServer, relative};
-use kopperdb::kopper::Kopper;
 
 use crate::api;
+use crate::storage::database::Database;
+use crate::delivery::{
+    dead_letter_service::DeadLetterService,
+    delivery_engine::DeliveryEngine,
+    credential_service::CredentialService,
+    history_service::HistoryService,
+    signing_service::SigningService,
+    websub_service::WebSubService
+};
 use crate::topic::{
+    consumer_service::ConsumerService,
+    group_service::GroupService,
+    log_service::LogService,
+    offset_service::OffsetService,
     publisher_service::PublisherService,
+    queue_service::QueueService,
+    retention_service::RetentionService,
+    schema_service::SchemaService,
     topic_service::TopicService
 };
 
 const SEGMENT_SIZE: usize = 4000; 
 
-pub fn router(config: &rocket::Config, db_folder: &str) -> Rocket<Build> {
+pub fn router(config: &rocket::Config, db_folder: &str, log_folder: &str, history_folder: &str) -> Rocket<Build> {
     
     let web_path = relative!("src/web");
 
     // DI management
-    let kopper = Kopper::create(db_folder, SEGMENT_SIZE).expect("Can't create Kopper!");
-    let topic_service = Arc::new(TopicService::new(kopper.clone()).expect("Can't create topic service"));
-    let publisher_service = Arc::new(PublisherService::new(topic_service.clone(), kopper));
+    let db = Database::open(db_folder, SEGMENT_SIZE).expect("Can't open database!");
+    let topic_service = Arc::new(TopicService::new(db.clone()).expect("Can't create topic service"));
+    let schema_service = Arc::new(SchemaService::new(topic_service.clone(), db.clone()));
+    let signing_service = Arc::new(SigningService::new(topic_service.clone(), db.clone()));
+    let credential_service = Arc::new(CredentialService::new(topic_service.clone(), db.clone()));
+    let history_service = Arc::new(HistoryService::new(history_folder));
+    let log_service = Arc::new(LogService::new(log_folder));
+    let group_service = Arc::new(GroupService::new(topic_service.clone(), log_service.clone(), db.clone()));
+    let publisher_service = Arc::new(PublisherService::new(topic_service.clone(), schema_service.clone(), log_service.clone()));
+    let dead_letter_service = Arc::new(DeadLetterService::new(topic_service.clone(), log_service.clone(), publisher_service.clone()));
+    let consumer_service = Arc::new(ConsumerService::new(topic_service.clone(), log_service.clone()));
+    let offset_service = Arc::new(OffsetService::new(topic_service.clone(), log_service.clone(), group_service.clone(), db));
+    let queue_service = Arc::new(QueueService::new(topic_service.clone(), log_service.clone(), offset_service.clone(), dead_letter_service.clone()));
+    let websub_service = Arc::new(WebSubService::new(topic_service.clone(), signing_service.clone(), offset_service.clone()));
+    let retention_service = RetentionService::start(topic_service.clone(), log_service.clone());
+    let delivery_engine = DeliveryEngine::start(topic_service.clone(), log_service, dead_letter_service.clone(), offset_service.clone(), signing_service.clone(), credential_service.clone(), history_service.clone());
     let templater = Arc::new(api::templater::Templater::new(web_path));
 
     rocket::custom(config)
@@ -30,12 +58,62 @@ pub fn router(config: &rocket::Config, db_folder: &str) -> Rocket<Build> {
             api::receiver::get_offset
         ])
 
+        // JSON API
+        .mount("/api", routes![
+            api::topics::get_topic_config,
+            api::topics::update_topic_config,
+            api::topics::get_default_config,
+            api::topics::update_default_config,
+            api::topics::get_schemas,
+            api::topics::get_latest_schema,
+            api::topics::get_schema,
+            api::topics::register_schema,
+            api::subscriptions::create_subscription,
+            api::subscriptions::get_subscriptions,
+            api::subscriptions::get_subscription,
+            api::subscriptions::update_subscription,
+            api::subscriptions::delete_subscription,
+            api::subscriptions::pause_subscription,
+            api::subscriptions::resume_subscription,
+            api::subscriptions::verify_subscription,
+            api::subscriptions::redrive_subscription,
+            api::subscriptions::rotate_secret,
+            api::subscriptions::get_credentials,
+            api::subscriptions::set_credentials,
+            api::subscriptions::get_attempts,
+            api::messages::fetch_messages,
+            api::groups::join_group,
+            api::groups::heartbeat,
+            api::groups::leave_group,
+            api::groups::get_group,
+            api::groups::commit_offsets,
+            api::groups::fetch_offsets,
+            api::groups::reset_group,
+            api::subscriptions::reset_subscription,
+            api::queues::receive,
+            api::queues::ack,
+            api::queues::nack
+        ])
+
+        // WEBSUB HUB
+        .mount("/websub", routes![api::websub::hub])
+
         // ADMIN API
         .mount("/", routes![api::admin::web_main])
         .mount("/admin", routes![
             // Htmx endpoints
             api::admin::create_topic,
             api::admin::create_subscriber,
+            api::admin::redrive_subscriber,
+            api::admin::reset_subscriber,
+            api::admin::reset_group,
+            api::admin::rotate_secret,
+            api::admin::update_subscriber,
+            api::admin::delete_subscriber,
+            api::admin::pause_subscriber,
+            api::admin::resume_subscriber,
+            api::admin::verify_subscriber,
+            api::admin::subscriber_attempts,
             
             api::admin::module_main,
             api::admin::module_topic,
@@ -46,6 +124,18 @@ pub fn router(config: &rocket::Config, db_folder: &str) -> Rocket<Build> {
         ])
         .manage(topic_service)
         .manage(publisher_service)
+        .manage(schema_service)
+        .manage(dead_letter_service)
+        .manage(offset_service)
+        .manage(consumer_service)
+        .manage(group_service)
+        .manage(queue_service)
+        .manage(signing_service)
+        .manage(websub_service)
+        .manage(credential_service)
+        .manage(history_service)
+        .manage(delivery_engine)
+        .manage(retention_service)
         
         .manage(templater)
         .mount("/static", FileServer::from(web_path.to_owned() + "/static"))
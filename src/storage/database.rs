@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use kopperdb::kopper::{Kopper, KeyValueIterator, KopperError};
+
+// Written in place of deleted values, Kopper can't remove keys
+const TOMBSTONE: &str = "";
+
+// Kopper cuts off a segment, and compacts one of the older ones, when the current one would grow
+// past its segment size. This one is never reached
+const NEVER_CUT_OFF: usize = usize::MAX / 2;
+
+// Directories next to the database's own, while it's being compacted
+const COMPACTING_SUFFIX: &str = ".compacting";
+const REPLACED_SUFFIX: &str = ".replaced";
+
+///
+/// Key-value store of the broker's metadata, persisted with Kopper.
+///
+/// Kopper opens the segments it compacts entries into for appending only, so once an entry's
+/// segment is compacted reading it fails with EBADF - and so does compacting that segment again,
+/// which takes down Kopper's compactor and every write after it. Kopper is never let to compact:
+/// it writes everything into a single segment, and once stale values take up more than
+/// `segment_size` bytes, and more than the live ones, the database copies the live values into
+/// a fresh directory and swaps it in.
+///
+/// Entries are only readable straight after recovery, so the database reads all of them when
+/// it's opened and keeps the values in memory. Every write goes to both under one lock, so
+/// readers always see the last saved value.
+///
+#[derive(Clone)]
+pub struct Database {
+    path: String,
+    segment_size: usize,
+    state: Arc<Mutex<State>>
+}
+
+struct State {
+    kopper: Kopper,
+    values: HashMap<String, String>,
+
+    /// Bytes in Kopper's segments, stale values included
+    written: usize,
+
+    /// Bytes the live values take in a segment
+    live: usize
+}
+
+impl Database {
+    pub fn open(path: &str, segment_size: usize) -> Result<Self, KopperError> {
+        recover(path)?;
+        let kopper = Kopper::create(path, NEVER_CUT_OFF)?;
+
+        // Kopper has no way of listing its keys, they're read from its segment files. Nothing
+        // was written yet, so nothing could have been compacted
+        let mut values = HashMap::new();
+        let mut written = 0;
+        for dir_entry in fs::read_dir(path)? {
+            let segment = fs::read(dir_entry?.path())?;
+            written += segment.len();
+
+            for (key, _, _) in KeyValueIterator::from(&segment) {
+                if values.contains_key(key) {
+                    continue;
+                }
+
+                let value = kopper.read(key)?;
+                values.insert(key.to_owned(), value);
+            }
+        }
+        values.retain(|_, value| value != TOMBSTONE);
+
+        let live = values.iter().map(|(key, value)| entry_size(key, value)).sum();
+        let state = State { kopper, values, written, live };
+
+        Ok(Database { path: path.to_owned(), segment_size, state: Arc::new(Mutex::new(state)) })
+    }
+
+    pub fn read(&self, key: &str) -> Result<String, KopperError> {
+        self.state.lock().unwrap()
+            .values
+            .get(key)
+            .cloned()
+            .ok_or_else(|| KopperError::KeyDoesNotExist(key.to_owned()))
+    }
+
+    pub fn write(&self, key: &str, value: &str) -> Result<(), KopperError> {
+        let mut state = self.state.lock().unwrap();
+
+        state.kopper.write(key, value)?;
+        state.written += entry_size(key, value);
+        state.live += entry_size(key, value);
+
+        if let Some(previous) = state.values.insert(key.to_owned(), value.to_owned()) {
+            state.live -= entry_size(key, &previous);
+        }
+
+        self.compact_if_stale(&mut state);
+        Ok(())
+    }
+
+    ///
+    /// Removes the key. Older copies of the value stay in Kopper's segment until the database
+    /// is compacted
+    ///
+    pub fn delete(&self, key: &str) -> Result<(), KopperError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.values.contains_key(key) {
+            return Ok(());
+        }
+
+        state.kopper.write(key, TOMBSTONE)?;
+        state.written += entry_size(key, TOMBSTONE);
+
+        if let Some(previous) = state.values.remove(key) {
+            state.live -= entry_size(key, &previous);
+        }
+
+        self.compact_if_stale(&mut state);
+        Ok(())
+    }
+
+    // Write itself is saved either way, compaction is tried again with the next one
+    fn compact_if_stale(&self, state: &mut State) {
+        if state.written - state.live <= state.live.max(self.segment_size) {
+            return;
+        }
+
+        if let Err(err) = self.compact(state) {
+            println!("Can't compact the database at {}: {err}", self.path);
+        }
+    }
+
+    ///
+    /// Copies the live values into a fresh directory, which then takes the place of the current
+    /// one. There's always a complete copy on disk, `recover` finishes the swap if it's interrupted
+    ///
+    fn compact(&self, state: &mut State) -> Result<(), KopperError> {
+        let compacting = format!("{}{COMPACTING_SUFFIX}", self.path);
+        let replaced = format!("{}{REPLACED_SUFFIX}", self.path);
+
+        remove_dir(&compacting)?;
+        let compacted = Kopper::create(&compacting, NEVER_CUT_OFF)?;
+        for (key, value) in state.values.iter() {
+            compacted.write(key, value)?;
+        }
+        drop(compacted);
+
+        fs::rename(&self.path, &replaced)?;
+        if let Err(err) = fs::rename(&compacting, &self.path) {
+            // Writes keep going to the current directory
+            fs::rename(&replaced, &self.path)?;
+            return Err(err.into());
+        }
+
+        state.kopper = Kopper::create(&self.path, NEVER_CUT_OFF)?;
+        state.written = state.live;
+
+        remove_dir(&replaced)?;
+        Ok(())
+    }
+}
+
+/// Finishes a compaction that was interrupted, or throws away its incomplete copy
+fn recover(path: &str) -> Result<(), KopperError> {
+    let compacting = format!("{path}{COMPACTING_SUFFIX}");
+
+    // Current directory is moved away only once the compacted copy is complete
+    if !Path::new(path).exists() && Path::new(&compacting).exists() {
+        fs::rename(&compacting, path)?;
+    }
+
+    remove_dir(&compacting)?;
+    remove_dir(&format!("{path}{REPLACED_SUFFIX}"))?;
+    Ok(())
+}
+
+fn remove_dir(path: &str) -> io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
+
+/// Size of the entry in a segment, key and value are both null terminated
+fn entry_size(key: &str, value: &str) -> usize {
+    key.len() + value.len() + 2
+}
+
+#[test]
+fn test_read_after_compaction() {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    let name: String = rand::thread_rng().sample_iter(&Alphanumeric).take(20).map(char::from).collect();
+    let path = format!("testfiles/database/{name}");
+
+    let db = Database::open(&path, 200).unwrap();
+    db.write("first", "kept").unwrap();
+    db.write("deleted", "secret").unwrap();
+    db.delete("deleted").unwrap();
+
+    // Database is compacted many times over, and stays in a single segment
+    for i in 0..200 {
+        db.write("counter", &i.to_string()).unwrap();
+    }
+    assert_eq!(fs::read_dir(&path).unwrap().count(), 1);
+
+    assert_eq!(db.read("first").unwrap(), "kept");
+    assert_eq!(db.read("counter").unwrap(), "199");
+    assert!(matches!(db.read("deleted"), Err(KopperError::KeyDoesNotExist(_))));
+    drop(db);
+
+    // Leftovers of an interrupted compaction don't stay around
+    fs::create_dir_all(format!("{path}{COMPACTING_SUFFIX}")).unwrap();
+
+    let reopened = Database::open(&path, 200).unwrap();
+    assert!(!Path::new(&format!("{path}{COMPACTING_SUFFIX}")).exists());
+    assert_eq!(reopened.read("first").unwrap(), "kept");
+    assert!(matches!(reopened.read("deleted"), Err(KopperError::KeyDoesNotExist(_))));
+}
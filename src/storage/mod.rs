@@ -1 +1,3 @@
+pub mod database;
+#[allow(clippy::module_inception)]
 pub mod storage;
\ No newline at end of file
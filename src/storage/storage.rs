@@ -49,11 +49,11 @@ impl Storage {
         };
 
         queue.add_message(message);
-        return Ok(queue.offset);
+        Ok(queue.offset)
     }
 
     fn topic_exists(&self, topic_name: &String) -> bool {
-        return self.store.contains_key(topic_name);
+        self.store.contains_key(topic_name)
     }
 }
 
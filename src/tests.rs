@@ -3,6 +3,8 @@
 use rand::{distributions::Alphanumeric, Rng};
 use rocket::http::ContentType;
 use crate::router;
+use crate::topic::queue_service::QueueService;
+use crate::topic::retention_service::RetentionService;
 
 const DB_PATH: &str = "testfiles/broker";
 
@@ -19,15 +21,25 @@ fn random_key_value_with_size(size: usize) -> (String, String) {
 }
 
 pub fn get_client() -> rocket::local::blocking::Client {
+    get_client_at(&new_db_path())
+}
+
+/// Path of a fresh database, unique for each test
+pub fn new_db_path() -> String {
+    let db_path = DB_PATH.to_owned() + "/" + &random_key_value_with_size(20).0;
+
+    println!("Creating database at {}", db_path);
+    db_path
+}
+
+/// Client of a broker working on an existing database, as if it was restarted
+pub fn get_client_at(db_path: &str) -> rocket::local::blocking::Client {
     let config = rocket::Config {
         log_level: rocket::config::LogLevel::Off,
         ..rocket::Config::debug_default()
     };
 
-    let db_path = DB_PATH.to_owned() + "/" + &random_key_value_with_size(20).0;
-
-    println!("Creating database at {}", db_path);
-    let rocket = router(&config, &db_path);
+    let rocket = router(&config, &(db_path.to_owned() + "/kopper"), &(db_path.to_owned() + "/log"), &(db_path.to_owned() + "/history"));
 
     rocket::local::blocking::Client::untracked(rocket).expect("Could not build the client")
 }
@@ -70,4 +82,689 @@ fn test_get_topic_info()
     let response = client.get("/admin/topic/toptopic").dispatch();
     assert!(response.into_string().unwrap().contains("new_sub"));
 }
-        
\ No newline at end of file
+
+#[test]
+fn test_create_topic_duplicate()
+{
+    let client = get_client();
+
+    let create = || client.post("/admin/topics")
+                                            .header(ContentType::Form)
+                                            .body("name=orders&owner=mimi")
+                                            .dispatch();
+
+    assert_eq!(create().status(), rocket::http::Status::Ok);
+    assert_eq!(create().status(), rocket::http::Status::Conflict);
+}
+
+#[test]
+fn test_create_topic_invalid_name()
+{
+    let client = get_client();
+
+    for name in ["", "..", "..%2Fx", "a%2Fb", "__internal", "spaces%20here", &"a".repeat(65)] {
+        let response = client.post("/admin/topics")
+                                            .header(ContentType::Form)
+                                            .body(format!("name={name}&owner=mimi"))
+                                            .dispatch();
+
+        assert_eq!(response.status(), rocket::http::Status::BadRequest, "name: {name}");
+    }
+
+    let response = client.get("/admin/module/main").dispatch();
+    assert!(!response.into_string().unwrap().contains("__internal"));
+}
+
+pub fn create_topic(client: &rocket::local::blocking::Client, name: &str) {
+    let response = client.post("/admin/topics")
+                                            .header(ContentType::Form)
+                                            .body(format!("name={name}&owner=mimi"))
+                                            .dispatch();
+
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+}
+
+#[test]
+fn test_topic_config()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let config = client.get("/api/topics/orders/config").dispatch().into_string().unwrap();
+    assert!(config.contains("\"partitions\":1"));
+
+    let new_config = config.replace("\"partitions\":1", "\"partitions\":4");
+    let response = client.put("/api/topics/orders/config").header(ContentType::JSON).body(&new_config).dispatch();
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+
+    let config = client.get("/api/topics/orders/config").dispatch().into_string().unwrap();
+    assert!(config.contains("\"partitions\":4"));
+
+    // Partitions can't be removed
+    let response = client.put("/api/topics/orders/config").header(ContentType::JSON).body(new_config.replace("\"partitions\":4", "\"partitions\":2")).dispatch();
+    assert_eq!(response.status(), rocket::http::Status::BadRequest);
+}
+
+#[test]
+fn test_default_config()
+{
+    let client = get_client();
+
+    let defaults = client.get("/api/config/defaults").dispatch().into_string().unwrap();
+    let response = client.put("/api/config/defaults")
+                                            .header(ContentType::JSON)
+                                            .body(defaults.replace("\"partitions\":1", "\"partitions\":3"))
+                                            .dispatch();
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+
+    create_topic(&client, "orders");
+    let config = client.get("/api/topics/orders/config").dispatch().into_string().unwrap();
+    assert!(config.contains("\"partitions\":3"));
+}
+
+#[test]
+fn test_retention()
+{
+    let client = get_client();
+
+    // Every message gets its own segment, only the last one is being written to
+    let configure = |topic: &str, from: &str, to: &str| {
+        create_topic(&client, topic);
+        let config = client.get(format!("/api/topics/{topic}/config")).dispatch().into_string().unwrap();
+        let config = config.replace("\"segment_size\":4096", "\"segment_size\":1").replace(from, to);
+        client.put(format!("/api/topics/{topic}/config")).header(ContentType::JSON).body(config).dispatch();
+    };
+
+    configure("clicks", "\"retention_ms\":null", "\"retention_ms\":3000");
+    configure("events", "\"retention_bytes\":null", "\"retention_bytes\":1");
+    configure("prices", "\"cleanup_policy\":\"delete\"", "\"cleanup_policy\":\"compact\"");
+
+    client.post("/api/topics/events/subscriptions").header(ContentType::JSON).body(r#"{"name": "jobs", "mode": {"type": "queue"}}"#).dispatch();
+
+    for (i, key) in ["a", "b", "a", "b", "a"].iter().enumerate() {
+        for topic in ["clicks", "events", "prices"] {
+            client.post(format!("/publish/publish/{topic}")).header(ContentType::JSON).body(format!(r#"{{"key": "{key}", "value": {i}}}"#)).dispatch();
+        }
+    }
+
+    let offsets = |topic: &str| {
+        let fetched = client.get(format!("/api/topics/{topic}/partitions/0/messages?offset=0")).dispatch().into_string().unwrap();
+        let fetched: rocket::serde::json::Value = rocket::serde::json::from_str(&fetched).unwrap();
+        fetched["messages"].as_array().unwrap().iter().map(|message| message["offset"].as_u64().unwrap()).collect::<Vec<_>>()
+    };
+
+    let retention_service = client.rocket().state::<std::sync::Arc<RetentionService>>().unwrap();
+    assert_eq!(retention_service.clean_up(), 4 + 3);
+    assert_eq!(offsets("clicks"), vec![0, 1, 2, 3, 4]);
+    assert_eq!(offsets("events"), vec![4]);
+    assert_eq!(offsets("prices"), vec![3, 4]);
+
+    // Removed messages can't be consumed anymore, they're not lagged behind on
+    let lag = client.get("/publish/offset/events/jobs").dispatch().into_string().unwrap();
+    assert!(lag.contains(r#""committed_offset":0,"high_watermark":5,"lag":1"#), "{lag}");
+
+    // Timestamps are in seconds, so are retention checks
+    std::thread::sleep(std::time::Duration::from_millis(3100));
+    assert_eq!(retention_service.clean_up(), 4);
+    assert_eq!(offsets("clicks"), vec![4]);
+}
+
+#[test]
+fn test_publish_message()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let publish = || client.post("/publish/publish/orders")
+                                            .header(ContentType::JSON)
+                                            .body(r#"{"key": "order-1", "value": {"id": 1}}"#)
+                                            .dispatch();
+
+    assert_eq!(publish().into_string().unwrap(), r#"{"partition":0,"offset":0}"#);
+    assert_eq!(publish().into_string().unwrap(), r#"{"partition":0,"offset":1}"#);
+
+    let response = client.post("/publish/publish/nope").header(ContentType::JSON).body(r#"{"value": 1}"#).dispatch();
+    assert_eq!(response.status(), rocket::http::Status::NotFound);
+}
+
+#[test]
+fn test_publish_message_too_large()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let config = client.get("/api/topics/orders/config").dispatch().into_string().unwrap();
+    let config = config.replace("\"max_message_size\":1048576", "\"max_message_size\":100");
+    client.put("/api/topics/orders/config").header(ContentType::JSON).body(config).dispatch();
+
+    let response = client.post("/publish/publish/orders")
+                                            .header(ContentType::JSON)
+                                            .body(format!(r#"{{"value": "{}"}}"#, "a".repeat(100)))
+                                            .dispatch();
+
+    assert_eq!(response.status(), rocket::http::Status::PayloadTooLarge);
+}
+
+#[test]
+fn test_publish_with_schema()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let schema = r#"{"type": "object", "properties": {"id": {"type": "integer"}}, "required": ["id"]}"#;
+    let response = client.post("/api/topics/orders/schemas").header(ContentType::JSON).body(schema).dispatch();
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+    assert!(response.into_string().unwrap().contains("\"version\":1"));
+
+    let publish = |body: &str| client.post("/publish/publish/orders")
+                                            .header(ContentType::JSON)
+                                            .body(body)
+                                            .dispatch();
+
+    assert_eq!(publish(r#"{"value": {"id": 1}}"#).status(), rocket::http::Status::Ok);
+
+    let response = publish(r#"{"value": {"id": "one"}}"#);
+    assert_eq!(response.status(), rocket::http::Status::UnprocessableEntity);
+    assert!(response.into_string().unwrap().contains("details"));
+
+    assert_eq!(publish(r#"{"value": {}}"#).status(), rocket::http::Status::UnprocessableEntity);
+
+    let page = client.get("/admin/module/topic/orders").dispatch().into_string().unwrap();
+    assert!(page.contains("Version 1"));
+}
+
+#[test]
+fn test_register_invalid_schema()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let response = client.post("/api/topics/orders/schemas").header(ContentType::JSON).body(r#"{"type": "not-a-type"}"#).dispatch();
+    assert_eq!(response.status(), rocket::http::Status::BadRequest);
+
+    let response = client.post("/api/topics/nope/schemas").header(ContentType::JSON).body(r#"{"type": "object"}"#).dispatch();
+    assert_eq!(response.status(), rocket::http::Status::NotFound);
+
+    let response = client.get("/api/topics/orders/schemas/latest").dispatch();
+    assert_eq!(response.status(), rocket::http::Status::NotFound);
+}
+
+#[test]
+fn test_schema_evolution()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let register = |schema: &'static str| client.post("/api/topics/orders/schemas").header(ContentType::JSON).body(schema).dispatch();
+
+    let v1 = r#"{"type": "object", "properties": {"id": {"type": "integer"}}, "required": ["id"]}"#;
+    let v2 = r#"{"type": "object", "properties": {"id": {"type": "integer"}, "note": {"type": "string"}}, "required": ["id"]}"#;
+    let breaking = r#"{"type": "object", "properties": {"id": {"type": "string"}}, "required": ["id"]}"#;
+
+    assert_eq!(register(v1).status(), rocket::http::Status::Ok);
+    assert_eq!(register(v2).status(), rocket::http::Status::Ok);
+
+    let response = register(breaking);
+    assert_eq!(response.status(), rocket::http::Status::Conflict);
+    assert!(response.into_string().unwrap().contains("type integer is no longer accepted"));
+
+    // Published messages are tagged with the version they were validated against
+    let response = client.post("/publish/publish/orders").header(ContentType::JSON).body(r#"{"value": {"id": 1}}"#).dispatch();
+    assert!(response.into_string().unwrap().contains("\"schema_version\":2"));
+
+    let response = client.get("/api/topics/orders/schemas/1").dispatch();
+    assert!(response.into_string().unwrap().contains("\"version\":1"));
+
+    // Admin shows the whole history, newest first
+    let page = client.get("/admin/module/topic/orders").dispatch().into_string().unwrap();
+    let (v2_at, v1_at) = (page.find("Version 2").unwrap(), page.find("Version 1").unwrap());
+    assert!(v2_at < v1_at && page.contains("&quot;note&quot;"));
+    assert_eq!(client.get("/api/topics/orders/schemas/3").dispatch().status(), rocket::http::Status::NotFound);
+
+    // Checks can be turned off
+    let config = client.get("/api/topics/orders/config").dispatch().into_string().unwrap();
+    let config = config.replace("\"schema_compatibility\":\"backward\"", "\"schema_compatibility\":\"none\"");
+    client.put("/api/topics/orders/config").header(ContentType::JSON).body(config).dispatch();
+
+    assert_eq!(register(breaking).status(), rocket::http::Status::Ok);
+}
+
+#[test]
+fn test_create_subscriber()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let subscribe = |body: &'static str| client.post("/admin/topics/orders/subscribe")
+                                            .header(ContentType::Form)
+                                            .body(body)
+                                            .dispatch();
+
+    let response = subscribe("name=billing&endpoint=http%3A%2F%2Flocalhost%3A1234%2Fhook");
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+    let created = response.into_string().unwrap();
+    assert!(created.contains("billing"));
+    assert!(created.contains("whsec_"));
+
+    assert_eq!(subscribe("name=billing&endpoint=http%3A%2F%2Flocalhost%3A1234%2Fhook").status(), rocket::http::Status::Conflict);
+    assert_eq!(subscribe("name=other&endpoint=nope").status(), rocket::http::Status::BadRequest);
+
+    let page = client.get("/admin/module/topic/orders").dispatch().into_string().unwrap();
+    assert!(page.contains("http://localhost:1234/hook"));
+
+    // Secret is revealed once, rotation reveals the new one
+    assert!(!page.contains("whsec_"));
+    let rotated = client.post("/admin/topics/orders/subscriptions/billing/secret/rotate").dispatch().into_string().unwrap();
+    assert!(rotated.contains("whsec_"));
+}
+
+#[test]
+fn test_admin_manage_subscriber()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+    client.post("/admin/topics/orders/subscribe")
+            .header(ContentType::Form)
+            .body("name=billing&endpoint=http%3A%2F%2Flocalhost%3A1%2Fhook")
+            .dispatch();
+
+    let row = client.post("/admin/topics/orders/subscriptions/billing/pause").dispatch().into_string().unwrap();
+    assert!(row.contains("paused") && row.contains("/resume"));
+
+    let row = client.post("/admin/topics/orders/subscriptions/billing/resume").dispatch().into_string().unwrap();
+    assert!(!row.contains(">paused<") && row.contains("/pause"));
+
+    // Nothing was delivered yet, localhost:1 can't consent
+    assert!(row.contains(">closed<") && row.contains("Last success at never"));
+    assert!(row.contains("pending verification"));
+
+    let edit = |body: &'static str| client.post("/admin/topics/orders/subscriptions/billing/endpoint")
+                                            .header(ContentType::Form)
+                                            .body(body)
+                                            .dispatch();
+
+    let response = edit("endpoint=http%3A%2F%2Flocalhost%3A2%2Fhook");
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+    assert!(response.into_string().unwrap().contains("http://localhost:2/hook"));
+    assert_eq!(edit("endpoint=nope").status(), rocket::http::Status::BadRequest);
+
+    let response = client.delete("/admin/topics/orders/subscriptions/billing").dispatch();
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+
+    let page = client.get("/admin/module/topic/orders").dispatch().into_string().unwrap();
+    assert!(!page.contains("billing"));
+}
+
+#[test]
+fn test_fetch_messages()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    for i in 0..5 {
+        client.post("/publish/publish/orders")
+                .header(ContentType::JSON)
+                .body(format!(r#"{{"key": "order-{i}", "headers": {{"source": "test"}}, "value": {{"id": {i}}}}}"#))
+                .dispatch();
+    }
+
+    let fetch = |query: &str| client.get(format!("/api/topics/orders/partitions/0/messages{query}")).dispatch();
+
+    let body = fetch("?offset=1&max=2").into_string().unwrap();
+    assert!(body.contains(r#""offset":1,"#));
+    assert!(body.contains(r#""key":"order-2","headers":{"source":"test"},"value":{"id":2}"#));
+    assert!(!body.contains(r#""offset":3,"#));
+    assert!(body.ends_with(r#""next_offset":3}"#));
+
+    // Everything from the start by default
+    let body = fetch("").into_string().unwrap();
+    assert!(body.contains(r#""value":{"id":0}"#) && body.contains(r#""value":{"id":4}"#));
+
+    // Nothing new yet
+    assert_eq!(fetch("?offset=5").into_string().unwrap(), r#"{"messages":[],"next_offset":5}"#);
+
+    assert_eq!(fetch("?offset=6").status(), rocket::http::Status::RangeNotSatisfiable);
+    assert_eq!(client.get("/api/topics/orders/partitions/1/messages").dispatch().status(), rocket::http::Status::NotFound);
+    assert_eq!(client.get("/api/topics/nope/partitions/0/messages").dispatch().status(), rocket::http::Status::NotFound);
+}
+
+#[test]
+fn test_consumer_group()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let config = client.get("/api/topics/orders/config").dispatch().into_string().unwrap();
+    client.put("/api/topics/orders/config").header(ContentType::JSON).body(config.replace("\"partitions\":1", "\"partitions\":3")).dispatch();
+
+    let post = |url: &str, body: &str| client.post(url.to_owned()).header(ContentType::JSON).body(body).dispatch();
+
+    let first = post("/api/groups/billing/members", r#"{"topic": "orders", "member": "a"}"#).into_string().unwrap();
+    assert_eq!(first, r#"{"group":"billing","member":"a","topic":"orders","generation":1,"partitions":[0,1,2]}"#);
+
+    // Second member with a short session, it takes over a partition
+    let second = post("/api/groups/billing/members", r#"{"topic": "orders", "member": "b", "session_timeout_ms": 200}"#).into_string().unwrap();
+    assert_eq!(second, r#"{"group":"billing","member":"b","topic":"orders","generation":2,"partitions":[1]}"#);
+
+    let heartbeat = post("/api/groups/billing/members/a/heartbeat", "{}").into_string().unwrap();
+    assert_eq!(heartbeat, r#"{"group":"billing","member":"a","topic":"orders","generation":2,"partitions":[0,2]}"#);
+
+    // Offsets are only accepted for owned partitions in the current generation
+    assert_eq!(post("/api/groups/billing/members/a/heartbeat", r#"{"generation": 1, "offsets": {"0": 0}}"#).status(), rocket::http::Status::Conflict);
+    assert_eq!(post("/api/groups/billing/members/a/heartbeat", r#"{"generation": 2, "offsets": {"1": 0}}"#).status(), rocket::http::Status::Conflict);
+    assert_eq!(post("/api/groups/billing/members/a/heartbeat", r#"{"generation": 2, "offsets": {"0": 0}}"#).status(), rocket::http::Status::Ok);
+
+    // Same goes for committing offsets directly
+    let commit = |fencing: &str| post("/api/groups/billing/offsets", &format!(r#"{{"offsets": [{{"topic": "orders", "partition": 0, "offset": 0}}]{fencing}}}"#)).status();
+    assert_eq!(commit(""), rocket::http::Status::Conflict);
+    assert_eq!(commit(r#", "member": "b", "generation": 2"#), rocket::http::Status::Conflict);
+    assert_eq!(commit(r#", "member": "a", "generation": 1"#), rocket::http::Status::Conflict);
+    assert_eq!(commit(r#", "member": "c", "generation": 2"#), rocket::http::Status::NotFound);
+    assert_eq!(commit(r#", "member": "a", "generation": 2"#), rocket::http::Status::Ok);
+
+    // Member b stops sending heartbeats and its partition goes back to a
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    assert_eq!(post("/api/groups/billing/members/b/heartbeat", "{}").status(), rocket::http::Status::NotFound);
+
+    let group = client.get("/api/groups/billing").dispatch().into_string().unwrap();
+    assert_eq!(group, r#"{"group":"billing","topic":"orders","generation":3,"members":[{"member":"a","partitions":[0,1,2]}],"offsets":{"0":0}}"#);
+
+    // Group sticks to its topic while it has members
+    create_topic(&client, "payments");
+    assert_eq!(post("/api/groups/billing/members", r#"{"topic": "payments"}"#).status(), rocket::http::Status::Conflict);
+
+    assert_eq!(client.delete("/api/groups/billing/members/a").dispatch().status(), rocket::http::Status::Ok);
+
+    // Commits of a membership that's gone are rejected, plain ones are fine
+    assert_eq!(commit(r#", "member": "a", "generation": 4"#), rocket::http::Status::Conflict);
+    assert_eq!(commit(""), rocket::http::Status::Ok);
+    let unknown = post("/api/groups/nope/offsets", r#"{"offsets": [{"topic": "orders", "partition": 0, "offset": 0}], "generation": 1}"#);
+    assert_eq!(unknown.status(), rocket::http::Status::NotFound);
+    let joined = post("/api/groups/billing/members", r#"{"topic": "payments"}"#).into_string().unwrap();
+    assert!(joined.contains(r#""member":"billing-"#));
+}
+
+#[test]
+fn test_group_offsets()
+{
+    let db_path = new_db_path();
+    let client = get_client_at(&db_path);
+    create_topic(&client, "orders");
+
+    for i in 0..3 {
+        client.post("/publish/publish/orders").header(ContentType::JSON).body(format!(r#"{{"value": {i}}}"#)).dispatch();
+    }
+
+    let commit = |client: &rocket::local::blocking::Client, body: &str| client.post("/api/groups/billing/offsets")
+                                            .header(ContentType::JSON)
+                                            .body(body)
+                                            .dispatch()
+                                            .status();
+
+    assert_eq!(commit(&client, r#"{"offsets": [{"topic": "orders", "partition": 0, "offset": 2, "metadata": "batch-7"}]}"#), rocket::http::Status::Ok);
+
+    // Offsets past the end of the partition, of missing partitions or topics are rejected
+    assert_eq!(commit(&client, r#"{"offsets": [{"topic": "orders", "partition": 0, "offset": 4}]}"#), rocket::http::Status::BadRequest);
+    assert_eq!(commit(&client, r#"{"offsets": [{"topic": "orders", "partition": 1, "offset": 0}]}"#), rocket::http::Status::BadRequest);
+    assert_eq!(commit(&client, r#"{"offsets": [{"topic": "nope", "partition": 0, "offset": 0}]}"#), rocket::http::Status::NotFound);
+
+    // Nothing from a rejected commit is saved
+    assert_eq!(commit(&client, r#"{"offsets": [{"topic": "orders", "partition": 0, "offset": 3}, {"topic": "orders", "partition": 0, "offset": 9}]}"#), rocket::http::Status::BadRequest);
+
+    // Offsets survive a restart
+    drop(client);
+    let client = get_client_at(&db_path);
+
+    let offsets = client.get("/api/groups/billing/offsets").dispatch().into_string().unwrap();
+    assert_eq!(offsets, r#"{"offsets":[{"topic":"orders","partition":0,"offset":2,"metadata":"batch-7"}]}"#);
+
+    let offsets = client.get("/api/groups/billing/offsets?topic=payments").dispatch().into_string().unwrap();
+    assert_eq!(offsets, r#"{"offsets":[]}"#);
+}
+
+#[test]
+fn test_metadata_after_compaction()
+{
+    let client = get_client();
+
+    let defaults = client.get("/api/config/defaults").dispatch().into_string().unwrap();
+    client.put("/api/config/defaults").header(ContentType::JSON).body(defaults.replace("\"partitions\":1", "\"partitions\":2")).dispatch();
+
+    create_topic(&client, "orders");
+    client.post("/api/topics/orders/schemas").header(ContentType::JSON).body(r#"{"type": "object"}"#).dispatch();
+    client.post("/publish/publish/orders").header(ContentType::JSON).body(r#"{"value": {}}"#).dispatch();
+    client.post("/api/groups/billing/offsets")
+        .header(ContentType::JSON)
+        .body(r#"{"offsets": [{"topic": "orders", "partition": 0, "offset": 1}]}"#)
+        .dispatch();
+
+    // Every update rewrites the topic list, segments holding the entries above get compacted
+    let config = client.get("/api/topics/orders/config").dispatch().into_string().unwrap();
+    for _ in 0..100 {
+        client.put("/api/topics/orders/config").header(ContentType::JSON).body(&config).dispatch();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    let response = client.post("/publish/publish/orders").header(ContentType::JSON).body(r#"{"value": {}}"#).dispatch();
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+
+    let offsets = client.get("/api/groups/billing/offsets").dispatch().into_string().unwrap();
+    assert!(offsets.contains(r#""offset":1"#));
+
+    create_topic(&client, "payments");
+    let config = client.get("/api/topics/payments/config").dispatch().into_string().unwrap();
+    assert!(config.contains("\"partitions\":2"));
+}
+
+#[test]
+fn test_reset_group()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    for i in 0..3 {
+        client.post("/publish/publish/orders").header(ContentType::JSON).body(format!(r#"{{"value": {i}}}"#)).dispatch();
+    }
+
+    let post = |url: &str, body: &str| client.post(url.to_owned()).header(ContentType::JSON).body(body).dispatch();
+
+    post("/api/groups/billing/offsets", r#"{"offsets": [{"topic": "orders", "partition": 0, "offset": 1}]}"#);
+    post("/api/groups/billing/members", r#"{"topic": "orders", "member": "a"}"#);
+
+    // Members would overwrite the reset
+    assert_eq!(post("/api/groups/billing/offsets/reset", r#"{"topic": "orders", "to": "latest"}"#).status(), rocket::http::Status::Conflict);
+    client.delete("/api/groups/billing/members/a").dispatch();
+
+    let reset = post("/api/groups/billing/offsets/reset", r#"{"topic": "orders", "to": "latest"}"#).into_string().unwrap();
+    assert_eq!(reset, r#"{"dry_run":false,"partitions":[{"partition":0,"previous_offset":1,"new_offset":3}]}"#);
+
+    let far_future = r#"{"topic": "orders", "to": "timestamp", "timestamp": 99999999999, "dry_run": true}"#;
+    let reset = post("/api/groups/billing/offsets/reset", far_future).into_string().unwrap();
+    assert_eq!(reset, r#"{"dry_run":true,"partitions":[{"partition":0,"previous_offset":3,"new_offset":3}]}"#);
+
+    let reset = post("/api/groups/billing/offsets/reset", r#"{"topic": "orders", "to": "earliest"}"#).into_string().unwrap();
+    assert_eq!(reset, r#"{"dry_run":false,"partitions":[{"partition":0,"previous_offset":3,"new_offset":0}]}"#);
+
+    assert_eq!(post("/api/groups/billing/offsets/reset", r#"{"topic": "orders", "to": "somewhere"}"#).status(), rocket::http::Status::UnprocessableEntity);
+}
+
+#[test]
+fn test_admin_reset_subscriber()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+    client.post("/admin/topics/orders/subscribe")
+            .header(ContentType::Form)
+            .body("name=billing&endpoint=http%3A%2F%2Flocalhost%3A1%2Fhook")
+            .dispatch();
+
+    let reset = |body: &str| client.post("/admin/topics/orders/reset").header(ContentType::Form).body(body).dispatch();
+
+    let response = reset("subscription=billing&to=latest&value=&dry_run=on");
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+    assert!(response.into_string().unwrap().contains("Dry run"));
+
+    assert_eq!(reset("subscription=billing&to=offset&value=").status(), rocket::http::Status::BadRequest);
+    assert_eq!(reset("subscription=nope&to=latest").status(), rocket::http::Status::NotFound);
+}
+
+#[test]
+fn test_admin_reset_group()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    for i in 0..3 {
+        client.post("/publish/publish/orders").header(ContentType::JSON).body(format!(r#"{{"value": {i}}}"#)).dispatch();
+    }
+    client.post("/api/groups/billing/offsets")
+            .header(ContentType::JSON)
+            .body(r#"{"offsets": [{"topic": "orders", "partition": 0, "offset": 1}]}"#)
+            .dispatch();
+
+    let reset = |body: &str| client.post("/admin/topics/orders/groups/reset").header(ContentType::Form).body(body).dispatch();
+
+    let response = reset("group=billing&to=latest&value=&dry_run=on");
+    assert_eq!(response.status(), rocket::http::Status::Ok);
+    assert!(response.into_string().unwrap().contains("Dry run"));
+
+    assert_eq!(reset("group=billing&to=latest").status(), rocket::http::Status::Ok);
+    let offsets = client.get("/api/groups/billing/offsets").dispatch().into_string().unwrap();
+    assert!(offsets.contains(r#""offset":3"#));
+
+    assert_eq!(reset("group=billing&to=timestamp&value=").status(), rocket::http::Status::BadRequest);
+
+    // Group with live members has to stop first
+    client.post("/api/groups/billing/members").header(ContentType::JSON).body(r#"{"topic": "orders", "member": "a"}"#).dispatch();
+    assert_eq!(reset("group=billing&to=earliest").status(), rocket::http::Status::Conflict);
+}
+
+#[test]
+fn test_queue()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+    create_topic(&client, "orders.dlq");
+
+    let post = |url: &str, body: &str| client.post(url.to_owned()).header(ContentType::JSON).body(body).dispatch().into_string().unwrap();
+
+    post("/api/topics/orders/subscriptions", r#"{
+        "name": "jobs",
+        "mode": {"type": "queue", "visibility_timeout_ms": 200},
+        "retry_policy": {"max_attempts": 2},
+        "dead_letter_topic": "orders.dlq"}"#);
+
+    for i in 0..3 {
+        client.post("/publish/publish/orders").header(ContentType::JSON).body(format!(r#"{{"value": {i}}}"#)).dispatch();
+    }
+
+    // Competing consumers get different messages
+    let received = post("/api/topics/orders/queues/jobs/receive?max=2", "");
+    assert!(received.contains(r#""receipt":"0-0-1""#) && received.contains(r#""receipt":"0-1-1""#));
+    assert!(!received.contains(r#""offset":2"#));
+
+    let received = post("/api/topics/orders/queues/jobs/receive", "");
+    assert!(received.contains(r#""receipt":"0-2-1","partition":0,"receive_count":1,"offset":2"#));
+    assert_eq!(post("/api/topics/orders/queues/jobs/receive", ""), r#"{"messages":[]}"#);
+
+    assert_eq!(post("/api/topics/orders/queues/jobs/ack", r#"{"receipts": ["0-0-1"]}"#), r#"{"settled":1,"failed":[]}"#);
+    assert!(post("/api/topics/orders/queues/jobs/ack", r#"{"receipts": ["0-0-1", "nope"]}"#).contains(r#""settled":0"#));
+
+    // Nacked message comes back right away, until it's nacked too many times
+    assert_eq!(post("/api/topics/orders/queues/jobs/nack", r#"{"receipts": ["0-1-1"]}"#), r#"{"settled":1,"failed":[]}"#);
+    assert!(post("/api/topics/orders/queues/jobs/receive", "").contains(r#""receipt":"0-1-2""#));
+    assert_eq!(post("/api/topics/orders/queues/jobs/nack", r#"{"receipts": ["0-1-2"], "reason": "boom"}"#), r#"{"settled":1,"failed":[]}"#);
+
+    let dead_letters = client.get("/api/topics/orders.dlq/partitions/0/messages").dispatch().into_string().unwrap();
+    assert!(dead_letters.contains(r#""x-dlq-last-error":"boom""#));
+    assert!(dead_letters.contains(r#""x-dlq-original-offset":"1""#));
+
+    // Message not acked in time is received again
+    std::thread::sleep(std::time::Duration::from_millis(250));
+    let received = post("/api/topics/orders/queues/jobs/receive", "");
+    assert!(received.contains(r#""receipt":"0-2-2""#));
+    assert!(!received.contains(r#""offset":1"#));
+
+    // Late ack of the first receive is rejected
+    assert!(post("/api/topics/orders/queues/jobs/ack", r#"{"receipts": ["0-2-1"]}"#).contains(r#""settled":0"#));
+    post("/api/topics/orders/queues/jobs/ack", r#"{"receipts": ["0-2-2"]}"#);
+
+    let offsets = client.get("/publish/offset/orders/jobs").dispatch().into_string().unwrap();
+    assert!(offsets.contains(r#""committed_offset":3"#));
+
+    // Push subscriptions can't be received from, and queues can't have endpoints
+    post("/api/topics/orders/subscriptions", r#"{"name": "billing", "endpoint": "http://localhost:1/hook"}"#);
+    let response = client.post("/api/topics/orders/queues/billing/receive").dispatch();
+    assert_eq!(response.status(), rocket::http::Status::BadRequest);
+
+    let response = client.post("/api/topics/orders/subscriptions")
+                            .header(ContentType::JSON)
+                            .body(r#"{"name": "other", "endpoint": "http://localhost:1/hook", "mode": {"type": "queue"}}"#)
+                            .dispatch();
+    assert_eq!(response.status(), rocket::http::Status::BadRequest);
+}
+
+#[test]
+fn test_queue_skips_removed_messages()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+
+    let config = client.get("/api/topics/orders/config").dispatch().into_string().unwrap();
+    let config = config.replace("\"segment_size\":4096", "\"segment_size\":1").replace("\"cleanup_policy\":\"delete\"", "\"cleanup_policy\":\"compact\"");
+    client.put("/api/topics/orders/config").header(ContentType::JSON).body(config).dispatch();
+
+    let post = |url: &str, body: &str| client.post(url.to_owned()).header(ContentType::JSON).body(body).dispatch().into_string().unwrap();
+    post("/api/topics/orders/subscriptions", r#"{"name": "jobs", "mode": {"type": "queue", "visibility_timeout_ms": 100}}"#);
+
+    for key in ["a", "a", "b"] {
+        client.post("/publish/publish/orders").header(ContentType::JSON).body(format!(r#"{{"key": "{key}", "value": 0}}"#)).dispatch();
+    }
+
+    assert!(post("/api/topics/orders/queues/jobs/receive?max=1", "").contains(r#""receipt":"0-0-1""#));
+
+    // Message in flight is compacted away, a later one must not come back in its place
+    client.rocket().state::<std::sync::Arc<RetentionService>>().unwrap().clean_up();
+    std::thread::sleep(std::time::Duration::from_millis(150));
+
+    let received = post("/api/topics/orders/queues/jobs/receive?max=1", "");
+    assert!(received.contains(r#""receipt":"0-1-1""#), "{received}");
+    assert!(!received.contains(r#""receipt":"0-0-2""#));
+}
+
+#[test]
+fn test_queue_competing_consumers()
+{
+    let client = get_client();
+    create_topic(&client, "orders");
+    client.post("/api/topics/orders/subscriptions")
+        .header(ContentType::JSON)
+        .body(r#"{"name": "jobs", "mode": {"type": "queue", "visibility_timeout_ms": 60000}}"#)
+        .dispatch();
+
+    for i in 0..100 {
+        client.post("/publish/publish/orders").header(ContentType::JSON).body(format!(r#"{{"value": {i}}}"#)).dispatch();
+    }
+
+    // Consumers ack while others receive, acked messages must not come back
+    let queue_service = client.rocket().state::<std::sync::Arc<QueueService>>().unwrap();
+    let received = std::sync::Mutex::new(vec![]);
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| loop {
+                let messages = queue_service.receive("orders", "jobs", 3, None).unwrap();
+                if messages.is_empty() {
+                    break;
+                }
+
+                let receipts: Vec<String> = messages.iter().map(|message| message.receipt.clone()).collect();
+                received.lock().unwrap().extend(messages.iter().map(|message| message.message.offset));
+                queue_service.ack("orders", "jobs", &receipts).unwrap();
+            });
+        }
+    });
+
+    let mut received = received.into_inner().unwrap();
+    received.sort();
+    assert_eq!(received, (0..100).collect::<Vec<u64>>());
+}
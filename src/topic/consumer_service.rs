@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde::Serialize;
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use thiserror::Error;
+
+use crate::delivery::dead_letter_service::REDRIVE_HEADER;
+use crate::partition::partition::Offset;
+use crate::topic::log_service::{LogService, LogEntry};
+use crate::topic::publisher_service::MessagePayload;
+use crate::topic::topic_service::{TopicService, TopicServiceError};
+
+// Upper bound of messages returned by a single fetch
+pub const MAX_FETCH: usize = 1000;
+
+#[derive(Debug, Error)]
+pub enum ConsumerServiceError {
+    #[error(transparent)]
+    Topic(#[from] TopicServiceError),
+
+    #[error("Topic {0} has no partition {1}")]
+    PartitionNotFound(String, u32),
+
+    #[error("Offset {offset} is beyond the end of the partition, next offset is {high_watermark}")]
+    OffsetOutOfRange { offset: Offset, high_watermark: Offset },
+
+    #[error("Internal error when reading messages, check logs")]
+    Internal
+}
+
+impl ConsumerServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            ConsumerServiceError::Topic(error) => error.status(),
+            ConsumerServiceError::PartitionNotFound(..) => Status::NotFound,
+            ConsumerServiceError::OffsetOutOfRange { .. } => Status::RangeNotSatisfiable,
+            ConsumerServiceError::Internal => Status::InternalServerError,
+        }
+    }
+}
+
+/// A message as read by a pull consumer
+#[derive(Serialize)]
+pub struct ConsumedMessage {
+    pub offset: Offset,
+    pub timestamp: u64,
+    pub key: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    pub value: serde_json::Value,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>
+}
+
+impl ConsumedMessage {
+    pub fn from_entry(entry: LogEntry) -> serde_json::Result<Self> {
+        let message: MessagePayload = serde_json::from_str(&entry.value)?;
+
+        Ok(ConsumedMessage {
+            offset: entry.offset,
+            timestamp: entry.timestamp,
+            key: message.key,
+            headers: message.headers,
+            value: message.value,
+            schema_version: message.schema_version
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct FetchedMessages {
+    pub messages: Vec<ConsumedMessage>,
+
+    /// Offset to fetch from next time
+    pub next_offset: Offset
+}
+
+///
+/// Lets consumers read topic partitions over http, instead of having messages pushed to them.
+/// Consumers keep track of their offsets themselves. There's no subscription behind a fetch, so
+/// subscription filters don't apply - consumers get every message of the partition.
+///
+pub struct ConsumerService {
+    topic_service: Arc<TopicService>,
+    log_service: Arc<LogService>
+}
+
+impl ConsumerService {
+    pub fn new(topic_service: Arc<TopicService>, log_service: Arc<LogService>) -> Self {
+        ConsumerService { topic_service, log_service }
+    }
+
+    ///
+    /// Reads up to `max` messages (capped at `MAX_FETCH`) starting at `offset`. Fetching at the end
+    /// of the partition returns no messages - the consumer should come back later. Re-driven dead letters
+    /// and unreadable entries are skipped, `next_offset` still moves past them
+    ///
+    pub fn fetch(&self, topic_name: &str, partition: u32, offset: Offset, max: usize) -> Result<FetchedMessages, ConsumerServiceError> {
+        let topic = self.topic_service.get_topic(topic_name)?;
+
+        if partition >= topic.config.partitions {
+            return Err(ConsumerServiceError::PartitionNotFound(topic_name.to_owned(), partition));
+        }
+
+        let internal = |err: &dyn std::fmt::Display| {
+            println!("Can't read partition {partition} of {topic_name}: {err}");
+            ConsumerServiceError::Internal
+        };
+
+        let high_watermark = self.log_service.high_watermark(&topic, partition).map_err(|err| internal(&err))?;
+        if offset > high_watermark {
+            return Err(ConsumerServiceError::OffsetOutOfRange { offset, high_watermark });
+        }
+
+        let entries = self.log_service.read(&topic, partition, offset, max.min(MAX_FETCH)).map_err(|err| internal(&err))?;
+        let next_offset = entries.last().map_or(offset, |last| last.offset + 1);
+
+        let messages = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let entry_offset = entry.offset;
+
+                match ConsumedMessage::from_entry(entry) {
+                    Ok(message) => Some(message),
+                    Err(err) => {
+                        // Can't happen unless the log is corrupted, the rest of the partition is still readable
+                        println!("Skipping unreadable message {topic_name}/{partition}/{entry_offset}: {err}");
+                        None
+                    }
+                }
+            })
+            // Re-driven dead letters are only meant for the subscription that failed them, pull consumers already read the original
+            .filter(|message| !message.headers.contains_key(REDRIVE_HEADER))
+            .collect();
+
+        Ok(FetchedMessages { messages, next_offset })
+    }
+}
@@ -0,0 +1,355 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use rocket::serde::json::serde_json::{self, Value};
+
+#[cfg(test)]
+use crate::topic::publisher_service::MessagePayload;
+
+///
+/// Expression that decides which messages a subscription gets, e.g.
+/// `type == "order.created" && region in ["eu", "us"]`.
+///
+/// Supports `==`, `!=`, `<`, `<=`, `>`, `>=`, `in [...]`, `&&`, `||`, `!` and parentheses.
+/// Names are resolved against the message:
+/// - `key` is the message key
+/// - `headers.<name>` is a header
+/// - `value.<path>` is a field of the payload, `value` alone is the whole payload
+/// - any other name (or dotted path) is a field of the payload, or a header if the payload has no such field
+///
+/// Missing fields are `null`.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter(Expr);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CompareOp, Operand),
+    In(Operand, Vec<Value>),
+    Truthy(Operand)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Literal(Value),
+    Path(Vec<String>)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq, Ne, Lt, Le, Gt, Ge
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Op(&'static str),
+    In
+}
+
+impl Filter {
+    pub fn parse(expression: &str) -> Result<Filter, String> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser { tokens, position: 0 };
+
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(format!("unexpected {token:?}"));
+        }
+
+        Ok(Filter(expr))
+    }
+
+    pub fn matches(&self, key: Option<&str>, headers: &BTreeMap<String, String>, value: &Value) -> bool {
+        self.0.eval(&Message { key, headers, value })
+    }
+}
+
+/// Parts of a message filters can look at
+struct Message<'a> {
+    key: Option<&'a str>,
+    headers: &'a BTreeMap<String, String>,
+    value: &'a Value
+}
+
+impl Expr {
+    fn eval(&self, message: &Message) -> bool {
+        match self {
+            Expr::Or(left, right) => left.eval(message) || right.eval(message),
+            Expr::And(left, right) => left.eval(message) && right.eval(message),
+            Expr::Not(expr) => !expr.eval(message),
+            Expr::Compare(left, op, right) => compare(&left.resolve(message), *op, &right.resolve(message)),
+            Expr::In(operand, list) => {
+                let value = operand.resolve(message);
+                list.iter().any(|item| compare(&value, CompareOp::Eq, item))
+            }
+            Expr::Truthy(operand) => match operand.resolve(message) {
+                Value::Null | Value::Bool(false) => false,
+                Value::String(string) => !string.is_empty(),
+                _ => true
+            }
+        }
+    }
+}
+
+impl Operand {
+    fn resolve(&self, message: &Message) -> Value {
+        let path = match self {
+            Operand::Literal(value) => return value.clone(),
+            Operand::Path(path) => path
+        };
+
+        let header = |name: &str| message.headers.get(name).map_or(Value::Null, |value| Value::String(value.clone()));
+
+        match path.as_slice() {
+            [key] if key == "key" => message.key.map_or(Value::Null, |key| Value::String(key.to_owned())),
+            [headers, name] if headers == "headers" => header(name),
+            [value, rest @ ..] if value == "value" => field(message.value, rest),
+            _ => match field(message.value, path) {
+                Value::Null if path.len() == 1 => header(&path[0]),
+                value => value
+            }
+        }
+    }
+}
+
+fn field(value: &Value, path: &[String]) -> Value {
+    let mut value = value;
+
+    for name in path {
+        value = match value {
+            Value::Object(fields) => match fields.get(name) {
+                Some(value) => value,
+                None => return Value::Null
+            },
+            Value::Array(items) => match name.parse::<usize>().ok().and_then(|index| items.get(index)) {
+                Some(value) => value,
+                None => return Value::Null
+            },
+            _ => return Value::Null
+        };
+    }
+
+    value.clone()
+}
+
+fn compare(left: &Value, op: CompareOp, right: &Value) -> bool {
+    let ordering = match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64().partial_cmp(&right.as_f64()),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        (left, right) if left == right => Some(Ordering::Equal),
+        _ => None
+    };
+
+    match op {
+        CompareOp::Eq => ordering == Some(Ordering::Equal),
+        CompareOp::Ne => ordering != Some(Ordering::Equal),
+        CompareOp::Lt => ordering == Some(Ordering::Less),
+        CompareOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        CompareOp::Gt => ordering == Some(Ordering::Greater),
+        CompareOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+    }
+}
+
+// Longer operators first, so `<=` isn't read as `<`
+const OPERATORS: [&str; 14] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "(", ")", "[", "]", ","];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = expression.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+        else if rest.starts_with('"') {
+            // Strings are json strings, escapes included
+            let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<Value>();
+            let value = stream.next()
+                .ok_or("unterminated string")?
+                .map_err(|err| format!("invalid string: {err}"))?;
+
+            tokens.push(Token::Literal(value));
+            rest = &rest[stream.byte_offset()..];
+        }
+        else {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected character '{}'", rest.chars().next().unwrap()));
+            }
+
+            let word = &rest[..end];
+            tokens.push(match word {
+                "true" => Token::Literal(Value::Bool(true)),
+                "false" => Token::Literal(Value::Bool(false)),
+                "null" => Token::Literal(Value::Null),
+                "in" => Token::In,
+                "and" => Token::Op("&&"),
+                "or" => Token::Op("||"),
+                "not" => Token::Op("!"),
+                _ => match serde_json::from_str::<serde_json::Number>(word) {
+                    Ok(number) => Token::Literal(Value::Number(number)),
+                    Err(_) if word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => Token::Ident(word.to_owned()),
+                    Err(_) => return Err(format!("invalid name or number '{word}'"))
+                }
+            });
+            rest = &rest[end..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("unexpected end of expression")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(token)) if *token == op) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.eat(op) {
+            true => Ok(()),
+            false => Err(format!("expected '{op}'"))
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+
+        if self.eat("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.operand()?;
+
+        if self.peek() == Some(&Token::In) {
+            self.position += 1;
+            return Ok(Expr::In(left, self.list()?));
+        }
+
+        let op = match self.peek() {
+            Some(Token::Op("==")) => CompareOp::Eq,
+            Some(Token::Op("!=")) => CompareOp::Ne,
+            Some(Token::Op("<")) => CompareOp::Lt,
+            Some(Token::Op("<=")) => CompareOp::Le,
+            Some(Token::Op(">")) => CompareOp::Gt,
+            Some(Token::Op(">=")) => CompareOp::Ge,
+            _ => return Ok(Expr::Truthy(left))
+        };
+
+        self.position += 1;
+        Ok(Expr::Compare(left, op, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next()? {
+            Token::Literal(value) => Ok(Operand::Literal(value)),
+            Token::Ident(name) => Ok(Operand::Path(name.split('.').map(str::to_owned).collect())),
+            token => Err(format!("expected a name or a value, got {token:?}"))
+        }
+    }
+
+    fn list(&mut self) -> Result<Vec<Value>, String> {
+        self.expect("[")?;
+
+        let mut items = vec![];
+        if self.eat("]") {
+            return Ok(items);
+        }
+
+        loop {
+            match self.next()? {
+                Token::Literal(value) => items.push(value),
+                token => return Err(format!("lists can only hold values, got {token:?}"))
+            }
+
+            if self.eat("]") {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+}
+
+#[cfg(test)]
+fn message(json: &str) -> MessagePayload {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn test_filter_comparisons() {
+    let order = message(r#"{"key": "k1", "headers": {"source": "web"}, "value": {"type": "order.created", "total": 120, "customer": {"region": "eu"}}}"#);
+    let matches = |expression: &str| Filter::parse(expression).unwrap().matches(order.key.as_deref(), &order.headers, &order.value);
+
+    assert!(matches(r#"type == "order.created""#));
+    assert!(!matches(r#"type == "order.deleted""#));
+    assert!(matches(r#"type != "order.deleted""#));
+    assert!(matches("total > 100 && total <= 120"));
+    assert!(matches(r#"customer.region in ["eu", "us"]"#));
+    assert!(!matches(r#"customer.region in ["us"]"#));
+    assert!(matches(r#"source == "web" && headers.source == "web" && key == "k1""#));
+    assert!(matches(r#"value.type == "order.created""#));
+    assert!(matches(r#"!(total < 100) or missing == null"#));
+    assert!(!matches("missing"));
+    assert!(matches("customer"));
+}
+
+#[test]
+fn test_filter_parse_errors() {
+    assert!(Filter::parse("").is_err());
+    assert!(Filter::parse("type ==").is_err());
+    assert!(Filter::parse(r#"type == "unterminated"#).is_err());
+    assert!(Filter::parse("(a == 1").is_err());
+    assert!(Filter::parse("a in [b]").is_err());
+    assert!(Filter::parse("a == 1 b").is_err());
+    assert!(Filter::parse("a # 1").is_err());
+}
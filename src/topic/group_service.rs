@@ -0,0 +1,544 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use kopperdb::kopper::KopperError;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Serialize, Deserialize};
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use thiserror::Error;
+
+use crate::partition::partition::Offset;
+use crate::topic::log_service::LogService;
+use crate::topic::topic_service::{check_name, TopicService, TopicServiceError};
+use crate::storage::database::Database;
+
+// Consumers can keep a bit of their own state next to a committed offset
+const MAX_METADATA_LENGTH: usize = 4096;
+
+// Bounds of the session timeout members can ask for
+const MIN_SESSION_TIMEOUT_MS: u64 = 100;
+const MAX_SESSION_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+pub const DEFAULT_SESSION_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Debug, Error)]
+pub enum GroupServiceError {
+    #[error(transparent)]
+    Topic(#[from] TopicServiceError),
+
+    #[error("Invalid group: {0}")]
+    InvalidGroup(String),
+
+    #[error("Group {0} doesn't exist")]
+    GroupNotFound(String),
+
+    #[error("Member {1} of group {0} doesn't exist, it has to join again")]
+    MemberNotFound(String, String),
+
+    #[error("Group {0} has live members, they have to leave first")]
+    GroupActive(String),
+
+    #[error("Group {0} has live members, offsets can only be committed by a member with its generation")]
+    MemberRequired(String),
+
+    #[error("Group {group} consumes topic {topic}")]
+    TopicMismatch { group: String, topic: String },
+
+    #[error("Group was rebalanced, current generation is {0}")]
+    StaleGeneration(u32),
+
+    #[error("Partition {0} is not assigned to the member")]
+    NotAssigned(u32),
+
+    #[error("Invalid offset commit: {0}")]
+    InvalidCommit(String),
+
+    #[error(transparent)]
+    Serde(#[from] serde_json::error::Error),
+
+    #[error("Internal database error, check logs")]
+    DatabaseError
+}
+
+impl GroupServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            GroupServiceError::Topic(error) => error.status(),
+            GroupServiceError::InvalidGroup(_) | GroupServiceError::InvalidCommit(_) => Status::BadRequest,
+            GroupServiceError::GroupNotFound(_) | GroupServiceError::MemberNotFound(..) => Status::NotFound,
+            GroupServiceError::GroupActive(_)
+                | GroupServiceError::MemberRequired(_)
+                | GroupServiceError::TopicMismatch { .. }
+                | GroupServiceError::StaleGeneration(_)
+                | GroupServiceError::NotAssigned(_) => Status::Conflict,
+            GroupServiceError::Serde(_) | GroupServiceError::DatabaseError => Status::InternalServerError,
+        }
+    }
+}
+
+/// What a member is supposed to consume
+#[derive(Debug, Serialize)]
+pub struct Assignment {
+    pub group: String,
+    pub member: String,
+    pub topic: String,
+
+    /// Bumped on every rebalance
+    pub generation: u32,
+    pub partitions: Vec<u32>
+}
+
+#[derive(Serialize)]
+pub struct MemberDescription {
+    pub member: String,
+    pub partitions: Vec<u32>
+}
+
+#[derive(Serialize)]
+pub struct GroupDescription {
+    pub group: String,
+    pub topic: String,
+    pub generation: u32,
+    pub members: Vec<MemberDescription>,
+
+    /// Committed offsets of the consumed topic, by partition
+    pub offsets: BTreeMap<u32, Offset>
+}
+
+/// Offset of a single topic partition, committed by a group
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OffsetCommit {
+    pub topic: String,
+    pub partition: u32,
+
+    /// Offset of the next message the group wants to consume
+    pub offset: Offset,
+
+    /// Anything the consumer wants to keep along with the offset
+    #[serde(default)]
+    pub metadata: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+struct CommittedOffset {
+    offset: Offset,
+
+    #[serde(default)]
+    metadata: Option<String>
+}
+
+///
+/// Committed offsets of a group, by topic and partition.
+/// Stored in the db under the `groups/<group name>/offsets` key
+///
+#[derive(Default, Serialize, Deserialize)]
+struct GroupOffsets(BTreeMap<String, BTreeMap<u32, CommittedOffset>>);
+
+impl GroupOffsets {
+    fn key(group_name: &str) -> String {
+        format!("groups/{group_name}/offsets")
+    }
+}
+
+struct Member {
+    session_timeout: Duration,
+    last_heartbeat: Instant
+}
+
+// Membership is only kept in memory. After a restart members find out
+// they're unknown on their next heartbeat and join again
+struct GroupState {
+    topic: String,
+    generation: u32,
+    members: BTreeMap<String, Member>,
+    assignment: BTreeMap<String, Vec<u32>>,
+    partitions: u32
+}
+
+impl GroupState {
+    /// Drops members that stopped sending heartbeats
+    fn expire_members(&mut self, now: Instant) {
+        self.members.retain(|_, member| now.duration_since(member.last_heartbeat) <= member.session_timeout);
+    }
+
+    ///
+    /// Spreads partitions evenly between live members - partition `p` goes to the `p % n`-th member,
+    /// in name order. Generation only changes when the assignment does
+    ///
+    fn rebalance(&mut self, partitions: u32) {
+        let members: Vec<&String> = self.members.keys().collect();
+        let mut assignment: BTreeMap<String, Vec<u32>> = members.iter().map(|&member| (member.clone(), vec![])).collect();
+
+        if !members.is_empty() {
+            for partition in 0..partitions {
+                let member = members[partition as usize % members.len()];
+                assignment.get_mut(member).unwrap().push(partition);
+            }
+        }
+
+        self.partitions = partitions;
+
+        if assignment != self.assignment {
+            self.assignment = assignment;
+            self.generation += 1;
+        }
+    }
+
+    fn assignment(&self, group_name: &str, member_name: &str) -> Assignment {
+        Assignment {
+            group: group_name.to_owned(),
+            member: member_name.to_owned(),
+            topic: self.topic.clone(),
+            generation: self.generation,
+            partitions: self.assignment.get(member_name).cloned().unwrap_or_default()
+        }
+    }
+}
+
+///
+/// Consumer groups let many replicas of a service share the partitions of a topic. Members join
+/// and keep sending heartbeats, every partition is assigned to exactly one live member. Partitions
+/// are reassigned when members join, leave, miss heartbeats, or the topic gets more partitions.
+///
+pub struct GroupService {
+    topic_service: Arc<TopicService>,
+    log_service: Arc<LogService>,
+    db: Database,
+
+    groups: Mutex<HashMap<String, GroupState>>,
+
+    // Committing is a read-modify-write of the group's offsets
+    metadata_lock: Mutex<()>
+}
+
+impl GroupService {
+    pub fn new(topic_service: Arc<TopicService>, log_service: Arc<LogService>, db: Database) -> Self {
+        GroupService {
+            topic_service,
+            log_service,
+            db,
+            groups: Mutex::new(HashMap::new()),
+            metadata_lock: Mutex::new(())
+        }
+    }
+
+    ///
+    /// Adds a member to the group, or refreshes it if it's already there. Member name is generated
+    /// if not given. A group consumes a single topic, joining with another one is rejected while
+    /// the group has members
+    ///
+    pub fn join(&self, group_name: &str, topic_name: &str, member_name: Option<&str>, session_timeout_ms: u64) -> Result<Assignment, GroupServiceError> {
+        check_name(group_name).map_err(|reason| GroupServiceError::InvalidGroup(format!("name '{group_name}': {reason}")))?;
+
+        let member_name = match member_name {
+            Some(name) => {
+                check_name(name).map_err(|reason| GroupServiceError::InvalidGroup(format!("member '{name}': {reason}")))?;
+                name.to_owned()
+            }
+            None => format!("{group_name}-{}", rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(8)
+                .map(char::from)
+                .collect::<String>())
+        };
+
+        if !(MIN_SESSION_TIMEOUT_MS..=MAX_SESSION_TIMEOUT_MS).contains(&session_timeout_ms) {
+            return Err(GroupServiceError::InvalidGroup(format!(
+                "session_timeout_ms must be between {MIN_SESSION_TIMEOUT_MS} and {MAX_SESSION_TIMEOUT_MS}")));
+        }
+
+        let topic = self.topic_service.get_topic(topic_name)?;
+        let now = Instant::now();
+
+        let mut groups = self.groups.lock().unwrap();
+        let group = groups.entry(group_name.to_owned()).or_insert_with(|| GroupState {
+            topic: topic.name.clone(),
+            generation: 0,
+            members: BTreeMap::new(),
+            assignment: BTreeMap::new(),
+            partitions: 0
+        });
+
+        group.expire_members(now);
+
+        if group.topic != topic.name {
+            match group.members.is_empty() {
+                true => group.topic = topic.name.clone(),
+                false => return Err(GroupServiceError::TopicMismatch { group: group_name.to_owned(), topic: group.topic.clone() }),
+            }
+        }
+
+        group.members.insert(member_name.clone(), Member {
+            session_timeout: Duration::from_millis(session_timeout_ms),
+            last_heartbeat: now
+        });
+
+        group.rebalance(topic.config.partitions);
+        Ok(group.assignment(group_name, &member_name))
+    }
+
+    ///
+    /// Keeps the member alive and returns its current assignment, which changes after rebalances.
+    /// Offsets can be committed along the way, but only for partitions the member owns in the given generation
+    ///
+    pub fn heartbeat(&self, group_name: &str, member_name: &str, generation: Option<u32>, offsets: &BTreeMap<u32, Offset>) -> Result<Assignment, GroupServiceError> {
+        let assignment = {
+            let mut groups = self.groups.lock().unwrap();
+            let group = self.live_group(&mut groups, group_name)?;
+
+            let member = group.members.get_mut(member_name)
+                .ok_or_else(|| GroupServiceError::MemberNotFound(group_name.to_owned(), member_name.to_owned()))?;
+            member.last_heartbeat = Instant::now();
+
+            group.assignment(group_name, member_name)
+        };
+
+        if !offsets.is_empty() {
+            let commits: Vec<OffsetCommit> = offsets.iter()
+                .map(|(&partition, &offset)| OffsetCommit { topic: assignment.topic.clone(), partition, offset, metadata: None })
+                .collect();
+
+            Self::fence(&assignment, generation, &commits)?;
+            self.commit_offsets(group_name, &commits)?;
+        }
+
+        Ok(assignment)
+    }
+
+    ///
+    /// Commits offsets on behalf of a member. While the group has live members, only the member owning
+    /// the partitions in the given generation can commit them, same as with heartbeats. Commits naming
+    /// a member or generation are rejected once the group has none
+    ///
+    pub fn commit_member_offsets(&self, group_name: &str, member_name: Option<&str>, generation: Option<u32>, commits: &[OffsetCommit]) -> Result<(), GroupServiceError> {
+        let assignment = {
+            let mut groups = self.groups.lock().unwrap();
+
+            match self.live_group(&mut groups, group_name) {
+                Ok(group) if !group.members.is_empty() => {
+                    let member_name = member_name.ok_or_else(|| GroupServiceError::MemberRequired(group_name.to_owned()))?;
+                    if !group.members.contains_key(member_name) {
+                        return Err(GroupServiceError::MemberNotFound(group_name.to_owned(), member_name.to_owned()));
+                    }
+
+                    Some(group.assignment(group_name, member_name))
+                }
+
+                // Commit meant for a membership that's gone, e.g. expired or lost in a restart
+                Ok(group) if member_name.is_some() || generation.is_some() => {
+                    return Err(GroupServiceError::StaleGeneration(group.generation));
+                }
+                Err(err) if member_name.is_some() || generation.is_some() => return Err(err),
+
+                _ => None
+            }
+        };
+
+        if let Some(assignment) = assignment {
+            Self::fence(&assignment, generation, commits)?;
+        }
+
+        self.commit_offsets(group_name, commits)
+    }
+
+    /// Removes the member, its partitions go to the others right away
+    pub fn leave(&self, group_name: &str, member_name: &str) -> Result<(), GroupServiceError> {
+        let mut groups = self.groups.lock().unwrap();
+        let group = self.live_group(&mut groups, group_name)?;
+
+        if group.members.remove(member_name).is_none() {
+            return Err(GroupServiceError::MemberNotFound(group_name.to_owned(), member_name.to_owned()));
+        }
+
+        let partitions = group.partitions;
+        group.rebalance(partitions);
+        Ok(())
+    }
+
+    /// True if the group has live members
+    pub fn is_active(&self, group_name: &str) -> bool {
+        let mut groups = self.groups.lock().unwrap();
+
+        match self.live_group(&mut groups, group_name) {
+            Ok(group) => !group.members.is_empty(),
+            Err(_) => false
+        }
+    }
+
+    pub fn describe(&self, group_name: &str) -> Result<GroupDescription, GroupServiceError> {
+        let (topic, generation, members) = {
+            let mut groups = self.groups.lock().unwrap();
+            let group = self.live_group(&mut groups, group_name)?;
+
+            let members = group.assignment.iter()
+                .map(|(member, partitions)| MemberDescription { member: member.clone(), partitions: partitions.clone() })
+                .collect();
+
+            (group.topic.clone(), group.generation, members)
+        };
+
+        let offsets = self.load_offsets(group_name)?.0
+            .remove(&topic)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(partition, committed)| (partition, committed.offset))
+            .collect();
+
+        Ok(GroupDescription { group: group_name.to_owned(), topic, generation, members, offsets })
+    }
+
+    ///
+    /// Saves offsets of the group. Every offset has to point at an existing message or the end of
+    /// its partition. Either all offsets are committed or none
+    ///
+    pub fn commit_offsets(&self, group_name: &str, commits: &[OffsetCommit]) -> Result<(), GroupServiceError> {
+        check_name(group_name).map_err(|reason| GroupServiceError::InvalidGroup(format!("name '{group_name}': {reason}")))?;
+
+        for commit in commits {
+            self.validate_commit(commit)?;
+        }
+
+        let _guard = self.metadata_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let mut group_offsets = self.load_offsets(group_name)?;
+        for commit in commits {
+            group_offsets.0.entry(commit.topic.clone()).or_default().insert(commit.partition, CommittedOffset {
+                offset: commit.offset,
+                metadata: commit.metadata.clone()
+            });
+        }
+
+        if let Err(err) = self.db.write(&GroupOffsets::key(group_name), &serde_json::to_string(&group_offsets)?) {
+            println!("Kopper error when writing {err}");
+            return Err(GroupServiceError::DatabaseError);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Offsets committed by the group, for all topics or just the given one
+    ///
+    pub fn fetch_offsets(&self, group_name: &str, topic_name: Option<&str>) -> Result<Vec<OffsetCommit>, GroupServiceError> {
+        let mut commits = vec![];
+
+        for (topic, partitions) in self.load_offsets(group_name)?.0 {
+            if topic_name.is_some_and(|topic_name| topic_name != topic) {
+                continue;
+            }
+
+            for (partition, committed) in partitions {
+                commits.push(OffsetCommit { topic: topic.clone(), partition, offset: committed.offset, metadata: committed.metadata });
+            }
+        }
+
+        Ok(commits)
+    }
+
+    fn validate_commit(&self, commit: &OffsetCommit) -> Result<(), GroupServiceError> {
+        let topic = self.topic_service.get_topic(&commit.topic)?;
+
+        if commit.partition >= topic.config.partitions {
+            return Err(GroupServiceError::InvalidCommit(format!("topic {} has no partition {}", topic.name, commit.partition)));
+        }
+
+        if commit.metadata.as_ref().is_some_and(|metadata| metadata.len() > MAX_METADATA_LENGTH) {
+            return Err(GroupServiceError::InvalidCommit(format!("metadata can't be longer than {MAX_METADATA_LENGTH} bytes")));
+        }
+
+        let high_watermark = self.log_service.high_watermark(&topic, commit.partition).map_err(|err| {
+            println!("Can't open partition {} of {}: {err}", commit.partition, topic.name);
+            GroupServiceError::DatabaseError
+        })?;
+
+        if commit.offset > high_watermark {
+            return Err(GroupServiceError::InvalidCommit(format!(
+                "offset {} is beyond the end of {}/{}, next offset is {high_watermark}", commit.offset, topic.name, commit.partition)));
+        }
+
+        Ok(())
+    }
+
+    fn load_offsets(&self, group_name: &str) -> Result<GroupOffsets, GroupServiceError> {
+        match self.db.read(&GroupOffsets::key(group_name)) {
+            Ok(serialized_offsets) => Ok(serde_json::from_str(&serialized_offsets)?),
+
+            // Group never committed anything
+            Err(KopperError::KeyDoesNotExist(_)) => Ok(GroupOffsets::default()),
+
+            Err(err) => {
+                println!("Kopper error when reading {err}");
+                Err(GroupServiceError::DatabaseError)
+            }
+        }
+    }
+
+    ///
+    /// Rejects commits of a member that missed a rebalance, or of partitions it doesn't own
+    ///
+    fn fence(assignment: &Assignment, generation: Option<u32>, commits: &[OffsetCommit]) -> Result<(), GroupServiceError> {
+        if generation != Some(assignment.generation) {
+            return Err(GroupServiceError::StaleGeneration(assignment.generation));
+        }
+
+        for commit in commits {
+            if commit.topic != assignment.topic {
+                return Err(GroupServiceError::TopicMismatch { group: assignment.group.clone(), topic: assignment.topic.clone() });
+            }
+
+            if !assignment.partitions.contains(&commit.partition) {
+                return Err(GroupServiceError::NotAssigned(commit.partition));
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Finds the group and brings it up to date - expired members are dropped and
+    /// partitions rebalanced if anything changed, including the partition count of the topic
+    ///
+    fn live_group<'a>(&self, groups: &'a mut HashMap<String, GroupState>, group_name: &str) -> Result<&'a mut GroupState, GroupServiceError> {
+        let group = groups.get_mut(group_name).ok_or_else(|| GroupServiceError::GroupNotFound(group_name.to_owned()))?;
+
+        let partitions = match self.topic_service.get_topic(&group.topic) {
+            Ok(topic) => topic.config.partitions,
+            Err(err) => {
+                println!("Can't read topic {} of group {group_name}: {err}", group.topic);
+                group.partitions
+            }
+        };
+
+        group.expire_members(Instant::now());
+        group.rebalance(partitions);
+
+        Ok(group)
+    }
+}
+
+#[test]
+fn test_rebalance_spreads_partitions() {
+    let member = || Member { session_timeout: Duration::from_secs(1), last_heartbeat: Instant::now() };
+    let mut group = GroupState { topic: "t".to_owned(), generation: 0, members: BTreeMap::new(), assignment: BTreeMap::new(), partitions: 0 };
+
+    group.members.insert("a".to_owned(), member());
+    group.rebalance(3);
+    assert_eq!(group.generation, 1);
+    assert_eq!(group.assignment["a"], vec![0, 1, 2]);
+
+    group.members.insert("b".to_owned(), member());
+    group.rebalance(3);
+    assert_eq!(group.generation, 2);
+    assert_eq!(group.assignment["a"], vec![0, 2]);
+    assert_eq!(group.assignment["b"], vec![1]);
+
+    // Nothing changed, same generation
+    group.rebalance(3);
+    assert_eq!(group.generation, 2);
+
+    // More members than partitions, someone stays idle
+    group.members.insert("c".to_owned(), member());
+    group.rebalance(2);
+    assert_eq!(group.assignment["c"], Vec::<u32>::new());
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde::Deserialize;
+use rocket::serde::json::serde_json;
+
+use crate::partition::partition::{Partition, PartitionError, Offset};
+use crate::topic::topic_config::{CleanupPolicy, DurabilityMode};
+use crate::topic::topic_service::TopicEntry;
+
+type PartitionRef = Arc<Mutex<Partition>>;
+
+// How many entries are read at once when scanning a partition
+const SCAN_BATCH: usize = 500;
+
+// Just the key of a published message, it's all compaction needs
+#[derive(Deserialize)]
+struct KeyOnly {
+    key: Option<String>
+}
+
+/// Owned copy of a single entry read from the log
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub offset: Offset,
+    pub timestamp: u64,
+    pub value: String
+}
+
+///
+/// Owns the on-disk logs of all topics. Every topic partition lives in its own folder:
+/// `<path>/<topic name>/<partition number>`. Partitions are opened (or recovered) lazily,
+/// the first time they're used.
+///
+pub struct LogService {
+    path: String,
+    partitions: Mutex<HashMap<(String, u32), PartitionRef>>
+}
+
+impl LogService {
+    pub fn new(path: &str) -> Self {
+        LogService { path: path.to_owned(), partitions: Mutex::new(HashMap::new()) }
+    }
+
+    ///
+    /// Appends a value to the given partition of a topic. Returns offset of the new entry
+    ///
+    pub fn append(&self, topic: &TopicEntry, partition: u32, value: &str) -> Result<Offset, PartitionError> {
+        let partition = self.partition(topic, partition)?;
+        let mut partition = partition.lock().unwrap();
+
+        // Config can change at runtime, so it's reapplied every time
+        partition.set_seg_length(topic.config.segment_size);
+
+        let offset = partition.produce(value)?;
+
+        if topic.config.durability == DurabilityMode::Fsync {
+            partition.sync()?;
+        }
+
+        Ok(offset)
+    }
+
+    ///
+    /// Reads up to `max_count` entries, starting at `offset`. Returns fewer entries 
+    /// (or none at all) if the end of the partition is reached.
+    ///
+    pub fn read(&self, topic: &TopicEntry, partition: u32, offset: Offset, max_count: usize) -> Result<Vec<LogEntry>, PartitionError> {
+        let partition = self.partition(topic, partition)?;
+        let partition = partition.lock().unwrap();
+
+        // Entries before the first one were cleaned up, reading continues with what's left
+        let mut entries = vec![];
+        let mut next_offset = match partition.first_offset() {
+            Ok(first_offset) => offset.max(first_offset),
+            Err(_) => offset
+        };
+
+        // Every consume returns one segment, keep going until we have enough or hit the end
+        while entries.len() < max_count && next_offset < partition.next_offset() {
+            let collection = partition.consume(next_offset)?;
+            let segment_start = next_offset;
+
+            while let Some(entry) = collection.next()? {
+                entries.push(LogEntry { offset: entry.offset, timestamp: entry.timestamp, value: entry.value.to_owned() });
+                next_offset = entry.offset + 1;
+
+                if entries.len() == max_count {
+                    break;
+                }
+            }
+
+            // Segment had nothing for us, don't spin on it forever
+            if next_offset == segment_start {
+                break;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    ///
+    /// Offset the next appended entry will get, i.e. one past the last entry of the partition
+    ///
+    pub fn high_watermark(&self, topic: &TopicEntry, partition: u32) -> Result<Offset, PartitionError> {
+        Ok(self.partition(topic, partition)?.lock().unwrap().next_offset())
+    }
+
+    ///
+    /// Offset of the earliest entry still kept in the partition. Same as the high-water mark if it's empty
+    ///
+    pub fn first_offset(&self, topic: &TopicEntry, partition: u32) -> Result<Offset, PartitionError> {
+        let partition = self.partition(topic, partition)?;
+        let partition = partition.lock().unwrap();
+
+        match partition.first_offset() {
+            Err(PartitionError::NoFirstOffset) => Ok(partition.next_offset()),
+            result => result
+        }
+    }
+
+    ///
+    /// Offset of the first entry appended at or after `timestamp` (seconds since epoch). Same as the
+    /// high-water mark if there's no such entry. Entries are scanned from the start of the partition
+    ///
+    pub fn offset_for_timestamp(&self, topic: &TopicEntry, partition: u32, timestamp: u64) -> Result<Offset, PartitionError> {
+        let mut offset = self.first_offset(topic, partition)?;
+
+        loop {
+            let entries = self.read(topic, partition, offset, SCAN_BATCH)?;
+
+            let Some(last) = entries.last() else { return Ok(offset) };
+            offset = last.offset + 1;
+
+            if let Some(entry) = entries.iter().find(|entry| entry.timestamp >= timestamp) {
+                return Ok(entry.offset);
+            }
+        }
+    }
+
+    ///
+    /// Applies the topic's cleanup policy to the partition. `Delete` removes entries older than
+    /// `retention_ms`, and the oldest ones while the partition is bigger than `retention_bytes`.
+    /// `Compact` keeps only the latest entry of every key, entries without a key stay. The segment
+    /// being written to is never cleaned. Returns how many entries were removed
+    ///
+    pub fn clean(&self, topic: &TopicEntry, partition: u32) -> Result<usize, PartitionError> {
+        match topic.config.cleanup_policy {
+            CleanupPolicy::Delete => self.delete_retained(topic, partition),
+            CleanupPolicy::Compact => self.compact(topic, partition)
+        }
+    }
+
+    fn delete_retained(&self, topic: &TopicEntry, partition: u32) -> Result<usize, PartitionError> {
+        let (retention_ms, retention_bytes) = (topic.config.retention_ms, topic.config.retention_bytes);
+        if retention_ms.is_none() && retention_bytes.is_none() {
+            return Ok(0);
+        }
+
+        let now_ms = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_millis() as u64;
+
+        let partition = self.partition(topic, partition)?;
+        let mut partition = partition.lock().unwrap();
+
+        let mut size = partition.size() as u64;
+        let mut deleting = true;
+
+        // Entries go oldest first, the first one that's kept stops the deleting
+        partition.retain(|entry, entry_size| {
+            let expired = retention_ms.is_some_and(|retention_ms| entry.timestamp * 1000 + retention_ms < now_ms);
+            let over_size = retention_bytes.is_some_and(|retention_bytes| size > retention_bytes);
+
+            deleting = deleting && (expired || over_size);
+            if deleting {
+                size -= entry_size as u64;
+            }
+
+            !deleting
+        })
+    }
+
+    fn compact(&self, topic: &TopicEntry, partition: u32) -> Result<usize, PartitionError> {
+        let key = |value: &str| serde_json::from_str::<KeyOnly>(value).ok().and_then(|message| message.key);
+
+        // Latest offset of every key. Entries appended meanwhile are newer still, they can only
+        // make compaction keep more than it could
+        let mut latest = HashMap::new();
+        let mut offset = self.first_offset(topic, partition)?;
+
+        loop {
+            let entries = self.read(topic, partition, offset, SCAN_BATCH)?;
+            let Some(last) = entries.last() else { break };
+            offset = last.offset + 1;
+
+            for entry in entries {
+                if let Some(key) = key(&entry.value) {
+                    latest.insert(key, entry.offset);
+                }
+            }
+        }
+
+        let partition = self.partition(topic, partition)?;
+        let mut partition = partition.lock().unwrap();
+
+        partition.retain(|entry, _| match key(entry.value) {
+            Some(key) => latest.get(&key).is_none_or(|&latest| latest <= entry.offset),
+            None => true
+        })
+    }
+
+    fn partition(&self, topic: &TopicEntry, partition: u32) -> Result<PartitionRef, PartitionError> {
+        if partition >= topic.config.partitions {
+            return Err(PartitionError::Internal(anyhow::anyhow!(
+                "Topic {} has no partition {partition}", topic.name)));
+        }
+
+        let mut partitions = self.partitions.lock().unwrap();
+        let key = (topic.name.clone(), partition);
+
+        if let Some(partition) = partitions.get(&key) {
+            return Ok(partition.clone());
+        }
+
+        let path = format!("{}/{}/{}", self.path, topic.name, partition);
+        let new_partition = Arc::new(Mutex::new(Partition::with_seg_length(&path, topic.config.segment_size)?));
+        partitions.insert(key, new_partition.clone());
+
+        Ok(new_partition)
+    }
+}
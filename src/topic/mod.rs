@@ -1,2 +1,15 @@
 pub mod topic_service;
-pub mod publisher_service;
\ No newline at end of file
+pub mod topic_config;
+pub mod publisher_service;
+pub mod log_service;
+pub mod schema_service;
+pub mod schema_compatibility;
+pub mod offset_service;
+pub mod consumer_service;
+pub mod group_service;
+pub mod queue_service;
+pub mod retention_service;
+pub mod filter;
+
+#[cfg(test)]
+mod tests;
\ No newline at end of file
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use kopperdb::kopper::KopperError;
+use serde::{Serialize, Deserialize};
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use thiserror::Error;
+
+use crate::partition::partition::Offset;
+use crate::storage::database::Database;
+use crate::topic::group_service::{GroupService, GroupServiceError, OffsetCommit};
+use crate::topic::log_service::LogService;
+use crate::topic::topic_service::{TopicService, TopicServiceError, TopicEntry, SubscriptionEntry};
+
+#[derive(Debug, Error)]
+pub enum OffsetServiceError {
+    #[error(transparent)]
+    Topic(#[from] TopicServiceError),
+
+    #[error(transparent)]
+    Group(#[from] GroupServiceError),
+
+    #[error(transparent)]
+    Serde(#[from] serde_json::error::Error),
+
+    #[error("Internal error when reading offsets, check logs")]
+    Internal
+}
+
+impl OffsetServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            OffsetServiceError::Topic(error) => error.status(),
+            OffsetServiceError::Group(error) => error.status(),
+            OffsetServiceError::Serde(_) | OffsetServiceError::Internal => Status::InternalServerError,
+        }
+    }
+}
+
+/// Progress of a consumer in a single partition
+#[derive(Serialize)]
+pub struct PartitionOffset {
+    pub partition: u32,
+
+    /// Offset of the next message the consumer gets
+    pub committed_offset: Offset,
+
+    /// Offset the next published message gets
+    pub high_watermark: Offset,
+
+    /// How many messages the consumer is behind
+    pub lag: u64
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionOffsets {
+    pub topic: String,
+    pub subscription: String,
+    pub partitions: Vec<PartitionOffset>,
+
+    /// Sum of lags of all partitions
+    pub lag: u64
+}
+
+/// Where to move a consumer in every partition of a topic
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(tag = "to", rename_all = "snake_case")]
+pub enum ResetTarget {
+    /// First message still kept
+    Earliest,
+
+    /// Past the last message, only new ones are consumed
+    Latest,
+
+    /// Explicit offset. Partitions that don't have it get the closest one they have
+    Offset { offset: Offset },
+
+    /// First message published at or after the timestamp, in seconds since epoch
+    Timestamp { timestamp: u64 }
+}
+
+#[derive(Serialize)]
+pub struct PartitionReset {
+    pub partition: u32,
+    pub previous_offset: Offset,
+    pub new_offset: Offset
+}
+
+#[derive(Serialize)]
+pub struct OffsetReset {
+    /// True if nothing was actually changed
+    pub dry_run: bool,
+    pub partitions: Vec<PartitionReset>
+}
+
+///
+/// Offset of the next message to deliver, by partition, for one subscription. Partitions without
+/// a committed offset are delivered from the start. Stored in the db under the
+/// `offsets/<topic name>/<subscription name>` key, so commits don't rewrite the whole topic list
+///
+#[derive(Default, Serialize, Deserialize)]
+pub struct CommittedOffsets(pub BTreeMap<u32, Offset>);
+
+impl CommittedOffsets {
+    pub fn key(topic_name: &str, subscription_name: &str) -> String {
+        format!("offsets/{topic_name}/{subscription_name}")
+    }
+}
+
+///
+/// Tracks how far consumers got in topic partitions, and lets operators move them around. Committed
+/// offsets live in the metadata store, so they survive broker restarts.
+///
+pub struct OffsetService {
+    topic_service: Arc<TopicService>,
+    log_service: Arc<LogService>,
+    group_service: Arc<GroupService>,
+    db: Database
+}
+
+impl OffsetService {
+    pub fn new(topic_service: Arc<TopicService>, log_service: Arc<LogService>, group_service: Arc<GroupService>, db: Database) -> Self {
+        OffsetService { topic_service, log_service, group_service, db }
+    }
+
+    pub fn subscription_offsets(&self, topic_name: &str, subscription_name: &str) -> Result<SubscriptionOffsets, OffsetServiceError> {
+        let topic = self.topic_service.get_topic(topic_name)?;
+        let subscription = self.topic_service.get_subscription(topic_name, subscription_name)?;
+        let committed = self.committed_offsets(topic_name, &subscription)?;
+
+        let mut partitions = vec![];
+        for partition in 0..topic.config.partitions {
+            let internal = |err: &dyn std::fmt::Display| {
+                println!("Can't open partition {partition} of {topic_name}: {err}");
+                OffsetServiceError::Internal
+            };
+
+            let first_offset = self.log_service.first_offset(&topic, partition).map_err(|err| internal(&err))?;
+            let high_watermark = self.log_service.high_watermark(&topic, partition).map_err(|err| internal(&err))?;
+
+            let committed_offset = committed.get(&partition).copied().unwrap_or(0);
+
+            // Messages removed by retention can't be consumed anymore, they're not part of the lag
+            partitions.push(PartitionOffset {
+                partition,
+                committed_offset,
+                high_watermark,
+                lag: high_watermark.saturating_sub(committed_offset.max(first_offset))
+            });
+        }
+
+        Ok(SubscriptionOffsets {
+            topic: topic.name,
+            subscription: subscription.name,
+            lag: partitions.iter().map(|partition| partition.lag).sum(),
+            partitions
+        })
+    }
+
+    ///
+    /// Offsets the subscription committed, by partition
+    ///
+    pub fn committed_offsets(&self, topic_name: &str, subscription: &SubscriptionEntry) -> Result<BTreeMap<u32, Offset>, OffsetServiceError> {
+        match self.db.read(&CommittedOffsets::key(topic_name, &subscription.name)) {
+            Ok(serialized) => Ok(serde_json::from_str::<CommittedOffsets>(&serialized)?.0),
+
+            // Nothing committed yet, unless it was committed when offsets were kept in the topic list
+            Err(KopperError::KeyDoesNotExist(_)) => Ok(subscription.offsets.clone()),
+
+            Err(err) => {
+                println!("Kopper error when reading {err}");
+                Err(OffsetServiceError::Internal)
+            }
+        }
+    }
+
+    ///
+    /// Moves the committed offset of the subscription from `expected` to `offset`. Nothing is saved
+    /// and false is returned if someone else moved it in the meantime, e.g. an admin reset it
+    ///
+    pub fn commit_subscription_offset(
+        &self,
+        topic_name: &str,
+        subscription_name: &str,
+        partition: u32,
+        expected: Offset,
+        offset: Offset
+    ) -> Result<bool, OffsetServiceError> {
+
+        // Subscription can't be removed in the meantime, its offsets would outlive it
+        let _guard = self.topic_service.lock_offsets();
+        let subscription = self.topic_service.get_subscription(topic_name, subscription_name)?;
+
+        let mut committed = self.committed_offsets(topic_name, &subscription)?;
+        if committed.get(&partition).copied().unwrap_or(0) != expected {
+            return Ok(false);
+        }
+
+        committed.insert(partition, offset);
+        self.save(topic_name, subscription_name, committed)?;
+
+        Ok(true)
+    }
+
+    ///
+    /// Moves the subscription to the target in every partition. With `dry_run` only reports where
+    /// it would end up. Delivery picks up the new offsets after the batch it's working on
+    ///
+    pub fn reset_subscription(&self, topic_name: &str, subscription_name: &str, target: ResetTarget, dry_run: bool) -> Result<OffsetReset, OffsetServiceError> {
+        let _guard = self.topic_service.lock_offsets();
+
+        let topic = self.topic_service.get_topic(topic_name)?;
+        let subscription = self.topic_service.get_subscription(topic_name, subscription_name)?;
+        let committed = self.committed_offsets(topic_name, &subscription)?;
+
+        let partitions = self.resolve(&topic, target, |partition| committed.get(&partition).copied().unwrap_or(0))?;
+
+        if !dry_run {
+            self.save(topic_name, subscription_name, partitions.iter().map(|reset| (reset.partition, reset.new_offset)).collect())?;
+        }
+
+        Ok(OffsetReset { dry_run, partitions })
+    }
+
+    ///
+    /// Moves the group to the target in every partition of the topic. Members would overwrite
+    /// the new offsets with their next commit, so the group can't have any
+    ///
+    pub fn reset_group(&self, group_name: &str, topic_name: &str, target: ResetTarget, dry_run: bool) -> Result<OffsetReset, OffsetServiceError> {
+        let topic = self.topic_service.get_topic(topic_name)?;
+
+        if self.group_service.is_active(group_name) {
+            return Err(GroupServiceError::GroupActive(group_name.to_owned()).into());
+        }
+
+        let committed = self.group_service.fetch_offsets(group_name, Some(topic_name))?;
+        let partitions = self.resolve(&topic, target, |partition| committed.iter()
+            .find(|commit| commit.partition == partition)
+            .map_or(0, |commit| commit.offset))?;
+
+        if !dry_run {
+            let commits: Vec<OffsetCommit> = partitions.iter()
+                .map(|reset| OffsetCommit { topic: topic.name.clone(), partition: reset.partition, offset: reset.new_offset, metadata: None })
+                .collect();
+
+            self.group_service.commit_offsets(group_name, &commits)?;
+        }
+
+        Ok(OffsetReset { dry_run, partitions })
+    }
+
+    fn save(&self, topic_name: &str, subscription_name: &str, offsets: BTreeMap<u32, Offset>) -> Result<(), OffsetServiceError> {
+        let serialized = serde_json::to_string(&CommittedOffsets(offsets))?;
+
+        if let Err(err) = self.db.write(&CommittedOffsets::key(topic_name, subscription_name), &serialized) {
+            println!("Kopper error when writing {err}");
+            return Err(OffsetServiceError::Internal);
+        }
+
+        Ok(())
+    }
+
+    fn resolve<F>(&self, topic: &TopicEntry, target: ResetTarget, previous: F) -> Result<Vec<PartitionReset>, OffsetServiceError>
+    where
+        F: Fn(u32) -> Offset
+    {
+        let internal = |partition: u32, err: &dyn std::fmt::Display| {
+            println!("Can't read partition {partition} of {}: {err}", topic.name);
+            OffsetServiceError::Internal
+        };
+
+        (0..topic.config.partitions).map(|partition| {
+            let first_offset = self.log_service.first_offset(topic, partition).map_err(|err| internal(partition, &err))?;
+            let high_watermark = self.log_service.high_watermark(topic, partition).map_err(|err| internal(partition, &err))?;
+
+            let new_offset = match target {
+                ResetTarget::Earliest => first_offset,
+                ResetTarget::Latest => high_watermark,
+                ResetTarget::Offset { offset } => offset.clamp(first_offset, high_watermark),
+                ResetTarget::Timestamp { timestamp } => self.log_service.offset_for_timestamp(topic, partition, timestamp)
+                    .map_err(|err| internal(partition, &err))?
+            };
+
+            Ok(PartitionReset { partition, previous_offset: previous(partition), new_offset })
+        }).collect()
+    }
+}
@@ -1,46 +1,123 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
-use serde::{Deserialize, Serialize};
-
-use kopperdb::kopper::*;
-use crate::topic::topic_service::TopicService;
+use std::sync::atomic::{AtomicU32, Ordering};
 
+use serde::{Deserialize, Serialize};
+use rocket::http::Status;
 use rocket::serde::json::serde_json;
+use thiserror::Error;
 
-#[derive(Serialize, Deserialize)]
+use crate::partition::partition::Offset;
+use crate::topic::log_service::LogService;
+use crate::topic::schema_service::{SchemaService, SchemaServiceError};
+use crate::topic::topic_service::{TopicService, TopicServiceError};
+
+///
+/// A message as published by a producer. It's stored in the log as-is (serialized to json)
+///
+#[derive(Clone, Serialize, Deserialize)]
 pub struct MessagePayload {
-    _metadata: Vec<String>,
-    _message: String
+    /// Messages with the same key always end up in the same partition
+    #[serde(default)]
+    pub key: Option<String>,
+
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    pub value: serde_json::Value,
+
+    /// Version of the topic's schema the value was validated against. Set by the broker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>
+}
+
+/// Where the published message ended up
+#[derive(Serialize)]
+pub struct PublishedMessage {
+    pub partition: u32,
+    pub offset: Offset,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>
 }
 
-// I want to use TopicService in this struct to verify if topic exists.
-// Should I use reference to topic_service? Such approach requires defining lifetime annotation
 pub struct PublisherService {
     topic_service: Arc<TopicService>,
-    db: Kopper
+    schema_service: Arc<SchemaService>,
+    log_service: Arc<LogService>,
+
+    // Used to spread messages without a key evenly between partitions
+    round_robin: AtomicU32
 }
 
+#[derive(Debug, Error)]
 pub enum PublisherServiceError {
+    #[error(transparent)]
+    Topic(#[from] TopicServiceError),
+
+    #[error(transparent)]
+    Schema(#[from] SchemaServiceError),
+
+    #[error("Message has {size} bytes, but topic allows at most {max}")]
+    MessageTooLarge { size: usize, max: usize },
+
+    #[error("Internal error when publishing, check logs")]
     Internal
 }
 
+impl PublisherServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            PublisherServiceError::Topic(error) => error.status(),
+            PublisherServiceError::Schema(error) => error.status(),
+            PublisherServiceError::MessageTooLarge { .. } => Status::PayloadTooLarge,
+            PublisherServiceError::Internal => Status::InternalServerError,
+        }
+    }
+}
+
 impl PublisherService {
-    pub fn new(topic_service: Arc<TopicService>, db: Kopper) -> Self {
-        let publisher = PublisherService {
-            topic_service: topic_service, db
-        };
-        publisher
+    pub fn new(topic_service: Arc<TopicService>, schema_service: Arc<SchemaService>, log_service: Arc<LogService>) -> Self {
+        PublisherService {
+            topic_service, schema_service, log_service, round_robin: AtomicU32::new(0)
+        }
     }
 
-    pub fn publish_message(&self, topic_name: &str, _message: MessagePayload) -> Result<(), PublisherServiceError> {
-        match self.topic_service.topic_exists(topic_name) {
-            Ok(_) => (),
-            Err(_) => return Err(PublisherServiceError::Internal),
-        };
+    pub fn publish_message(&self, topic_name: &str, mut message: MessagePayload) -> Result<PublishedMessage, PublisherServiceError> {
+        let topic = self.topic_service.get_topic(topic_name)?;
+
+        // Nothing that doesn't match the schema can get to the log
+        message.schema_version = self.schema_service.validate(topic_name, &message.value)?;
 
-        let serialized_message = serde_json::to_string(&_message).expect("Failed to serialize");
+        let serialized_message = serde_json::to_string(&message).map_err(|_| PublisherServiceError::Internal)?;
 
-        // TODO: Fix this with a publisherservice errors!!!
-        self.db.write("someMessageQueueKeyOrSomething", &serialized_message).expect("Couldn't write to Kopper!");
-        Ok(())
+        if serialized_message.len() > topic.config.max_message_size {
+            return Err(PublisherServiceError::MessageTooLarge {
+                size: serialized_message.len(),
+                max: topic.config.max_message_size
+            });
+        }
+
+        let partition = self.choose_partition(message.key.as_deref(), topic.config.partitions);
+
+        let offset = self.log_service.append(&topic, partition, &serialized_message).map_err(|err| {
+            println!("Can't append to partition {partition} of {topic_name}: {err}");
+            PublisherServiceError::Internal
+        })?;
+
+        Ok(PublishedMessage { partition, offset, schema_version: message.schema_version })
     }
-}
\ No newline at end of file
+
+    fn choose_partition(&self, key: Option<&str>, partitions: u32) -> u32 {
+        match key {
+            Some(key) => {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                (hasher.finish() % partitions as u64) as u32
+            }
+            None => self.round_robin.fetch_add(1, Ordering::Relaxed) % partitions
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use rocket::http::Status;
+use thiserror::Error;
+
+use crate::delivery::dead_letter_service::{DeadLetterService, REDRIVE_HEADER};
+use crate::partition::partition::Offset;
+use crate::topic::consumer_service::{ConsumedMessage, MAX_FETCH};
+use crate::topic::log_service::{LogService, LogEntry};
+use crate::topic::offset_service::OffsetService;
+use crate::topic::topic_service::{TopicService, TopicServiceError, TopicEntry, SubscriptionEntry, SubscriptionMode};
+
+#[derive(Debug, Error)]
+pub enum QueueServiceError {
+    #[error(transparent)]
+    Topic(#[from] TopicServiceError),
+
+    #[error("Subscription {1} to topic {0} is not a queue")]
+    NotAQueue(String, String),
+
+    #[error("Internal error when reading the queue, check logs")]
+    Internal
+}
+
+impl QueueServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            QueueServiceError::Topic(error) => error.status(),
+            QueueServiceError::NotAQueue(..) => Status::BadRequest,
+            QueueServiceError::Internal => Status::InternalServerError,
+        }
+    }
+}
+
+/// A message handed out to a queue consumer
+#[derive(Serialize)]
+pub struct ReceivedMessage {
+    /// Identifies this particular receive of the message, needed to ack or nack it
+    pub receipt: String,
+    pub partition: u32,
+
+    /// How many times the message was received, this time included
+    pub receive_count: u32,
+
+    #[serde(flatten)]
+    pub message: ConsumedMessage
+}
+
+/// Receipt that couldn't be acked or nacked, and why
+#[derive(Serialize)]
+pub struct FailedReceipt {
+    pub receipt: String,
+    pub error: String
+}
+
+#[derive(Serialize)]
+pub struct Settled {
+    pub settled: usize,
+    pub failed: Vec<FailedReceipt>
+}
+
+// Message received, but not acked yet
+struct InFlight {
+    visible_at: Instant,
+    receives: u32,
+    nacks: u32
+}
+
+///
+/// In-memory state of a queue. Everything below the committed offsets of the subscription is acked,
+/// and that's all that survives a restart - messages in flight become visible again
+///
+struct QueueState {
+    // Committed offsets, as last seen or saved
+    committed: BTreeMap<u32, Offset>,
+
+    // First offset never received, by partition
+    next: BTreeMap<u32, Offset>,
+
+    in_flight: BTreeMap<(u32, Offset), InFlight>,
+
+    // Partition new messages are taken from first, rotated so none of them starves
+    first_partition: u32
+}
+
+impl QueueState {
+    fn new(committed: BTreeMap<u32, Offset>) -> Self {
+        QueueState { next: committed.clone(), committed, in_flight: BTreeMap::new(), first_partition: 0 }
+    }
+
+    /// Lowest offset that's not acked yet
+    fn floor(&self, partition: u32) -> Offset {
+        self.in_flight
+            .range((partition, 0)..=(partition, Offset::MAX))
+            .next()
+            .map_or_else(|| self.next.get(&partition).copied().unwrap_or(0), |((_, offset), _)| *offset)
+    }
+}
+
+///
+/// Work-queue semantics on top of a topic. A queue is a subscription in `queue` mode, many consumers
+/// can receive from it at once and every message goes to one of them. Consumers ack messages
+/// they're done with and nack the ones they failed. Messages that aren't acked in time come back.
+///
+pub struct QueueService {
+    topic_service: Arc<TopicService>,
+    log_service: Arc<LogService>,
+    offset_service: Arc<OffsetService>,
+    dead_letter_service: Arc<DeadLetterService>,
+
+    // By (topic name, subscription name)
+    queues: Mutex<HashMap<(String, String), QueueState>>
+}
+
+impl QueueService {
+    pub fn new(
+        topic_service: Arc<TopicService>,
+        log_service: Arc<LogService>,
+        offset_service: Arc<OffsetService>,
+        dead_letter_service: Arc<DeadLetterService>
+    ) -> Self {
+        QueueService { topic_service, log_service, offset_service, dead_letter_service, queues: Mutex::new(HashMap::new()) }
+    }
+
+    ///
+    /// Hands out up to `max` messages (capped at `MAX_FETCH`) - first the ones that became visible
+    /// again, then new ones. Received messages stay hidden for the visibility timeout, queue's default is used if not given
+    ///
+    pub fn receive(&self, topic_name: &str, subscription_name: &str, max: usize, visibility_timeout_ms: Option<u64>) -> Result<Vec<ReceivedMessage>, QueueServiceError> {
+        // Loaded under the lock, an older copy of the committed offsets would reset the queue
+        let mut queues = self.queues.lock().unwrap();
+        let (topic, subscription, default_timeout) = self.load(topic_name, subscription_name)?;
+
+        // Paused queues hand out nothing, messages in flight can still be acked
+        if subscription.paused {
+            return Ok(vec![]);
+        }
+
+        let visible_at = Instant::now() + Duration::from_millis(visibility_timeout_ms.unwrap_or(default_timeout));
+        let max = max.min(MAX_FETCH);
+
+        let committed = self.committed_offsets(topic_name, &subscription)?;
+        let queue = Self::queue(&mut queues, &subscription, committed, topic_name);
+
+        let mut received = vec![];
+        let now = Instant::now();
+
+        // Messages nobody acked in time go first
+        let expired: Vec<(u32, Offset)> = queue.in_flight.iter()
+            .filter(|(_, in_flight)| in_flight.visible_at <= now)
+            .map(|(&key, _)| key)
+            .take(max)
+            .collect();
+
+        for (partition, offset) in expired {
+            let entry = match self.entry(&topic, partition, offset) {
+                Ok(Some(entry)) => entry,
+
+                // Removed by retention or compaction, there's nothing left to hand out
+                Ok(None) => {
+                    queue.in_flight.remove(&(partition, offset));
+                    continue;
+                }
+
+                Err(err) => {
+                    println!("Can't read {topic_name}/{partition}/{offset}: {err}");
+                    return Err(QueueServiceError::Internal);
+                }
+            };
+
+            let in_flight = queue.in_flight.get_mut(&(partition, offset)).unwrap();
+            in_flight.visible_at = visible_at;
+            in_flight.receives += 1;
+
+            if let Some(message) = Self::received(topic_name, partition, entry, in_flight.receives) {
+                received.push(message);
+            }
+        }
+
+        // Then new ones, from all partitions
+        let partitions = topic.config.partitions;
+        let filter = subscription.filter();
+        for i in 0..partitions {
+            let partition = (queue.first_partition + i) % partitions;
+            let next = queue.next.get(&partition).copied().unwrap_or(0);
+
+            if received.len() == max {
+                break;
+            }
+
+            let entries = self.log_service.read(&topic, partition, next, max - received.len()).map_err(|err| {
+                println!("Can't read partition {partition} of {topic_name}: {err}");
+                QueueServiceError::Internal
+            })?;
+
+            for entry in entries {
+                let offset = entry.offset;
+                queue.next.insert(partition, offset + 1);
+
+                let Some(message) = Self::received(topic_name, partition, entry, 1) else { continue };
+
+                // Re-driven dead letters are only meant for the subscription that failed them
+                if message.message.headers.get(REDRIVE_HEADER).is_some_and(|target| target != subscription_name) {
+                    continue;
+                }
+
+                if filter.as_ref().is_some_and(|filter| !filter.matches(message.message.key.as_deref(), &message.message.headers, &message.message.value)) {
+                    continue;
+                }
+
+                queue.in_flight.insert((partition, offset), InFlight { visible_at, receives: 1, nacks: 0 });
+                received.push(message);
+            }
+        }
+
+        queue.first_partition = (queue.first_partition + 1) % partitions;
+
+        // Skipped and dropped messages might have moved the floor
+        self.commit(queue, &subscription, topic_name);
+
+        Ok(received)
+    }
+
+    /// Marks messages as done, they won't be received again
+    pub fn ack(&self, topic_name: &str, subscription_name: &str, receipts: &[String]) -> Result<Settled, QueueServiceError> {
+        self.settle(topic_name, subscription_name, receipts, |_, _, _| Ok(true))
+    }
+
+    ///
+    /// Makes messages visible again after `delay_ms`. A message nacked as many times as the retry policy
+    /// allows attempts goes to the dead letter topic, if the queue has one
+    ///
+    pub fn nack(&self, topic_name: &str, subscription_name: &str, receipts: &[String], delay_ms: u64, reason: Option<&str>) -> Result<Settled, QueueServiceError> {
+        let (topic, ..) = self.load(topic_name, subscription_name)?;
+        let visible_at = Instant::now() + Duration::from_millis(delay_ms);
+
+        self.settle(topic_name, subscription_name, receipts, |subscription, (partition, offset), in_flight| {
+            in_flight.nacks += 1;
+            in_flight.visible_at = visible_at;
+
+            if !subscription.retry_policy.is_exhausted(in_flight.nacks) || subscription.dead_letter_topic.is_none() {
+                return Ok(false);
+            }
+
+            // Removed by retention or compaction in the meantime, nothing left to dead letter
+            let Some(entry) = self.entry(&topic, partition, offset)? else {
+                return Ok(true);
+            };
+            let last_error = reason.unwrap_or("nacked");
+
+            self.dead_letter_service.dead_letter(topic_name, subscription, partition, &entry, last_error, in_flight.nacks)
+                .map(|_| true)
+                .map_err(|err| err.to_string())
+        })
+    }
+
+    ///
+    /// Runs `settle` on every in-flight message matching the receipts. Messages it returns true for
+    /// are done and removed from the queue
+    ///
+    fn settle<F>(&self, topic_name: &str, subscription_name: &str, receipts: &[String], mut settle: F) -> Result<Settled, QueueServiceError>
+    where
+        F: FnMut(&SubscriptionEntry, (u32, Offset), &mut InFlight) -> Result<bool, String>
+    {
+        let mut queues = self.queues.lock().unwrap();
+        let (_, subscription, _) = self.load(topic_name, subscription_name)?;
+        let committed = self.committed_offsets(topic_name, &subscription)?;
+        let queue = Self::queue(&mut queues, &subscription, committed, topic_name);
+
+        let mut settled = Settled { settled: 0, failed: vec![] };
+
+        for receipt in receipts {
+            let result = parse_receipt(receipt).and_then(|(key, receives)| {
+                let in_flight = match queue.in_flight.get_mut(&key) {
+                    Some(in_flight) if in_flight.receives == receives => in_flight,
+                    _ => return Err("message was acked or received again since".to_owned())
+                };
+
+                if settle(&subscription, key, in_flight)? {
+                    queue.in_flight.remove(&key);
+                }
+
+                Ok(())
+            });
+
+            match result {
+                Ok(()) => settled.settled += 1,
+                Err(error) => settled.failed.push(FailedReceipt { receipt: receipt.clone(), error })
+            }
+        }
+
+        self.commit(queue, &subscription, topic_name);
+
+        Ok(settled)
+    }
+
+    fn load(&self, topic_name: &str, subscription_name: &str) -> Result<(TopicEntry, SubscriptionEntry, u64), QueueServiceError> {
+        let topic = self.topic_service.get_topic(topic_name)?;
+        let subscription = self.topic_service.get_subscription(topic_name, subscription_name)?;
+
+        match subscription.mode {
+            SubscriptionMode::Queue { visibility_timeout_ms } => Ok((topic, subscription, visibility_timeout_ms)),
+            SubscriptionMode::Push => Err(QueueServiceError::NotAQueue(topic_name.to_owned(), subscription_name.to_owned()))
+        }
+    }
+
+    ///
+    /// Entry at exactly `offset`. Reads start at the first offset still in the log, so an entry
+    /// removed by retention or compaction would otherwise come back as a later one
+    ///
+    fn entry(&self, topic: &TopicEntry, partition: u32, offset: Offset) -> Result<Option<LogEntry>, String> {
+        match self.log_service.read(topic, partition, offset, 1) {
+            Ok(mut entries) => Ok(entries.pop().filter(|entry| entry.offset == offset)),
+            Err(err) => Err(err.to_string())
+        }
+    }
+
+    ///
+    /// State of the queue, started over if its committed offsets were moved by someone else, e.g. reset
+    ///
+    fn queue<'a>(
+        queues: &'a mut HashMap<(String, String), QueueState>,
+        subscription: &SubscriptionEntry,
+        committed: BTreeMap<u32, Offset>,
+        topic_name: &str
+    ) -> &'a mut QueueState {
+
+        let queue = queues
+            .entry((topic_name.to_owned(), subscription.name.clone()))
+            .or_insert_with(|| QueueState::new(committed.clone()));
+
+        if queue.committed != committed {
+            *queue = QueueState::new(committed);
+        }
+
+        queue
+    }
+
+    fn committed_offsets(&self, topic_name: &str, subscription: &SubscriptionEntry) -> Result<BTreeMap<u32, Offset>, QueueServiceError> {
+        self.offset_service.committed_offsets(topic_name, subscription).map_err(|err| {
+            println!("Can't load committed offsets of {topic_name}/{}: {err}", subscription.name);
+            QueueServiceError::Internal
+        })
+    }
+
+    /// Saves how far the queue is acked, in partitions where that changed
+    fn commit(&self, queue: &mut QueueState, subscription: &SubscriptionEntry, topic_name: &str) {
+        for (&partition, _) in queue.next.clone().iter() {
+            let committed = queue.committed.get(&partition).copied().unwrap_or(0);
+            let floor = queue.floor(partition);
+
+            if floor == committed {
+                continue;
+            }
+
+            match self.offset_service.commit_subscription_offset(topic_name, &subscription.name, partition, committed, floor) {
+                Ok(true) => { queue.committed.insert(partition, floor); },
+                Ok(false) => (), // Moved by someone else, the queue starts over next time
+                Err(err) => println!("Can't commit offset {floor} of {topic_name}/{partition} for {}: {err}", subscription.name)
+            }
+        }
+    }
+
+    fn received(topic_name: &str, partition: u32, entry: LogEntry, receives: u32) -> Option<ReceivedMessage> {
+        let offset = entry.offset;
+
+        match ConsumedMessage::from_entry(entry) {
+            Ok(message) => Some(ReceivedMessage {
+                receipt: format!("{partition}-{offset}-{receives}"),
+                partition,
+                receive_count: receives,
+                message
+            }),
+            Err(err) => {
+                // Can't happen unless the log is corrupted
+                println!("Skipping unreadable message {topic_name}/{partition}/{offset}: {err}");
+                None
+            }
+        }
+    }
+}
+
+/// Receipts look like `<partition>-<offset>-<receive count>`
+fn parse_receipt(receipt: &str) -> Result<((u32, Offset), u32), String> {
+    let invalid = || format!("invalid receipt '{receipt}'");
+
+    let mut parts = receipt.split('-');
+    let mut next = || parts.next().ok_or_else(invalid);
+
+    let partition = next()?.parse().map_err(|_| invalid())?;
+    let offset = next()?.parse().map_err(|_| invalid())?;
+    let receives = next()?.parse().map_err(|_| invalid())?;
+
+    if next().is_ok() {
+        return Err(invalid());
+    }
+
+    Ok(((partition, offset), receives))
+}
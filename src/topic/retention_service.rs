@@ -0,0 +1,58 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::topic::log_service::LogService;
+use crate::topic::topic_service::TopicService;
+
+// How often partitions are cleaned up
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+///
+/// Enforces retention and cleanup policies of all topics in the background, see `LogService::clean`
+///
+pub struct RetentionService {
+    topic_service: Arc<TopicService>,
+    log_service: Arc<LogService>
+}
+
+impl RetentionService {
+    ///
+    /// Creates the service and starts a background thread that cleans up every `CLEANUP_INTERVAL`
+    ///
+    pub fn start(topic_service: Arc<TopicService>, log_service: Arc<LogService>) -> Arc<Self> {
+        let service = Arc::new(RetentionService { topic_service, log_service });
+
+        let cleaner = service.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(CLEANUP_INTERVAL);
+            cleaner.clean_up();
+        });
+
+        service
+    }
+
+    ///
+    /// Cleans every partition of every topic. Returns how many entries were removed
+    ///
+    pub fn clean_up(&self) -> usize {
+        let topics = match self.topic_service.get_topics() {
+            Ok(topics) => topics,
+            Err(err) => {
+                println!("Retention service can't read topics: {err}");
+                return 0;
+            }
+        };
+
+        let mut removed = 0;
+        for topic in topics.0 {
+            for partition in 0..topic.config.partitions {
+                match self.log_service.clean(&topic, partition) {
+                    Ok(count) => removed += count,
+                    Err(err) => println!("Can't clean partition {partition} of {}: {err}", topic.name)
+                }
+            }
+        }
+
+        removed
+    }
+}
@@ -0,0 +1,214 @@
+use serde::{Serialize, Deserialize};
+use rocket::serde::json::serde_json::{Value, Map};
+
+///
+/// Decides which schema changes are allowed when registering a new version
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompatibilityMode {
+    /// Consumers using the new schema can read messages written with the previous one
+    #[default]
+    Backward,
+
+    /// Consumers using the previous schema can read messages written with the new one
+    Forward,
+
+    /// Both backward and forward
+    Full,
+
+    /// Anything goes
+    None
+}
+
+///
+/// Checks if `new` schema can replace `previous` under the given mode.
+/// Returns a list of found problems, prefixed with path to the place in the schema.
+///
+/// The check is structural, it looks at: types, required properties, enums,
+/// `additionalProperties: false`, and recurses into properties and array items.
+/// Other keywords (min/max, patterns, formats, combinators) are not compared.
+///
+pub fn check_compatibility(mode: CompatibilityMode, previous: &Value, new: &Value) -> Result<(), Vec<String>> {
+    let mut errors = vec![];
+
+    if mode == CompatibilityMode::Backward || mode == CompatibilityMode::Full {
+        can_read(new, previous, "#", &mut errors);
+    }
+
+    if mode == CompatibilityMode::Forward || mode == CompatibilityMode::Full {
+        can_read(previous, new, "#", &mut errors);
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors)
+    }
+}
+
+///
+/// Checks if every message valid against `writer` schema is also valid against `reader` schema
+///
+fn can_read(reader: &Value, writer: &Value, path: &str, errors: &mut Vec<String>) {
+
+    // `true` and `{}` accept anything, `false` accepts nothing
+    let reader = match reader {
+        Value::Bool(true) => return,
+        Value::Object(reader) if reader.is_empty() => return,
+        Value::Object(reader) => reader,
+        _ => {
+            errors.push(format!("{path}: schema rejects values the other schema accepts"));
+            return;
+        }
+    };
+
+    let empty = Map::new();
+    let writer = match writer {
+        Value::Bool(false) => return,
+        Value::Object(writer) => writer,
+        _ => &empty
+    };
+
+    check_types(reader, writer, path, errors);
+    check_enum(reader, writer, path, errors);
+    check_properties(reader, writer, path, errors);
+
+    if let Some(reader_items) = reader.get("items") {
+        let writer_items = writer.get("items").unwrap_or(&Value::Bool(true));
+        can_read(reader_items, writer_items, &format!("{path}/items"), errors);
+    }
+}
+
+fn check_types(reader: &Map<String, Value>, writer: &Map<String, Value>, path: &str, errors: &mut Vec<String>) {
+    let reader_types = match types(reader) {
+        Some(types) => types,
+        None => return, // Reader accepts any type
+    };
+
+    let writer_types = match types(writer) {
+        Some(types) => types,
+        None => {
+            errors.push(format!("{path}: type narrowed to {}", reader_types.join(", ")));
+            return;
+        }
+    };
+
+    for writer_type in writer_types {
+        // Every integer is a number, so that's a widening
+        let accepted = reader_types.contains(&writer_type)
+            || (writer_type == "integer" && reader_types.contains(&"number"));
+
+        if !accepted {
+            errors.push(format!("{path}: type {writer_type} is no longer accepted"));
+        }
+    }
+}
+
+fn types(schema: &Map<String, Value>) -> Option<Vec<&str>> {
+    match schema.get("type")? {
+        Value::String(single) => Some(vec![single.as_str()]),
+        Value::Array(many) => Some(many.iter().filter_map(Value::as_str).collect()),
+        _ => None
+    }
+}
+
+fn check_enum(reader: &Map<String, Value>, writer: &Map<String, Value>, path: &str, errors: &mut Vec<String>) {
+    let reader_enum = match reader.get("enum").and_then(Value::as_array) {
+        Some(values) => values,
+        None => return,
+    };
+
+    match writer.get("enum").and_then(Value::as_array) {
+        Some(writer_enum) => {
+            for value in writer_enum.iter().filter(|value| !reader_enum.contains(value)) {
+                errors.push(format!("{path}: enum value {value} is no longer accepted"));
+            }
+        }
+        None => errors.push(format!("{path}: enum added")),
+    }
+}
+
+fn check_properties(reader: &Map<String, Value>, writer: &Map<String, Value>, path: &str, errors: &mut Vec<String>) {
+    let required = |schema: &Map<String, Value>| -> Vec<String> {
+        schema.get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).map(str::to_owned).collect())
+            .unwrap_or_default()
+    };
+
+    // Messages written without a property can't be read by someone who requires it
+    let writer_required = required(writer);
+    for property in required(reader).iter().filter(|property| !writer_required.contains(property)) {
+        errors.push(format!("{path}: property {property} became required"));
+    }
+
+    let empty = Map::new();
+    let reader_properties = reader.get("properties").and_then(Value::as_object).unwrap_or(&empty);
+    let writer_properties = writer.get("properties").and_then(Value::as_object).unwrap_or(&empty);
+
+    for (name, reader_property) in reader_properties {
+        if let Some(writer_property) = writer_properties.get(name) {
+            can_read(reader_property, writer_property, &format!("{path}/properties/{name}"), errors);
+        }
+    }
+
+    // Closed reader schema only accepts properties it knows about
+    if reader.get("additionalProperties") == Some(&Value::Bool(false)) {
+        if writer.get("additionalProperties") != Some(&Value::Bool(false)) {
+            errors.push(format!("{path}: additional properties are no longer accepted"));
+        }
+
+        for name in writer_properties.keys().filter(|name| !reader_properties.contains_key(*name)) {
+            errors.push(format!("{path}: property {name} is no longer accepted"));
+        }
+    }
+}
+
+#[cfg(test)]
+fn check(mode: CompatibilityMode, previous: &str, new: &str) -> Result<(), Vec<String>> {
+    use rocket::serde::json::serde_json;
+    check_compatibility(mode, &serde_json::from_str(previous).unwrap(), &serde_json::from_str(new).unwrap())
+}
+
+#[test]
+fn test_adding_optional_property() {
+    let previous = r#"{"type": "object", "properties": {"id": {"type": "integer"}}, "required": ["id"]}"#;
+    let new = r#"{"type": "object", "properties": {"id": {"type": "integer"}, "note": {"type": "string"}}, "required": ["id"]}"#;
+
+    assert!(check(CompatibilityMode::Full, previous, new).is_ok());
+}
+
+#[test]
+fn test_adding_required_property() {
+    let previous = r#"{"type": "object", "properties": {"id": {"type": "integer"}}}"#;
+    let new = r#"{"type": "object", "properties": {"id": {"type": "integer"}}, "required": ["id"]}"#;
+
+    // Old messages may lack the id, but new ones are still readable by old consumers
+    assert_eq!(check(CompatibilityMode::Backward, previous, new).unwrap_err(), vec!["#: property id became required"]);
+    assert!(check(CompatibilityMode::Forward, previous, new).is_ok());
+    assert!(check(CompatibilityMode::None, previous, new).is_ok());
+}
+
+#[test]
+fn test_changing_property_type() {
+    let previous = r#"{"type": "object", "properties": {"id": {"type": "integer"}}}"#;
+    let widened = r#"{"type": "object", "properties": {"id": {"type": "number"}}}"#;
+    let changed = r#"{"type": "object", "properties": {"id": {"type": "string"}}}"#;
+
+    assert!(check(CompatibilityMode::Backward, previous, widened).is_ok());
+    assert!(check(CompatibilityMode::Forward, previous, widened).is_err());
+    assert_eq!(
+        check(CompatibilityMode::Backward, previous, changed).unwrap_err(),
+        vec!["#/properties/id: type integer is no longer accepted"]);
+}
+
+#[test]
+fn test_enum_and_items() {
+    let previous = r#"{"type": "array", "items": {"enum": ["a", "b"]}}"#;
+    let new = r#"{"type": "array", "items": {"enum": ["a", "b", "c"]}}"#;
+
+    assert!(check(CompatibilityMode::Backward, previous, new).is_ok());
+    assert_eq!(
+        check(CompatibilityMode::Forward, previous, new).unwrap_err(),
+        vec!["#/items: enum value \"c\" is no longer accepted"]);
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use kopperdb::kopper::KopperError;
+use serde::{Serialize, Deserialize};
+use rocket::http::Status;
+use rocket::serde::json::serde_json;
+use thiserror::Error;
+
+use crate::topic::schema_compatibility::{check_compatibility, CompatibilityMode};
+use crate::topic::topic_service::{TopicService, TopicServiceError};
+use crate::storage::database::Database;
+
+#[derive(Debug, Error)]
+pub enum SchemaServiceError {
+    #[error(transparent)]
+    Topic(#[from] TopicServiceError),
+
+    #[error("Topic {0} has no schema")]
+    NoSchema(String),
+
+    #[error("Topic {0} has no schema version {1}")]
+    NoSchemaVersion(String, u32),
+
+    #[error("Schema is not compatible with version {version} in {mode:?} mode")]
+    Incompatible { version: u32, mode: CompatibilityMode, errors: Vec<String> },
+
+    #[error("Invalid schema: {0}")]
+    InvalidSchema(String),
+
+    #[error("Message doesn't match schema version {version} of the topic")]
+    ValidationFailed { version: u32, errors: Vec<String> },
+
+    #[error(transparent)]
+    Serde(#[from] serde_json::error::Error),
+
+    #[error("Internal database error, check logs")]
+    DatabaseError
+}
+
+impl SchemaServiceError {
+    pub fn status(&self) -> Status {
+        match self {
+            SchemaServiceError::Topic(error) => error.status(),
+            SchemaServiceError::NoSchema(_) | SchemaServiceError::NoSchemaVersion(..) => Status::NotFound,
+            SchemaServiceError::Incompatible { .. } => Status::Conflict,
+            SchemaServiceError::InvalidSchema(_) => Status::BadRequest,
+            SchemaServiceError::ValidationFailed { .. } => Status::UnprocessableEntity,
+            SchemaServiceError::Serde(_) | SchemaServiceError::DatabaseError => Status::InternalServerError,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SchemaVersion {
+    pub version: u32,
+    pub schema: serde_json::Value,
+
+    /// Seconds since epoch
+    pub created_at: u64
+}
+
+///
+/// All schema versions registered for one topic, oldest first.
+/// Stored in the db under the `schemas/<topic name>` key
+///
+#[derive(Default, Serialize, Deserialize)]
+pub struct SchemaList(pub Vec<SchemaVersion>);
+
+impl SchemaList {
+    fn key(topic_name: &str) -> String {
+        format!("schemas/{topic_name}")
+    }
+
+    pub fn latest(&self) -> Option<&SchemaVersion> {
+        self.0.last()
+    }
+}
+
+///
+/// Keeps JSON Schemas of topics and validates published messages against them.
+/// Topics without a schema accept any json.
+///
+pub struct SchemaService {
+    topic_service: Arc<TopicService>,
+    db: Database,
+
+    // Registering a version is a read-modify-write of the schema list, same as topic updates
+    metadata_lock: Mutex<()>,
+
+    // Compiling a schema is expensive, so compiled schemas are kept around. Versions never change
+    validators: Mutex<HashMap<(String, u32), Arc<jsonschema::Validator>>>
+}
+
+impl SchemaService {
+    pub fn new(topic_service: Arc<TopicService>, db: Database) -> Self {
+        SchemaService {
+            topic_service,
+            db,
+            metadata_lock: Mutex::new(()),
+            validators: Mutex::new(HashMap::new())
+        }
+    }
+
+    ///
+    /// Adds a new version of the topic's schema. From now on published messages are validated against it.
+    /// The new version has to be compatible with the latest one, according to the topic's compatibility mode
+    ///
+    pub fn register_schema(&self, topic_name: &str, schema: serde_json::Value) -> Result<SchemaVersion, SchemaServiceError> {
+
+        let topic = self.topic_service.get_topic(topic_name)?;
+
+        jsonschema::validator_for(&schema).map_err(|err| SchemaServiceError::InvalidSchema(err.to_string()))?;
+
+        let _guard = self.metadata_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut schemas = self.get_schemas(topic_name)?;
+
+        if let Some(latest) = schemas.latest() {
+            let mode = topic.config.schema_compatibility;
+
+            check_compatibility(mode, &latest.schema, &schema)
+                .map_err(|errors| SchemaServiceError::Incompatible { version: latest.version, mode, errors })?;
+        }
+
+        let new_version = SchemaVersion {
+            version: schemas.latest().map_or(1, |latest| latest.version + 1),
+            schema,
+            created_at: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs()
+        };
+
+        schemas.0.push(new_version.clone());
+
+        if let Err(err) = self.db.write(&SchemaList::key(topic_name), &serde_json::to_string(&schemas)?) {
+            println!("Kopper error when writing {err}");
+            return Err(SchemaServiceError::DatabaseError);
+        }
+
+        Ok(new_version)
+    }
+
+    pub fn get_schemas(&self, topic_name: &str) -> Result<SchemaList, SchemaServiceError> {
+        match self.db.read(&SchemaList::key(topic_name)) {
+            Ok(serialized_list) => Ok(serde_json::from_str(&serialized_list)?),
+
+            // Topic never had a schema
+            Err(KopperError::KeyDoesNotExist(_)) => Ok(SchemaList::default()),
+
+            Err(err) => {
+                println!("Kopper error when reading {err}");
+                Err(SchemaServiceError::DatabaseError)
+            }
+        }
+    }
+
+    pub fn get_schema(&self, topic_name: &str, version: u32) -> Result<SchemaVersion, SchemaServiceError> {
+        self.get_schemas(topic_name)?
+            .0
+            .into_iter()
+            .find(|schema| schema.version == version)
+            .ok_or_else(|| SchemaServiceError::NoSchemaVersion(topic_name.to_owned(), version))
+    }
+
+    pub fn get_latest_schema(&self, topic_name: &str) -> Result<SchemaVersion, SchemaServiceError> {
+        self.get_schemas(topic_name)?
+            .latest()
+            .cloned()
+            .ok_or_else(|| SchemaServiceError::NoSchema(topic_name.to_owned()))
+    }
+
+    ///
+    /// Validates the message against the latest schema of the topic. Returns the version
+    /// the message was validated against, or None if the topic has no schema.
+    ///
+    pub fn validate(&self, topic_name: &str, message: &serde_json::Value) -> Result<Option<u32>, SchemaServiceError> {
+        let schema = match self.get_schemas(topic_name)?.latest() {
+            Some(schema) => schema.clone(),
+            None => return Ok(None),
+        };
+
+        let validator = self.validator(topic_name, &schema)?;
+
+        let errors: Vec<String> = validator
+            .iter_errors(message)
+            .map(|error| format!("{}: {}", error.instance_path, error))
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(SchemaServiceError::ValidationFailed { version: schema.version, errors });
+        }
+
+        Ok(Some(schema.version))
+    }
+
+    fn validator(&self, topic_name: &str, schema: &SchemaVersion) -> Result<Arc<jsonschema::Validator>, SchemaServiceError> {
+        let mut validators = self.validators.lock().unwrap();
+        let key = (topic_name.to_owned(), schema.version);
+
+        if let Some(validator) = validators.get(&key) {
+            return Ok(validator.clone());
+        }
+
+        let validator = Arc::new(jsonschema::validator_for(&schema.schema)
+            .map_err(|err| SchemaServiceError::InvalidSchema(err.to_string()))?);
+
+        validators.insert(key, validator.clone());
+        Ok(validator)
+    }
+}
@@ -0,0 +1,143 @@
+
+use std::sync::Arc;
+
+use rand::{distributions::Alphanumeric, Rng};
+
+use crate::delivery::credential_service::DeliveryCredentials;
+use crate::delivery::signing_service::SigningSecrets;
+use crate::storage::database::Database;
+
+use super::consumer_service::ConsumerService;
+use super::log_service::LogService;
+use super::topic_config::TopicConfig;
+use super::topic_service::{TopicService, TopicServiceError, TopicEntry, SubscriptionEntry};
+
+const DB_PATH: &str = "testfiles/topic";
+
+fn random_str_with_size(size: usize) -> String {
+    Rng::sample_iter(rand::thread_rng(), &Alphanumeric)
+    .take(size)
+    .map(char::from)
+    .collect()
+}
+
+fn new_db() -> Database {
+    Database::open(&format!("{}/{}", DB_PATH, random_str_with_size(20)), 4000).unwrap()
+}
+
+fn new_service() -> TopicService {
+    TopicService::new(new_db()).unwrap()
+}
+
+fn topic(name: &str) -> TopicEntry {
+    TopicEntry { name: name.to_owned(), owner: "mimi".to_owned(), subscribers: vec![], config: TopicConfig::default() }
+}
+
+#[test]
+fn parallel_create_topic() -> Result<(), TopicServiceError> {
+    let service = Arc::new(new_service());
+
+    let handles: Vec<_> = (0..16).map(|i| {
+        let service = service.clone();
+        std::thread::spawn(move || service.create_topic(topic(&format!("topic-{i}"))))
+    }).collect();
+
+    for handle in handles {
+        handle.join().unwrap()?;
+    }
+
+    assert_eq!(service.get_topics()?.0.len(), 16);
+    Ok(())
+}
+
+#[test]
+fn parallel_create_and_subscribe() -> Result<(), TopicServiceError> {
+    let service = Arc::new(new_service());
+    service.create_topic(topic("shared"))?;
+
+    let handles: Vec<_> = (0..16).map(|i| {
+        let service = service.clone();
+        std::thread::spawn(move || {
+            service.create_topic(topic(&format!("topic-{i}")))?;
+            service.subscribe_topic("shared", SubscriptionEntry::new(&format!("sub-{i}"), "http://localhost:1234")).map(|_| ())
+        })
+    }).collect();
+
+    for handle in handles {
+        handle.join().unwrap()?;
+    }
+
+    assert_eq!(service.get_topics()?.0.len(), 17);
+    assert_eq!(service.get_topic("shared")?.subscribers.len(), 16);
+    Ok(())
+}
+
+#[test]
+fn failed_update_is_not_saved() -> Result<(), TopicServiceError> {
+    let service = new_service();
+    service.create_topic(topic("orders"))?;
+
+    let err = service.create_topic(topic("orders")).unwrap_err();
+    assert_eq!(err.to_string(), TopicServiceError::AlreadyExists("orders".to_owned()).to_string());
+    assert_eq!(service.get_topics()?.0.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn unsubscribe_deletes_secrets() -> Result<(), TopicServiceError> {
+    let db = new_db();
+    let service = TopicService::new(db.clone())?;
+    service.create_topic(topic("orders"))?;
+    service.subscribe_topic("orders", SubscriptionEntry::new("billing", "http://localhost:1234"))?;
+
+    let keys = [SigningSecrets::key("orders", "billing"), DeliveryCredentials::key("orders", "billing")];
+    for key in &keys {
+        db.write(key, "{}").unwrap();
+    }
+
+    service.unsubscribe_topic("orders", "billing")?;
+    for key in &keys {
+        assert!(db.read(key).is_err(), "{key} outlived the subscription");
+    }
+    Ok(())
+}
+
+#[test]
+fn parallel_subscribe_with_same_name() -> Result<(), TopicServiceError> {
+    let db = new_db();
+    let service = Arc::new(TopicService::new(db.clone())?);
+    service.create_topic(topic("orders"))?;
+
+    // Only the subscription that got created may store its secret
+    let handles: Vec<_> = (0..16).map(|i| {
+        let (service, db) = (service.clone(), db.clone());
+        std::thread::spawn(move || {
+            service.subscribe_topic_with("orders", SubscriptionEntry::new("billing", "http://localhost:1234"), |subscription| {
+                db.write(&SigningSecrets::key("orders", &subscription.name), &i.to_string()).unwrap();
+                Ok::<_, TopicServiceError>(i)
+            })
+        })
+    }).collect();
+
+    let created: Vec<_> = handles.into_iter().filter_map(|handle| handle.join().unwrap().ok()).collect();
+    assert_eq!(created.len(), 1);
+    assert_eq!(db.read(&SigningSecrets::key("orders", "billing")).unwrap(), created[0].1.to_string());
+    Ok(())
+}
+
+#[test]
+fn fetch_skips_unreadable_entries() -> Result<(), Box<dyn std::error::Error>> {
+    let service = Arc::new(new_service());
+    service.create_topic(topic("orders"))?;
+    let topic = service.get_topic("orders")?;
+
+    let log_service = Arc::new(LogService::new(&format!("{}/{}", DB_PATH, random_str_with_size(20))));
+    log_service.append(&topic, 0, r#"{"value":1}"#)?;
+    log_service.append(&topic, 0, "not json")?;
+    log_service.append(&topic, 0, r#"{"value":2}"#)?;
+
+    let fetched = ConsumerService::new(service, log_service).fetch("orders", 0, 0, 10)?;
+    assert_eq!(fetched.messages.iter().map(|message| message.offset).collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(fetched.next_offset, 3);
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use serde::{Serialize, Deserialize};
+
+use crate::topic::schema_compatibility::CompatibilityMode;
+
+// Defaults used when the broker-wide defaults haven't been changed by an admin
+const DEFAULT_SEGMENT_SIZE: usize = 4096;
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+const DEFAULT_PARTITIONS: u32 = 1;
+
+// Upper bound for partition count, each partition is a separate folder and open file
+const MAX_PARTITIONS: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupPolicy {
+    /// Old messages are removed when they fall out of retention
+    Delete,
+
+    /// Only the latest message for each key is kept, messages without a key stay. Retention doesn't apply
+    Compact
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DurabilityMode {
+    /// Message is handed over to the OS, which flushes it to disk when it sees fit
+    Buffered,
+
+    /// Message is flushed to disk before publish returns
+    Fsync
+}
+
+///
+/// Configuration of a single topic. New topics get a copy of the broker-wide defaults,
+/// which can be changed at runtime just like the config of every topic.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TopicConfig {
+    /// How long messages are kept, in milliseconds. None means forever. Enforced by `RetentionService`,
+    /// only with the delete cleanup policy
+    pub retention_ms: Option<u64>,
+
+    /// How many bytes a partition can keep. None means no limit
+    pub retention_bytes: Option<u64>,
+
+    /// Size of the chunk of the log read from disk at once, in bytes
+    pub segment_size: usize,
+
+    /// Maximum size of a published message, in bytes
+    pub max_message_size: usize,
+
+    pub cleanup_policy: CleanupPolicy,
+
+    pub durability: DurabilityMode,
+
+    /// Number of partitions. Can be increased, but never decreased
+    pub partitions: u32,
+
+    /// Changes allowed when registering a new schema version
+    #[serde(default)]
+    pub schema_compatibility: CompatibilityMode
+}
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        TopicConfig {
+            retention_ms: None,
+            retention_bytes: None,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            cleanup_policy: CleanupPolicy::Delete,
+            durability: DurabilityMode::Buffered,
+            partitions: DEFAULT_PARTITIONS,
+            schema_compatibility: CompatibilityMode::default()
+        }
+    }
+}
+
+impl TopicConfig {
+    /// Checks if values make sense on their own. Returns the reason if they don't
+    pub fn validate(&self) -> Result<(), String> {
+        if self.partitions == 0 || self.partitions > MAX_PARTITIONS {
+            return Err(format!("partitions must be between 1 and {MAX_PARTITIONS}"));
+        }
+
+        if self.segment_size == 0 {
+            return Err("segment_size must be greater than 0".to_owned());
+        }
+
+        if self.max_message_size == 0 {
+            return Err("max_message_size must be greater than 0".to_owned());
+        }
+
+        if self.retention_ms == Some(0) || self.retention_bytes == Some(0) {
+            return Err("retention must be greater than 0, or null for no limit".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Checks if a topic configured with `self` can be switched to `new_config`
+    pub fn validate_change(&self, new_config: &TopicConfig) -> Result<(), String> {
+        new_config.validate()?;
+
+        if new_config.partitions < self.partitions {
+            return Err(format!("partitions can't be decreased from {} to {}", self.partitions, new_config.partitions));
+        }
+
+        Ok(())
+    }
+}
@@ -1,14 +1,50 @@
-use kopperdb::kopper::*;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use kopperdb::kopper::KopperError;
 use serde::{Serialize, Deserialize};
 use rocket::serde::json::serde_json;
+use rocket::http::Status;
 
 use thiserror::Error;
 
+use crate::delivery::batch_policy::BatchPolicy;
+use crate::delivery::circuit_breaker::{CircuitBreakerPolicy, SubscriptionHealth};
+use crate::delivery::credential_service::DeliveryCredentials;
+use crate::delivery::rate_limiter::RateLimit;
+use crate::delivery::verification::VerificationStatus;
+use crate::delivery::websub_service::WebSubLease;
+use crate::delivery::retry_policy::RetryPolicy;
+use crate::delivery::signing_service::SigningSecrets;
+use crate::partition::partition::Offset;
+use crate::topic::filter::Filter;
+use crate::topic::offset_service::CommittedOffsets;
+use crate::topic::topic_config::TopicConfig;
+use crate::storage::database::Database;
+
 #[derive(Debug, Error)]
 pub enum TopicServiceError {
     #[error("Topic {0} doesn't exist")]
     TopicNotFound(String),
 
+    #[error("Topic {0} already exists")]
+    AlreadyExists(String),
+
+    #[error("Invalid topic name '{0}': {1}")]
+    InvalidName(String, &'static str),
+
+    #[error("Invalid topic config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Subscription {1} to topic {0} doesn't exist")]
+    SubscriptionNotFound(String, String),
+
+    #[error("Subscription {1} to topic {0} already exists")]
+    SubscriptionExists(String, String),
+
+    #[error("Invalid subscription: {0}")]
+    InvalidSubscription(String),
+
     #[error(transparent)]
     Serde(#[from] serde_json::error::Error),
 
@@ -19,17 +55,273 @@ pub enum TopicServiceError {
     // and only bubble up info that the error happened - no details.
 }
 
+impl TopicServiceError {
+    /// HTTP status that should be returned to the client for this error
+    pub fn status(&self) -> Status {
+        match self {
+            TopicServiceError::TopicNotFound(_) | TopicServiceError::SubscriptionNotFound(..) => Status::NotFound,
+            TopicServiceError::AlreadyExists(_) | TopicServiceError::SubscriptionExists(..) => Status::Conflict,
+            TopicServiceError::InvalidName(..) 
+                | TopicServiceError::InvalidConfig(_) 
+                | TopicServiceError::InvalidSubscription(_) => Status::BadRequest,
+            TopicServiceError::Serde(_) | TopicServiceError::DatabaseError => Status::InternalServerError,
+        }
+    }
+}
+
+// Topic and subscription names end up as directory names and URL segments, so they're kept to a safe subset
+const MAX_NAME_LENGTH: usize = 64;
+const RESERVED_TOPIC_PREFIX: &str = "__";
+
+///
+/// Checks the topic name against the naming policy:
+/// - 1 to 64 characters long
+/// - only ASCII letters, digits, '.', '_' and '-'
+/// - not "." or ".." 
+/// - doesn't start with "__", which is reserved for internal topics
+///
+pub fn validate_topic_name(name: &str) -> Result<(), TopicServiceError> {
+    let invalid = |reason| Err(TopicServiceError::InvalidName(name.to_owned(), reason));
+
+    if let Err(reason) = check_name(name) {
+        return invalid(reason);
+    }
+
+    if name.starts_with(RESERVED_TOPIC_PREFIX) {
+        return invalid("names starting with '__' are reserved");
+    }
+
+    Ok(())
+}
+
+///
+/// Subscription names follow the same policy as topic names (without the reserved prefix),
+/// push endpoints have to be http(s) URLs and queues can't have one
+///
+pub fn validate_subscription(subscription: &SubscriptionEntry) -> Result<(), TopicServiceError> {
+    let name = &subscription.name;
+
+    check_name(name)
+        .map_err(|reason| TopicServiceError::InvalidSubscription(format!("name '{name}': {reason}")))?;
+
+    match subscription.mode {
+        SubscriptionMode::Push => {
+            let endpoint = reqwest::Url::parse(&subscription.endpoint)
+                .map_err(|err| TopicServiceError::InvalidSubscription(format!("endpoint '{}': {err}", subscription.endpoint)))?;
+
+            if endpoint.scheme() != "http" && endpoint.scheme() != "https" {
+                return Err(TopicServiceError::InvalidSubscription(format!("endpoint '{endpoint}' is not an http(s) URL")));
+            }
+        }
+
+        SubscriptionMode::Queue { visibility_timeout_ms } => {
+            if !subscription.endpoint.is_empty() {
+                return Err(TopicServiceError::InvalidSubscription("queues are pulled from, they can't have an endpoint".to_owned()));
+            }
+
+            if visibility_timeout_ms == 0 {
+                return Err(TopicServiceError::InvalidSubscription("visibility_timeout_ms must be greater than 0".to_owned()));
+            }
+        }
+    }
+
+    if let Some(filter) = &subscription.filter {
+        Filter::parse(filter).map_err(|reason| TopicServiceError::InvalidSubscription(format!("filter '{filter}': {reason}")))?;
+    }
+
+    subscription.retry_policy.validate()
+        .map_err(|reason| TopicServiceError::InvalidSubscription(format!("retry policy: {reason}")))?;
+
+    subscription.circuit_breaker.validate()
+        .map_err(|reason| TopicServiceError::InvalidSubscription(format!("circuit breaker: {reason}")))?;
+
+    subscription.rate_limit.validate()
+        .map_err(|reason| TopicServiceError::InvalidSubscription(format!("rate limit: {reason}")))?;
+
+    if subscription.concurrency == 0 || subscription.concurrency > MAX_CONCURRENCY {
+        return Err(TopicServiceError::InvalidSubscription(format!("concurrency must be between 1 and {MAX_CONCURRENCY}")));
+    }
+
+    if subscription.concurrency > 1 && (subscription.mode != SubscriptionMode::Push || subscription.batch.is_some()) {
+        return Err(TopicServiceError::InvalidSubscription("only push subscriptions without batches can have concurrency".to_owned()));
+    }
+
+    if subscription.rate_limit != RateLimit::default() && subscription.mode != SubscriptionMode::Push {
+        return Err(TopicServiceError::InvalidSubscription("only push subscriptions can have a rate limit".to_owned()));
+    }
+
+    if let Some(batch) = &subscription.batch {
+        if subscription.mode != SubscriptionMode::Push {
+            return Err(TopicServiceError::InvalidSubscription("only push subscriptions can have a batch policy".to_owned()));
+        }
+
+        batch.validate().map_err(|reason| TopicServiceError::InvalidSubscription(format!("batch policy: {reason}")))?;
+    }
+
+    Ok(())
+}
+
+/// Naming policy shared by everything that ends up in paths or URLs - topics, subscriptions, groups
+pub fn check_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("name can't be empty");
+    }
+
+    if name.len() > MAX_NAME_LENGTH {
+        return Err("name can't be longer than 64 characters");
+    }
+
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-') {
+        return Err("only letters, digits, '.', '_' and '-' are allowed");
+    }
+
+    if name == "." || name == ".." {
+        return Err("name can't be a relative path");
+    }
+
+    Ok(())
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TopicEntry {
     pub name: String,
     pub owner: String,
-    pub subscribers: Vec<SubscriptionEntry>
+    pub subscribers: Vec<SubscriptionEntry>,
+
+    // Topics created before configs existed get the built-in defaults
+    #[serde(default)]
+    pub config: TopicConfig
+}
+
+// One message at a time, in offset order
+pub const DEFAULT_CONCURRENCY: usize = 1;
+
+// Upper bound of messages a push subscription can have in flight at once
+pub const MAX_CONCURRENCY: usize = 64;
+
+fn default_concurrency() -> usize {
+    DEFAULT_CONCURRENCY
+}
+
+// How long a received queue message stays hidden from other consumers, unless they say otherwise
+const DEFAULT_VISIBILITY_TIMEOUT_MS: u64 = 30_000;
+
+fn default_visibility_timeout() -> u64 {
+    DEFAULT_VISIBILITY_TIMEOUT_MS
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SubscriptionMode {
+    /// Messages are POSTed to the endpoint, one by one
+    #[default]
+    Push,
+
+    /// Competing consumers receive messages and ack them one by one. Received messages
+    /// are hidden from others until acked, nacked or until the visibility timeout passes
+    Queue {
+        #[serde(default = "default_visibility_timeout")]
+        visibility_timeout_ms: u64
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SubscriptionEntry {
     pub name: String,
-    pub endpoint: String
+
+    /// Empty for queues
+    #[serde(default)]
+    pub endpoint: String,
+
+    #[serde(default)]
+    pub mode: SubscriptionMode,
+
+    /// Nothing is delivered (or received from a queue) while paused, offsets stay where they were
+    #[serde(default)]
+    pub paused: bool,
+
+    /// Push subscriptions get messages only once their endpoint is verified, see `VerificationStatus`
+    #[serde(default)]
+    pub verification: VerificationStatus,
+
+    /// Only messages matching the filter are delivered, see `Filter` for the syntax. Applies to push
+    /// and queue subscriptions - pull consumers and groups read partitions directly and get everything
+    #[serde(default)]
+    pub filter: Option<String>,
+
+    /// For queues only `max_attempts` matters - it's how many times a message can be nacked
+    #[serde(default)]
+    pub retry_policy: RetryPolicy,
+
+    /// Messages are pushed in batches instead of one by one, if set
+    #[serde(default)]
+    pub batch: Option<BatchPolicy>,
+
+    /// How many messages can be delivered at once. Messages with the same key are still delivered
+    /// one at a time, in offset order. Messages without a key aren't ordered with anything
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerPolicy,
+
+    /// Push subscriptions only, queues are read at the subscriber's own pace
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+
+    /// Kept up to date by the delivery engine
+    #[serde(default)]
+    pub health: SubscriptionHealth,
+
+    /// Set for subscriptions made through the WebSub hub
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websub: Option<WebSubLease>,
+
+    /// Topic that gets messages which couldn't be delivered. Without it they're retried forever
+    #[serde(default)]
+    pub dead_letter_topic: Option<String>,
+
+    /// How far the dead letter topic was re-driven, by its partition
+    #[serde(default)]
+    pub redriven: BTreeMap<u32, Offset>,
+
+    /// Offsets committed before they got their own key, see `CommittedOffsets`. Only read
+    /// until the subscription commits again
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub offsets: BTreeMap<u32, Offset>
+}
+
+impl SubscriptionEntry {
+    /// Subscription with default settings
+    pub fn new(name: &str, endpoint: &str) -> Self {
+        SubscriptionEntry {
+            name: name.to_owned(),
+            endpoint: endpoint.to_owned(),
+            mode: SubscriptionMode::Push,
+            paused: false,
+            verification: VerificationStatus::Pending,
+            filter: None,
+            retry_policy: RetryPolicy::default(),
+            batch: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            circuit_breaker: CircuitBreakerPolicy::default(),
+            rate_limit: RateLimit::default(),
+            health: SubscriptionHealth::default(),
+            websub: None,
+            dead_letter_topic: None,
+            redriven: BTreeMap::new(),
+            offsets: BTreeMap::new()
+        }
+    }
+
+    /// Parsed filter. Filters are validated on subscribe, one that doesn't parse lets everything through
+    pub fn filter(&self) -> Option<Filter> {
+        let expression = self.filter.as_deref()?;
+
+        Filter::parse(expression)
+            .map_err(|err| println!("Ignoring invalid filter of subscription {}: {err}", self.name))
+            .ok()
+    }
 }
 
 // This is a tuple. Elements of tuple are accessed with indices: tuple.0
@@ -37,12 +329,12 @@ pub struct SubscriptionEntry {
 pub struct TopicList(pub Vec<TopicEntry>);
 
 impl TopicList {
-    fn to_json(self) -> serde_json::Result<String> {
+    fn to_json(&self) -> serde_json::Result<String> {
         serde_json::to_string(&self.0)
     }
 
     fn from_json(str: &str) -> serde_json::Result<Self> {
-        serde_json::from_str(&str)
+        serde_json::from_str(str)
     }
 
     fn key() -> &'static str {
@@ -50,12 +342,24 @@ impl TopicList {
     } 
 }
 
+// Key of the broker-wide defaults for new topics. Missing until first changed by an admin
+const DEFAULT_CONFIG_KEY: &str = "topic_defaults";
+
 pub struct TopicService {
-    db: Kopper
+    db: Database,
+
+    // Every change to the topic list is a read-modify-write of a single db entry. Kopper makes
+    // the read and the write atomic on their own, but not the whole sequence, so the writers
+    // take turns here. Readers don't need it - they always see a complete list.
+    metadata_lock: Mutex<()>,
+
+    // Committed offsets are kept apart from the topic list, see `CommittedOffsets`. They're written
+    // under this lock, so a subscription can't be removed while its offsets are being committed
+    offsets_lock: Mutex<()>
 }
 
 impl TopicService {
-    pub fn new(db: Kopper) -> Result<Self, TopicServiceError> {
+    pub fn new(db: Database) -> Result<Self, TopicServiceError> {
 
         // Verify existence of the topic list entry
         if let Err(read_error) = db.read(TopicList::key()) {
@@ -82,26 +386,181 @@ impl TopicService {
             }
         };
         
-        Ok(TopicService{ db })
+        Ok(TopicService{ db, metadata_lock: Mutex::new(()), offsets_lock: Mutex::new(()) })
     }
 
-    pub fn topic_exists(&self, topic_name: &str) -> Result<bool, TopicServiceError> {
-        let topic_list = self.fetch_topic_list()?;
+    ///
+    /// Adds the subscription to the topic and returns it as saved. Push subscriptions start
+    /// pending, until their endpoint is verified
+    ///
+    #[allow(dead_code)] // Everything outside tests has secrets to prepare
+    pub fn subscribe_topic(&self, topic_name: &str, subscription_entry: SubscriptionEntry) -> Result<SubscriptionEntry, TopicServiceError> {
+        self.subscribe_topic_with(topic_name, subscription_entry, |_| Ok::<_, TopicServiceError>(()))
+            .map(|(subscription_entry, ())| subscription_entry)
+    }
+
+    ///
+    /// Same as `subscribe_topic`, but runs `prepare` right before the subscription is saved, e.g. to
+    /// store its secrets. It runs under the metadata lock once the subscription is known to be valid
+    /// and new, so nobody can create or remove one with the same name in the meantime. Nothing is
+    /// saved if `prepare` fails
+    ///
+    pub fn subscribe_topic_with<T, E, F>(&self, topic_name: &str, mut subscription_entry: SubscriptionEntry, prepare: F) -> Result<(SubscriptionEntry, T), E>
+    where
+        E: From<TopicServiceError>,
+        F: FnOnce(&SubscriptionEntry) -> Result<T, E>
+    {
+        validate_subscription(&subscription_entry)?;
+
+        subscription_entry.verification = match subscription_entry.mode {
+            SubscriptionMode::Push => VerificationStatus::Pending,
+            SubscriptionMode::Queue { .. } => VerificationStatus::Verified
+        };
+
+        let _guard = self.lock_metadata();
+        let mut topic_list = self.fetch_topic_list()?;
+
+        if let Some(dead_letter_topic) = &subscription_entry.dead_letter_topic {
+            if dead_letter_topic == topic_name {
+                return Err(TopicServiceError::InvalidSubscription("topic can't be its own dead letter topic".to_owned()).into());
+            }
+
+            if !topic_list.0.iter().any(|x| &x.name == dead_letter_topic) {
+                return Err(TopicServiceError::InvalidSubscription(format!("dead letter topic {dead_letter_topic} doesn't exist")).into());
+            }
+        }
+
+        let topic = self.find_topic_in_list(topic_name, &mut topic_list)?;
+        if topic.subscribers.iter().any(|x| x.name == subscription_entry.name) {
+            return Err(TopicServiceError::SubscriptionExists(topic_name.to_owned(), subscription_entry.name).into());
+        }
+        topic.subscribers.push(subscription_entry.clone());
+
+        // Removed subscription of the same name could have left something behind, if removing it failed
+        self.delete_subscription_data(topic_name, &subscription_entry.name);
 
-        Ok(topic_list.0
-            .iter()
-            .any(|x| x.name == topic_name)) // Match on names only
+        let prepared = prepare(&subscription_entry)?;
+        if let Err(err) = self.save_topic_list(topic_list) {
+            self.delete_subscription_data(topic_name, &subscription_entry.name);
+            return Err(err.into());
+        }
+
+        Ok((subscription_entry, prepared))
     }
 
-    pub fn subscribe_topic(&self, topic_name: &str, subscription_entry: SubscriptionEntry) -> Result<(), TopicServiceError> {
-        
-        let mut topic_list =  self.fetch_topic_list()?;
+    ///
+    /// Runs `update` on the subscription and saves the result. Same guarantees as other topic updates
+    ///
+    pub fn update_subscription<T, F>(&self, topic_name: &str, subscription_name: &str, update: F) -> Result<T, TopicServiceError>
+    where
+        F: FnOnce(&mut SubscriptionEntry) -> Result<T, TopicServiceError>
+    {
+        self.update_topic_list(|topic_list| {
+            let subscription = self.find_topic_in_list(topic_name, topic_list)?
+                .subscribers
+                .iter_mut()
+                .find(|x| x.name == subscription_name)
+                .ok_or_else(|| TopicServiceError::SubscriptionNotFound(topic_name.to_owned(), subscription_name.to_owned()))?;
+
+            update(subscription)
+        })
+    }
+
+    ///
+    /// Runs `read` on the subscription while holding the metadata lock, so it can't be removed in
+    /// the meantime. Nothing is saved, it's meant for data kept apart from the subscription, e.g. secrets
+    ///
+    pub fn with_subscription<T, E, F>(&self, topic_name: &str, subscription_name: &str, read: F) -> Result<T, E>
+    where
+        E: From<TopicServiceError>,
+        F: FnOnce(&SubscriptionEntry) -> Result<T, E>
+    {
+        let _guard = self.lock_metadata();
+        read(&self.get_subscription(topic_name, subscription_name)?)
+    }
+
+    ///
+    /// Removes the subscription together with its committed offsets, signing secrets and delivery
+    /// credentials. Its worker stops after the delivery it's working on
+    ///
+    pub fn unsubscribe_topic(&self, topic_name: &str, subscription_name: &str) -> Result<SubscriptionEntry, TopicServiceError> {
+        let _guard = self.lock_metadata();
+        let mut topic_list = self.fetch_topic_list()?;
         let topic = self.find_topic_in_list(topic_name, &mut topic_list)?;
 
-        topic.subscribers.push(subscription_entry);
+        let index = topic.subscribers.iter()
+            .position(|x| x.name == subscription_name)
+            .ok_or_else(|| TopicServiceError::SubscriptionNotFound(topic_name.to_owned(), subscription_name.to_owned()))?;
 
+        let removed = topic.subscribers.remove(index);
         self.save_topic_list(topic_list)?;
-        Ok(())
+        self.delete_subscription_data(topic_name, subscription_name);
+
+        Ok(removed)
+    }
+
+    ///
+    /// Points a push subscription to a new endpoint. Deliveries stop until the new endpoint
+    /// is verified, a delivery that's being retried switches to it afterwards
+    ///
+    pub fn change_endpoint(&self, topic_name: &str, subscription_name: &str, endpoint: &str) -> Result<SubscriptionEntry, TopicServiceError> {
+        self.update_subscription(topic_name, subscription_name, |subscription| {
+            if subscription.mode != SubscriptionMode::Push {
+                return Err(TopicServiceError::InvalidSubscription("queues are pulled from, they can't have an endpoint".to_owned()));
+            }
+
+            let updated = SubscriptionEntry {
+                endpoint: endpoint.to_owned(),
+                verification: VerificationStatus::Pending,
+                ..subscription.clone()
+            };
+            validate_subscription(&updated)?;
+
+            *subscription = updated;
+            Ok(subscription.clone())
+        })
+    }
+
+    /// Takes effect with the next request to the endpoint
+    pub fn set_rate_limit(&self, topic_name: &str, subscription_name: &str, rate_limit: &RateLimit) -> Result<SubscriptionEntry, TopicServiceError> {
+        self.update_subscription(topic_name, subscription_name, |subscription| {
+            let updated = SubscriptionEntry {
+                rate_limit: rate_limit.clone(),
+                ..subscription.clone()
+            };
+            validate_subscription(&updated)?;
+
+            *subscription = updated;
+            Ok(subscription.clone())
+        })
+    }
+
+    /// Verifies the push subscription's endpoint again, e.g. after the verification failed
+    pub fn request_verification(&self, topic_name: &str, subscription_name: &str) -> Result<SubscriptionEntry, TopicServiceError> {
+        self.update_subscription(topic_name, subscription_name, |subscription| {
+            if subscription.mode != SubscriptionMode::Push {
+                return Err(TopicServiceError::InvalidSubscription("queues are pulled from, they have no endpoint to verify".to_owned()));
+            }
+
+            subscription.verification = VerificationStatus::Pending;
+            Ok(subscription.clone())
+        })
+    }
+
+    /// Paused subscriptions keep their offsets, but get no messages until they're resumed
+    pub fn set_paused(&self, topic_name: &str, subscription_name: &str, paused: bool) -> Result<SubscriptionEntry, TopicServiceError> {
+        self.update_subscription(topic_name, subscription_name, |subscription| {
+            subscription.paused = paused;
+            Ok(subscription.clone())
+        })
+    }
+
+    pub fn get_subscription(&self, topic_name: &str, subscription_name: &str) -> Result<SubscriptionEntry, TopicServiceError> {
+        self.get_topic(topic_name)?
+            .subscribers
+            .into_iter()
+            .find(|x| x.name == subscription_name)
+            .ok_or_else(|| TopicServiceError::SubscriptionNotFound(topic_name.to_owned(), subscription_name.to_owned()))
     }
 
     pub fn get_topic(&self, topic_name: &str) -> Result<TopicEntry, TopicServiceError> {
@@ -116,15 +575,106 @@ impl TopicService {
 
     pub fn create_topic(&self, topic: TopicEntry) -> Result<(), TopicServiceError> {
 
-        // Fetch from DB
-        let mut topic_list = self.fetch_topic_list()?;
+        validate_topic_name(&topic.name)?;
+        topic.config.validate().map_err(TopicServiceError::InvalidConfig)?;
+
+        self.update_topic_list(|topic_list| {
+            if topic_list.0.iter().any(|x| x.name == topic.name) {
+                return Err(TopicServiceError::AlreadyExists(topic.name));
+            }
+
+            // Append our new topic to the list
+            topic_list.0.push(topic);
+            Ok(())
+        })
+    }
+
+    pub fn update_topic_config(&self, topic_name: &str, config: TopicConfig) -> Result<TopicConfig, TopicServiceError> {
+
+        self.update_topic_list(|topic_list| {
+            let topic = self.find_topic_in_list(topic_name, topic_list)?;
+            topic.config.validate_change(&config).map_err(TopicServiceError::InvalidConfig)?;
+
+            topic.config = config;
+            Ok(topic.config.clone())
+        })
+    }
+
+    ///
+    /// Config given to newly created topics
+    ///
+    pub fn get_default_config(&self) -> Result<TopicConfig, TopicServiceError> {
+        match self.db.read(DEFAULT_CONFIG_KEY) {
+            Ok(serialized_config) => Ok(serde_json::from_str(&serialized_config)?),
+
+            // Never changed, use the built-in one
+            Err(KopperError::KeyDoesNotExist(_)) => Ok(TopicConfig::default()),
+
+            Err(err) => {
+                println!("Kopper error when reading {err}");
+                Err(TopicServiceError::DatabaseError)
+            }
+        }
+    }
+
+    pub fn set_default_config(&self, config: TopicConfig) -> Result<TopicConfig, TopicServiceError> {
+        config.validate().map_err(TopicServiceError::InvalidConfig)?;
 
-        // Append our new topic to the list
-        topic_list.0.push(topic);
+        if let Err(err) = self.db.write(DEFAULT_CONFIG_KEY, &serde_json::to_string(&config)?) {
+            println!("Kopper error when writing {err}");
+            return Err(TopicServiceError::DatabaseError);
+        }
 
-        // Save to DB
+        Ok(config)
+    }
+
+    ///
+    /// Runs `update` on the freshly fetched topic list and saves the result, all while holding
+    /// the metadata lock - concurrent updates are applied one after another, none gets lost.
+    /// Nothing is saved if `update` returns an error.
+    ///
+    fn update_topic_list<T, F>(&self, update: F) -> Result<T, TopicServiceError> 
+    where
+        F: FnOnce(&mut TopicList) -> Result<T, TopicServiceError>
+    {
+        let _guard = self.lock_metadata();
+
+        let mut topic_list = self.fetch_topic_list()?;
+        let result = update(&mut topic_list)?;
         self.save_topic_list(topic_list)?;
-        Ok(())
+
+        Ok(result)
+    }
+
+    fn lock_metadata(&self) -> MutexGuard<'_, ()> {
+        // Lock is only poisoned if someone panicked mid-update, but then nothing was saved
+        self.metadata_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Held while committing subscription offsets
+    pub fn lock_offsets(&self) -> MutexGuard<'_, ()> {
+        self.offsets_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    ///
+    /// Drops secrets and offsets kept apart from the subscription, so they don't outlive it. Failing here
+    /// doesn't bring the subscription back, a new one with the same name clears them again
+    ///
+    fn delete_subscription_data(&self, topic_name: &str, subscription_name: &str) {
+        let keys = [
+            SigningSecrets::key(topic_name, subscription_name),
+            SigningSecrets::staged_key(topic_name, subscription_name),
+            DeliveryCredentials::key(topic_name, subscription_name),
+            CommittedOffsets::key(topic_name, subscription_name)
+        ];
+
+        let _guard = self.lock_offsets();
+
+        for key in keys {
+            if let Err(err) = self.db.delete(&key) {
+                println!("Kopper error when deleting {key}: {err}");
+            }
+        }
     }
 
     fn find_topic_in_list<'a>(&self, topic_name: &str, topic_list: &'a mut TopicList) -> Result<&'a mut TopicEntry, TopicServiceError> {
@@ -139,7 +689,7 @@ impl TopicService {
     }
 
     fn fetch_topic_list(&self) -> Result<TopicList, TopicServiceError> {
-        
+
         let serialized_list = match self.db.read(TopicList::key()) {
             Ok(topic_list) => topic_list,
 
@@ -156,7 +706,7 @@ impl TopicService {
 
     fn save_topic_list(&self, topic_list: TopicList) -> Result<(), TopicServiceError> {
         
-        let serialized_list = TopicList::to_json(topic_list)?;
+        let serialized_list = TopicList::to_json(&topic_list)?;
 
         if let Err(err) = self.db.write(TopicList::key(), &serialized_list) {
             // Handle error by logging it and return 'redacted' error